@@ -0,0 +1,543 @@
+use async_trait::async_trait;
+use frankenstein::{
+    AnswerCallbackQueryParams, AsyncApi, AsyncTelegramApi, ChatMember, DeleteMessageParams,
+    EditMessageResponse, EditMessageTextParams, Error, GetChatAdministratorsParams,
+    GetUpdatesParams, Message, MethodResponse, PinChatMessageParams, SendDocumentParams,
+    SendMessageParams, SetWebhookParams, UnpinChatMessageParams, Update, User,
+};
+use serde::Serialize;
+use std::fmt::Debug;
+
+/// `setWebhook` with the `secret_token` frankenstein 0.10's
+/// `SetWebhookParams` has no field for.
+#[derive(Debug, Serialize)]
+struct WebhookRegistration<'a> {
+    #[serde(flatten)]
+    params: &'a SetWebhookParams,
+    secret_token: &'a str,
+}
+
+/// The part of the Telegram API the bot talks to, so that the transport can be
+/// swapped and tests can record calls instead of making them. Requests are
+/// awaited, so a chat waiting on Telegram doesn't hold up a worker thread.
+#[async_trait]
+pub trait BotClient: Debug + Send + Sync {
+    async fn get_updates(
+        &self,
+        params: &GetUpdatesParams,
+    ) -> Result<MethodResponse<Vec<Update>>, Error>;
+
+    /// Registers the webhook along with the secret Telegram is to send back
+    /// in the `X-Telegram-Bot-Api-Secret-Token` header of every update.
+    async fn set_webhook(
+        &self,
+        params: &SetWebhookParams,
+        secret_token: &str,
+    ) -> Result<MethodResponse<bool>, Error>;
+
+    async fn send_message(
+        &self,
+        params: &SendMessageParams,
+    ) -> Result<MethodResponse<Message>, Error>;
+
+    async fn send_document(
+        &self,
+        params: &SendDocumentParams,
+    ) -> Result<MethodResponse<Message>, Error>;
+
+    async fn edit_message_text(
+        &self,
+        params: &EditMessageTextParams,
+    ) -> Result<EditMessageResponse, Error>;
+
+    async fn pin_chat_message(
+        &self,
+        params: &PinChatMessageParams,
+    ) -> Result<MethodResponse<bool>, Error>;
+
+    async fn unpin_chat_message(
+        &self,
+        params: &UnpinChatMessageParams,
+    ) -> Result<MethodResponse<bool>, Error>;
+
+    async fn delete_message(
+        &self,
+        params: &DeleteMessageParams,
+    ) -> Result<MethodResponse<bool>, Error>;
+
+    async fn get_chat_administrators(
+        &self,
+        params: &GetChatAdministratorsParams,
+    ) -> Result<MethodResponse<Vec<ChatMember>>, Error>;
+
+    async fn get_me(&self) -> Result<MethodResponse<User>, Error>;
+
+    async fn answer_callback_query(
+        &self,
+        params: &AnswerCallbackQueryParams,
+    ) -> Result<MethodResponse<bool>, Error>;
+}
+
+#[async_trait]
+impl BotClient for AsyncApi {
+    async fn get_updates(
+        &self,
+        params: &GetUpdatesParams,
+    ) -> Result<MethodResponse<Vec<Update>>, Error> {
+        AsyncTelegramApi::get_updates(self, params).await
+    }
+
+    async fn set_webhook(
+        &self,
+        params: &SetWebhookParams,
+        secret_token: &str,
+    ) -> Result<MethodResponse<bool>, Error> {
+        let params = WebhookRegistration {
+            params,
+            secret_token,
+        };
+        AsyncTelegramApi::request(self, "setWebhook", Some(params)).await
+    }
+
+    async fn send_message(
+        &self,
+        params: &SendMessageParams,
+    ) -> Result<MethodResponse<Message>, Error> {
+        AsyncTelegramApi::send_message(self, params).await
+    }
+
+    async fn send_document(
+        &self,
+        params: &SendDocumentParams,
+    ) -> Result<MethodResponse<Message>, Error> {
+        AsyncTelegramApi::send_document(self, params).await
+    }
+
+    async fn edit_message_text(
+        &self,
+        params: &EditMessageTextParams,
+    ) -> Result<EditMessageResponse, Error> {
+        AsyncTelegramApi::edit_message_text(self, params).await
+    }
+
+    async fn pin_chat_message(
+        &self,
+        params: &PinChatMessageParams,
+    ) -> Result<MethodResponse<bool>, Error> {
+        AsyncTelegramApi::pin_chat_message(self, params).await
+    }
+
+    async fn unpin_chat_message(
+        &self,
+        params: &UnpinChatMessageParams,
+    ) -> Result<MethodResponse<bool>, Error> {
+        AsyncTelegramApi::unpin_chat_message(self, params).await
+    }
+
+    async fn delete_message(
+        &self,
+        params: &DeleteMessageParams,
+    ) -> Result<MethodResponse<bool>, Error> {
+        AsyncTelegramApi::delete_message(self, params).await
+    }
+
+    async fn get_chat_administrators(
+        &self,
+        params: &GetChatAdministratorsParams,
+    ) -> Result<MethodResponse<Vec<ChatMember>>, Error> {
+        AsyncTelegramApi::get_chat_administrators(self, params).await
+    }
+
+    async fn get_me(&self) -> Result<MethodResponse<User>, Error> {
+        AsyncTelegramApi::get_me(self).await
+    }
+
+    async fn answer_callback_query(
+        &self,
+        params: &AnswerCallbackQueryParams,
+    ) -> Result<MethodResponse<bool>, Error> {
+        AsyncTelegramApi::answer_callback_query(self, params).await
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::BotClient;
+    use async_trait::async_trait;
+    use frankenstein::{
+        api_params, AnswerCallbackQueryParams, ChatBuilder, ChatMember, ChatMemberOwner, ChatType,
+        DeleteMessageParams, EditMessageResponse, EditMessageTextParams, Error, ErrorResponse,
+        GetChatAdministratorsParams, GetUpdatesParams, HttpError, Message, MessageBuilder,
+        MethodResponse, PinChatMessageParams, SendDocumentParams, SendMessageParams,
+        SetWebhookParams, UnpinChatMessageParams, Update, User, UserBuilder,
+    };
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone, PartialEq)]
+    pub enum Call {
+        Send(String),
+        /// A sent file, with its contents.
+        Document(String),
+        Edit(i32, String),
+        Pin(i32),
+        Unpin(i32),
+        Delete(i32),
+        /// An answered button press, with the text shown to the user.
+        Answer(Option<String>),
+    }
+
+    /// Records every call and hands out increasing message ids.
+    #[derive(Debug, Default)]
+    pub struct MockClient {
+        pub calls: Mutex<Vec<Call>>,
+        /// Users reported as chat owners.
+        pub admins: Vec<u64>,
+        /// Messages that fail to edit, as if someone had deleted them.
+        pub deleted: Vec<i32>,
+        /// Sending fails, as if Telegram were unreachable.
+        pub offline: bool,
+    }
+
+    impl MockClient {
+        pub fn calls(&self) -> Vec<Call> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        pub fn sent_messages(&self) -> Vec<String> {
+            self.calls()
+                .into_iter()
+                .filter_map(|call| match call {
+                    Call::Send(text) => Some(text),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn record(&self, call: Call) -> usize {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(call);
+            calls.len()
+        }
+    }
+
+    fn ok<T>(result: T) -> Result<MethodResponse<T>, Error> {
+        Ok(MethodResponse {
+            ok: true,
+            result,
+            description: None,
+        })
+    }
+
+    #[async_trait]
+    impl BotClient for MockClient {
+        /// There's never anything new.
+        async fn get_updates(
+            &self,
+            _params: &GetUpdatesParams,
+        ) -> Result<MethodResponse<Vec<Update>>, Error> {
+            ok(vec![])
+        }
+
+        async fn set_webhook(
+            &self,
+            _params: &SetWebhookParams,
+            _secret_token: &str,
+        ) -> Result<MethodResponse<bool>, Error> {
+            ok(true)
+        }
+        async fn send_message(
+            &self,
+            params: &SendMessageParams,
+        ) -> Result<MethodResponse<Message>, Error> {
+            if self.offline {
+                return Err(Error::HttpError(HttpError {
+                    code: 502,
+                    message: "Bad Gateway".to_string(),
+                }));
+            }
+
+            let message_id = self.record(Call::Send(params.text.clone())) as i32;
+            let chat = ChatBuilder::default()
+                .id(0)
+                .type_field(ChatType::Group)
+                .build()
+                .unwrap();
+            let message = MessageBuilder::default()
+                .message_id(message_id)
+                .date(0_u64)
+                .chat(chat)
+                .text(params.text.clone())
+                .build()
+                .unwrap();
+
+            ok(message)
+        }
+
+        async fn send_document(
+            &self,
+            params: &SendDocumentParams,
+        ) -> Result<MethodResponse<Message>, Error> {
+            if self.offline {
+                return Err(Error::HttpError(HttpError {
+                    code: 502,
+                    message: "Bad Gateway".to_string(),
+                }));
+            }
+
+            let contents = match &params.document {
+                api_params::File::InputFile(file) => std::fs::read_to_string(&file.path).unwrap(),
+                api_params::File::String(file_id) => file_id.clone(),
+            };
+            let message_id = self.record(Call::Document(contents)) as i32;
+            let chat = ChatBuilder::default()
+                .id(0)
+                .type_field(ChatType::Group)
+                .build()
+                .unwrap();
+            let message = MessageBuilder::default()
+                .message_id(message_id)
+                .date(0_u64)
+                .chat(chat)
+                .build()
+                .unwrap();
+
+            ok(message)
+        }
+
+        async fn edit_message_text(
+            &self,
+            params: &EditMessageTextParams,
+        ) -> Result<EditMessageResponse, Error> {
+            let message_id = params.message_id.unwrap_or_default();
+
+            if self.deleted.contains(&message_id) {
+                return Err(Error::ApiError(ErrorResponse {
+                    ok: false,
+                    description: "Bad Request: message to edit not found".to_string(),
+                    error_code: 400,
+                }));
+            }
+
+            self.record(Call::Edit(
+                params.message_id.unwrap_or_default(),
+                params.text.clone(),
+            ));
+
+            Ok(EditMessageResponse::Bool(MethodResponse {
+                ok: true,
+                result: true,
+                description: None,
+            }))
+        }
+
+        async fn pin_chat_message(
+            &self,
+            params: &PinChatMessageParams,
+        ) -> Result<MethodResponse<bool>, Error> {
+            self.record(Call::Pin(params.message_id));
+            ok(true)
+        }
+
+        async fn unpin_chat_message(
+            &self,
+            params: &UnpinChatMessageParams,
+        ) -> Result<MethodResponse<bool>, Error> {
+            self.record(Call::Unpin(params.message_id.unwrap_or_default()));
+            ok(true)
+        }
+
+        async fn delete_message(
+            &self,
+            params: &DeleteMessageParams,
+        ) -> Result<MethodResponse<bool>, Error> {
+            self.record(Call::Delete(params.message_id));
+            ok(true)
+        }
+
+        async fn get_chat_administrators(
+            &self,
+            _params: &GetChatAdministratorsParams,
+        ) -> Result<MethodResponse<Vec<ChatMember>>, Error> {
+            let admins = self
+                .admins
+                .iter()
+                .map(|&id| {
+                    let user = UserBuilder::default()
+                        .id(id)
+                        .is_bot(false)
+                        .first_name("admin")
+                        .build()
+                        .unwrap();
+
+                    ChatMember::Owner(ChatMemberOwner {
+                        user,
+                        custom_title: None,
+                        is_anonymous: false,
+                    })
+                })
+                .collect();
+
+            ok(admins)
+        }
+
+        async fn get_me(&self) -> Result<MethodResponse<User>, Error> {
+            ok(UserBuilder::default()
+                .id(1_u64)
+                .is_bot(true)
+                .first_name("bot")
+                .build()
+                .unwrap())
+        }
+
+        async fn answer_callback_query(
+            &self,
+            params: &AnswerCallbackQueryParams,
+        ) -> Result<MethodResponse<bool>, Error> {
+            self.record(Call::Answer(params.text.clone()));
+            ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frankenstein::SetWebhookParamsBuilder;
+    use futures::future::join_all;
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use std::convert::Infallible;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn webhook_is_registered_with_its_secret() {
+        let params = SetWebhookParamsBuilder::default()
+            .url("https://example.com/webhook")
+            .build()
+            .unwrap();
+        let registration = WebhookRegistration {
+            params: &params,
+            secret_token: "s3cret",
+        };
+
+        assert_eq!(
+            serde_json::to_value(&registration).unwrap(),
+            serde_json::json!({"url": "https://example.com/webhook", "secret_token": "s3cret"})
+        );
+    }
+
+    /// A Telegram stand-in that takes `delay` to answer every request, on a
+    /// thread of its own so that it keeps answering whatever the runtime
+    /// under test is doing.
+    fn slow_telegram(delay: Duration) -> String {
+        let (url_tx, url_rx) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            tokio::runtime::Runtime::new()
+                .unwrap()
+                .block_on(async move {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    url_tx
+                        .send(format!("http://{}/bot", listener.local_addr().unwrap()))
+                        .unwrap();
+
+                    loop {
+                        let (socket, _) = listener.accept().await.unwrap();
+                        let service = service_fn(move |_| async move {
+                            tokio::time::sleep(delay).await;
+                            let body =
+                                r#"{"ok":true,"result":{"id":1,"is_bot":true,"first_name":"bot"}}"#;
+                            Ok::<_, Infallible>(hyper::Response::new(hyper::Body::from(body)))
+                        });
+                        tokio::spawn(Http::new().serve_connection(socket, service));
+                    }
+                });
+        });
+
+        url_rx.recv().unwrap()
+    }
+
+    /// Ticks every 10 ms and adds up how late each tick came, which is how
+    /// long the runtime was kept from running it.
+    fn spawn_ticker() -> (Arc<AtomicU64>, tokio::task::JoinHandle<()>) {
+        let blocked_ms = Arc::new(AtomicU64::new(0));
+        let ticker = {
+            let blocked_ms = Arc::clone(&blocked_ms);
+            tokio::spawn(async move {
+                let tick = Duration::from_millis(10);
+                loop {
+                    let started = Instant::now();
+                    tokio::time::sleep(tick).await;
+                    let late = started.elapsed().saturating_sub(tick);
+                    blocked_ms.fetch_add(late.as_millis() as u64, Ordering::Relaxed);
+                }
+            })
+        };
+
+        (blocked_ms, ticker)
+    }
+
+    // The flavour `block_in_place` used to panic on.
+    #[tokio::test(flavor = "current_thread")]
+    async fn requests_leave_the_thread_to_other_tasks() {
+        let api = AsyncApi::new_url(slow_telegram(Duration::from_millis(200)));
+        let (blocked_ms, ticker) = spawn_ticker();
+        tokio::task::yield_now().await;
+
+        let started = Instant::now();
+        let responses = join_all((0..10).map(|_| BotClient::get_me(&api))).await;
+        let elapsed = started.elapsed();
+        ticker.abort();
+
+        assert!(responses.iter().all(|response| response.is_ok()));
+        // Ten requests at once on one thread take about as long as one.
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+        assert!(blocked_ms.load(Ordering::Relaxed) < 100);
+    }
+
+    /// Compares how long the runtime's only thread is kept from other tasks
+    /// while 50 requests that take 100 ms each are in flight, made the way the
+    /// blocking client made them and through the async one. Run it with
+    /// `cargo test bench_ -- --ignored --nocapture`.
+    #[tokio::test(flavor = "current_thread")]
+    #[ignore]
+    async fn bench_blocked_time_under_load() {
+        const REQUESTS: usize = 50;
+        let url = slow_telegram(Duration::from_millis(100));
+
+        let (blocked_ms, ticker) = spawn_ticker();
+        tokio::task::yield_now().await;
+        let started = Instant::now();
+        let get_me = format!("{}/getMe", url);
+        join_all((0..REQUESTS).map(|_| async {
+            ureq::post(&get_me).send_string("{}").unwrap();
+        }))
+        .await;
+        // The ticker only gets to run once the last request is done.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        ticker.abort();
+        println!(
+            "blocking: {:?} for {} requests, thread blocked for {} ms",
+            started.elapsed(),
+            REQUESTS,
+            blocked_ms.load(Ordering::Relaxed)
+        );
+
+        let api = AsyncApi::new_url(url);
+        let (blocked_ms, ticker) = spawn_ticker();
+        tokio::task::yield_now().await;
+        let started = Instant::now();
+        join_all((0..REQUESTS).map(|_| BotClient::get_me(&api))).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        ticker.abort();
+        println!(
+            "async: {:?} for {} requests, thread blocked for {} ms",
+            started.elapsed(),
+            REQUESTS,
+            blocked_ms.load(Ordering::Relaxed)
+        );
+    }
+}
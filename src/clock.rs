@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use std::fmt::Debug;
+
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    use super::Clock;
+    use chrono::{DateTime, Duration, Utc};
+    use std::sync::Mutex;
+
+    /// A clock that only moves when told to.
+    #[derive(Debug)]
+    pub struct MockClock {
+        now: Mutex<DateTime<Utc>>,
+    }
+
+    impl MockClock {
+        pub fn new(now: DateTime<Utc>) -> Self {
+            Self {
+                now: Mutex::new(now),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = *now + duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().unwrap()
+        }
+    }
+}
@@ -0,0 +1,108 @@
+pub mod chat;
+
+use serde::Deserialize;
+use std::time::Duration;
+use std::{env, fs, io};
+
+pub const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Defaults applied to every new challenge. Chats that are already running
+/// keep the values they were created with.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub duration: usize,
+    pub repeats: usize,
+    pub cycle_length: usize,
+    pub cycle_increase: isize,
+    pub min_repeats: usize,
+    /// Minimum number of seconds between two edits of a daily message.
+    pub edit_debounce_secs: u64,
+    /// Sent when a challenge starts instead of the built-in greeting in the
+    /// chat's language. `{exercise}`, `{repeats}` and `{duration}` are
+    /// replaced with the challenge's values.
+    pub greeting: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            duration: 35,
+            repeats: 100,
+            cycle_length: 7,
+            cycle_increase: 25,
+            min_repeats: 1,
+            edit_debounce_secs: 2,
+            greeting: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn path() -> String {
+        env::var("CONFIG_PATH").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string())
+    }
+
+    /// Reads the config file, falling back to defaults when it doesn't exist.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("Failed to read {}: {}", path, err)),
+        };
+
+        let config: Self = toml::from_str(&contents)
+            .map_err(|err| format!("Failed to parse {}: {}", path, err))?;
+
+        if config.cycle_length == 0 {
+            return Err(format!("{}: cycle_length must be positive", path));
+        }
+
+        Ok(config)
+    }
+
+    pub fn changes(&self, new: &Config) -> Vec<String> {
+        let mut changes = vec![];
+
+        if self.duration != new.duration {
+            changes.push(format!("duration: {} -> {}", self.duration, new.duration));
+        }
+        if self.repeats != new.repeats {
+            changes.push(format!("repeats: {} -> {}", self.repeats, new.repeats));
+        }
+        if self.cycle_length != new.cycle_length {
+            changes.push(format!(
+                "cycle_length: {} -> {}",
+                self.cycle_length, new.cycle_length
+            ));
+        }
+        if self.cycle_increase != new.cycle_increase {
+            changes.push(format!(
+                "cycle_increase: {} -> {}",
+                self.cycle_increase, new.cycle_increase
+            ));
+        }
+        if self.min_repeats != new.min_repeats {
+            changes.push(format!(
+                "min_repeats: {} -> {}",
+                self.min_repeats, new.min_repeats
+            ));
+        }
+        if self.edit_debounce_secs != new.edit_debounce_secs {
+            changes.push(format!(
+                "edit_debounce_secs: {} -> {}",
+                self.edit_debounce_secs, new.edit_debounce_secs
+            ));
+        }
+
+        if self.greeting != new.greeting {
+            changes.push("greeting changed".to_string());
+        }
+
+        changes
+    }
+
+    pub fn edit_debounce(&self) -> Duration {
+        Duration::from_secs(self.edit_debounce_secs)
+    }
+}
@@ -0,0 +1,214 @@
+//! A chat's own settings, kept in `<dir>/<chat_id>.toml` apart from the
+//! challenge, so the next `/start` in that chat picks them up again.
+//!
+//! Every field is optional: a file from an older version simply lacks the
+//! newer settings, and unknown keys from a newer one are ignored.
+
+use super::Config;
+use crate::messages::Locale;
+use crate::workout::{Layout, ScheduleEnd, WorkoutMode, WorkoutState};
+use chrono::Weekday;
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::{env, fs, io};
+
+pub const DEFAULT_CHAT_CONFIG_DIR: &str = "config";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChatConfig {
+    pub duration: Option<usize>,
+    /// The first day's target.
+    pub repeats: Option<usize>,
+    pub cycle_length: Option<usize>,
+    pub cycle_increase: Option<isize>,
+    pub min_repeats: Option<usize>,
+    pub exercise: Option<String>,
+    pub locale: Option<Locale>,
+    pub layout: Option<Layout>,
+    pub reset_hour: Option<u32>,
+    pub timezone: Option<Tz>,
+    /// Missing when reminders are off.
+    pub reminder_hour: Option<u32>,
+    /// Missing for daily targets.
+    pub goal: Option<usize>,
+    pub schedule: Option<Vec<usize>>,
+    pub schedule_end: Option<ScheduleEnd>,
+    pub silent: Option<bool>,
+    pub strict_input: Option<bool>,
+    pub allow_gifts: Option<bool>,
+    pub clean_chat: Option<bool>,
+    pub pause_extends: Option<bool>,
+    /// Missing when there is no rest day.
+    pub rest_weekday: Option<Weekday>,
+}
+
+impl ChatConfig {
+    pub fn dir() -> String {
+        env::var("CHAT_CONFIG_DIR").unwrap_or_else(|_| DEFAULT_CHAT_CONFIG_DIR.to_string())
+    }
+
+    fn path(dir: &str, chat_id: i64) -> PathBuf {
+        PathBuf::from(dir).join(format!("{}.toml", chat_id))
+    }
+
+    /// The challenge's current settings.
+    pub fn from_state(state: &WorkoutState) -> Self {
+        let goal = match state.mode {
+            WorkoutMode::TotalGoal { target } => Some(target),
+            WorkoutMode::Daily => None,
+        };
+
+        Self {
+            duration: Some(state.duration),
+            repeats: Some(state.day_target(0)),
+            cycle_length: Some(state.cycle_length),
+            cycle_increase: Some(state.cycle_increase),
+            min_repeats: Some(state.min_repeats),
+            exercise: Some(state.exercise.clone()),
+            locale: Some(state.locale),
+            layout: Some(state.layout),
+            reset_hour: Some(state.reset_hour),
+            timezone: Some(state.timezone),
+            reminder_hour: state.reminder_hour,
+            goal,
+            schedule: state.schedule.clone(),
+            schedule_end: Some(state.schedule_end),
+            silent: Some(state.silent),
+            strict_input: Some(state.strict_input),
+            allow_gifts: Some(state.allow_gifts),
+            clean_chat: Some(state.clean_chat),
+            pause_extends: Some(state.pause_extends),
+            rest_weekday: state.rest_weekday,
+        }
+    }
+
+    /// `defaults` with this chat's values for the new challenge.
+    pub fn over(&self, defaults: &Config) -> Config {
+        Config {
+            duration: self.duration.unwrap_or(defaults.duration),
+            repeats: self.repeats.unwrap_or(defaults.repeats),
+            cycle_length: self.cycle_length.unwrap_or(defaults.cycle_length),
+            cycle_increase: self.cycle_increase.unwrap_or(defaults.cycle_increase),
+            min_repeats: self.min_repeats.unwrap_or(defaults.min_repeats),
+            ..defaults.clone()
+        }
+    }
+
+    /// Sets what `over` can't, on a challenge that hasn't started.
+    pub fn apply(&self, state: &mut WorkoutState) {
+        if let Some(exercise) = &self.exercise {
+            state.exercise = exercise.clone();
+        }
+        if let Some(target) = self.goal {
+            state.mode = WorkoutMode::TotalGoal { target };
+        }
+        if let Some(schedule) = &self.schedule {
+            state.repeats = schedule.first().copied().unwrap_or(state.repeats);
+            state.day_targets = vec![state.repeats];
+            state.schedule = Some(schedule.clone());
+        }
+
+        state.set_locale(self.locale.unwrap_or(state.locale));
+        state.layout = self.layout.unwrap_or(state.layout);
+        state.reset_hour = self.reset_hour.unwrap_or(state.reset_hour);
+        state.timezone = self.timezone.unwrap_or(state.timezone);
+        state.reminder_hour = self.reminder_hour.or(state.reminder_hour);
+        state.schedule_end = self.schedule_end.unwrap_or(state.schedule_end);
+        state.silent = self.silent.unwrap_or(state.silent);
+        state.strict_input = self.strict_input.unwrap_or(state.strict_input);
+        state.allow_gifts = self.allow_gifts.unwrap_or(state.allow_gifts);
+        state.clean_chat = self.clean_chat.unwrap_or(state.clean_chat);
+        state.pause_extends = self.pause_extends.unwrap_or(state.pause_extends);
+        state.rest_weekday = self.rest_weekday.or(state.rest_weekday);
+    }
+
+    /// The chat's saved settings, or none when it has no file yet.
+    pub fn load(dir: &str, chat_id: i64) -> Result<Self, String> {
+        let path = Self::path(dir, chat_id);
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(format!("Failed to read {}: {}", path.display(), err)),
+        };
+
+        let config: Self = toml::from_str(&contents)
+            .map_err(|err| format!("Failed to parse {}: {}", path.display(), err))?;
+
+        if config.cycle_length == Some(0) {
+            return Err(format!("{}: cycle_length must be positive", path.display()));
+        }
+
+        Ok(config)
+    }
+
+    pub fn save(&self, dir: &str, chat_id: i64) -> Result<(), String> {
+        let path = Self::path(dir, chat_id);
+        let contents = toml::to_string(self)
+            .map_err(|err| format!("Failed to serialize {}: {}", path.display(), err))?;
+
+        fs::create_dir_all(dir)
+            .and_then(|()| fs::write(&path, contents))
+            .map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> String {
+        let dir = env::temp_dir().join(format!("chat-config-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        dir.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn settings_survive_into_the_next_challenge() {
+        let dir = temp_dir("round-trip");
+        let mut state = WorkoutState::new(-100, &Config::default());
+        state.duration = 10;
+        state.set_start_repeats(40).unwrap();
+        state.locale = Locale::En;
+        state.timezone = Tz::Europe__Moscow;
+        state.reminder_hour = Some(18);
+        state.mode = WorkoutMode::TotalGoal { target: 5000 };
+
+        ChatConfig::from_state(&state).save(&dir, -100).unwrap();
+
+        let chat_config = ChatConfig::load(&dir, -100).unwrap();
+        let mut restored = WorkoutState::new(-100, &chat_config.over(&Config::default()));
+        chat_config.apply(&mut restored);
+
+        assert_eq!(restored.duration, 10);
+        assert_eq!(restored.day_target(0), 40);
+        assert_eq!(restored.locale, Locale::En);
+        assert_eq!(restored.timezone, Tz::Europe__Moscow);
+        assert_eq!(restored.reminder_hour, Some(18));
+        assert_eq!(restored.mode, WorkoutMode::TotalGoal { target: 5000 });
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_and_unknown_settings_are_fine() {
+        let dir = temp_dir("compat");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            ChatConfig::path(&dir, 1),
+            "duration = 20\nconfetti = true\n",
+        )
+        .unwrap();
+
+        let chat_config = ChatConfig::load(&dir, 1).unwrap();
+        assert_eq!(chat_config.duration, Some(20));
+        assert_eq!(chat_config.locale, None);
+
+        assert_eq!(ChatConfig::load(&dir, 2).unwrap(), ChatConfig::default());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
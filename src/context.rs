@@ -1,10 +1,69 @@
+use crate::client::BotClient;
+use crate::clock::{Clock, SystemClock};
+use crate::config::chat::ChatConfig;
+use crate::config::Config;
+use crate::error::WorkoutError;
+use crate::events::{self, Event};
+use crate::messages::{Locale, Text};
+use crate::metrics::{self, Counter};
+use crate::storage::Store;
+use crate::workout::{ChallengeKey, Layout, Participant, ScheduleEnd, WorkoutState};
+use chrono::{DateTime, Duration as ChronoDuration, Utc, Weekday};
+use chrono_tz::Tz;
 use frankenstein::{
-    Api, EditMessageResponse, EditMessageTextParams, EditMessageTextParamsBuilder, Error, Message,
-    PinChatMessageParams, PinChatMessageParamsBuilder, SendMessageParams, SendMessageParamsBuilder,
-    TelegramApi, UnpinChatMessageParams, UnpinChatMessageParamsBuilder,
+    api_params, AnswerCallbackQueryParams, AnswerCallbackQueryParamsBuilder, ChatMember,
+    DeleteMessageParams, DeleteMessageParamsBuilder, EditMessageResponse, EditMessageTextParams,
+    EditMessageTextParamsBuilder, Error, GetChatAdministratorsParams,
+    GetChatAdministratorsParamsBuilder, InlineKeyboardButton, InlineKeyboardButtonBuilder,
+    InlineKeyboardMarkup, InputFile, Message, PinChatMessageParams, PinChatMessageParamsBuilder,
+    ReplyMarkup, SendDocumentParams, SendDocumentParamsBuilder, SendMessageParams,
+    SendMessageParamsBuilder, UnpinChatMessageParams, UnpinChatMessageParamsBuilder,
 };
-use std::collections::HashMap;
+use log::{debug, error, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard};
 use tokio::sync::mpsc::Sender;
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+/// How long a destructive command waits for its confirmation.
+const CONFIRMATION_TTL: Duration = Duration::from_secs(60);
+
+/// A command that throws the challenge's progress away, so it's only run
+/// once confirmed with a button.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Destructive {
+    Stop,
+    Reset,
+}
+
+impl Destructive {
+    fn command(self) -> ContextCommand {
+        match self {
+            Destructive::Stop => ContextCommand::Stop,
+            Destructive::Reset => ContextCommand::Reset,
+        }
+    }
+
+    fn question(self) -> Text<'static> {
+        match self {
+            Destructive::Stop => Text::ConfirmStop,
+            Destructive::Reset => Text::ConfirmReset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PendingConfirmation {
+    pub action: Destructive,
+    /// Who asked. They or any administrator may answer.
+    pub user_id: u64,
+    /// The message with the buttons.
+    pub message_id: i32,
+    pub expires_at: Instant,
+}
 
 #[derive(Debug, Clone)]
 pub enum ContextState {
@@ -12,216 +71,1163 @@ pub enum ContextState {
     Active,
 }
 
+#[derive(Debug)]
 pub enum ContextCommand {
+    /// Explains the rules when a challenge starts.
+    Greet {
+        template: Option<String>,
+    },
+    /// A slash command the bot doesn't have.
+    UnknownCommand,
     SendDailyMessage,
-    AddPushups { username: String, count: usize },
+    /// Reminds users who are still below today's target.
+    Remind,
+    AddPushups {
+        participant: Participant,
+        /// Negative counts correct earlier mistakes.
+        count: i64,
+        weight: f64,
+        /// The message this one replied to, if any.
+        reply_to: Option<i32>,
+        /// The log itself, so that editing it later corrects the count.
+        message_id: Option<i32>,
+    },
+    /// Evicts a participant along with all their reps.
+    RemoveUser {
+        name: String,
+    },
+    /// A rep log was edited to say `count` instead.
+    EditPushups {
+        message_id: i32,
+        count: i64,
+        weight: f64,
+    },
+    Join {
+        participant: Participant,
+    },
+    Leave {
+        participant: Participant,
+    },
+    AddNote {
+        text: String,
+    },
+    ShowStatus,
+    ShowWinners,
+    ShowLeaderboard,
+    /// `/me`: the caller's own numbers.
+    ShowMe {
+        user_id: i64,
+    },
+    ShowStats,
+    /// Sends all of `progress` as a CSV file.
+    Export,
+    /// Ranks by one day's reps; `None` is today.
+    ShowTop {
+        day: Option<usize>,
+    },
+    /// `/top` with something that isn't a day number.
+    NoSuchDay,
+    ShowHistory,
+    ShowConfig,
+    Undo,
+    /// Asks `user_id` to confirm `action` before running it.
+    Confirm {
+        action: Destructive,
+        user_id: u64,
+    },
+    /// A press of a confirmation button. The handler turns it into the
+    /// pending command, or answers why not.
+    ConfirmationAnswer {
+        query_id: String,
+        user_id: u64,
+        message_id: i32,
+        confirmed: bool,
+    },
+    /// Ends the challenge before `duration` days have passed.
+    Stop,
+    /// Starts the challenge over from day 0, keeping its settings.
+    Reset,
+    SetCleanChat {
+        enabled: bool,
+    },
+    CleanUpMessage {
+        user_id: u64,
+        message_id: i32,
+    },
+    GiveReps {
+        from: Participant,
+        /// Display name of the recipient as written in the command.
+        to: String,
+        count: usize,
+    },
+    SetGifts {
+        enabled: bool,
+    },
+    SetStrictInput {
+        enabled: bool,
+    },
+    SetLocale {
+        locale: Locale,
+    },
+    /// Lets the final and end-of-cycle messages notify.
+    SetNotifications {
+        enabled: bool,
+    },
+    /// `/pause` and `/resume`.
+    SetPaused {
+        paused: bool,
+    },
+    SetPauseExtends {
+        enabled: bool,
+    },
+    /// `None` turns rest days off.
+    SetRestWeekday {
+        weekday: Option<Weekday>,
+    },
+    SetUserTarget {
+        /// Display name of the user the target is for.
+        name: String,
+        /// `None` goes back to the common target.
+        target: Option<usize>,
+    },
+    SetLayout {
+        layout: Layout,
+    },
+    SetExercise {
+        name: String,
+    },
+    SetDuration {
+        days: usize,
+    },
+    SetCycleLength {
+        days: usize,
+    },
+    /// Moves the challenge to a day, counted from 1.
+    SetDay {
+        day: usize,
+    },
+    SetCycleIncrease {
+        increase: isize,
+    },
+    /// Sets the target of the first day.
+    SetStartRepeats {
+        repeats: usize,
+    },
+    /// `None` goes back to daily targets.
+    SetGoal {
+        target: Option<usize>,
+    },
+    SetSchedule {
+        /// `None` goes back to the cycle math.
+        schedule: Option<Vec<usize>>,
+        end: ScheduleEnd,
+    },
+    SetResetTime {
+        hour: u32,
+        timezone: Option<Tz>,
+    },
+    SetReminder {
+        /// `None` turns reminders off.
+        hour: Option<u32>,
+    },
+    /// Runs `command` only if `user_id` is a chat administrator.
+    AdminOnly {
+        user_id: u64,
+        command: Box<ContextCommand>,
+    },
+    /// Answers whether `message_id` is this challenge's daily message.
+    IsDailyMessage {
+        message_id: i32,
+        answer: oneshot::Sender<bool>,
+    },
+    /// One line about the challenge, for the owner's `/admin_list`, in the
+    /// language of the chat that asked.
+    Describe {
+        locale: Locale,
+        answer: oneshot::Sender<String>,
+    },
+    /// The operator reloaded the config with a new `edit_debounce_secs`.
+    SetEditDebounce(Duration),
+    /// The bot is no longer in the chat: forgets the challenge and stops its
+    /// handler without sending anything.
+    Removed,
+    /// Flushes and saves the chat, then stops its handler. `done` is
+    /// signalled once that happened.
+    Shutdown {
+        done: oneshot::Sender<()>,
+    },
+}
+
+impl ContextCommand {
+    /// Whether the command changes a setting the chat keeps for its next
+    /// challenge.
+    pub fn changes_settings(&self) -> bool {
+        matches!(
+            self,
+            ContextCommand::SetCleanChat { .. }
+                | ContextCommand::SetGifts { .. }
+                | ContextCommand::SetStrictInput { .. }
+                | ContextCommand::SetLocale { .. }
+                | ContextCommand::SetNotifications { .. }
+                | ContextCommand::SetPauseExtends { .. }
+                | ContextCommand::SetRestWeekday { .. }
+                | ContextCommand::SetLayout { .. }
+                | ContextCommand::SetExercise { .. }
+                | ContextCommand::SetDuration { .. }
+                | ContextCommand::SetCycleLength { .. }
+                | ContextCommand::SetCycleIncrease { .. }
+                | ContextCommand::SetStartRepeats { .. }
+                | ContextCommand::SetGoal { .. }
+                | ContextCommand::SetSchedule { .. }
+                | ContextCommand::SetResetTime { .. }
+                | ContextCommand::SetReminder { .. }
+        )
+    }
 }
 
 #[derive(Debug)]
 pub struct ContextData {
-    pub chat_id: i64,
-    pub daily_message_id: Option<i32>,
-    pub current_day: usize,
-    pub cycle_length: usize,
-    pub cycle_increase: usize,
-    pub duration: usize,
-    pub repeats: usize,
-    pub progress: Vec<HashMap<String, usize>>,
-    pub users: Vec<String>,
-    pub api: Api,
+    pub state: WorkoutState,
+    /// Set when the daily message needs an edit that hasn't gone through yet.
+    pub pending_edit: bool,
+    /// Telegram asked us to hold off editing until then.
+    pub edits_blocked_until: Option<Instant>,
+    pub last_edit_at: Option<Instant>,
+    pub edit_debounce: Duration,
+    /// Replaces the daily reset with days of this length, for testing.
+    pub day_length: Option<ChronoDuration>,
+    /// When the next reminder is due. Recomputed on restore, so a reminder
+    /// missed while the bot was down is skipped.
+    pub next_reminder: Option<DateTime<Utc>>,
+    /// The chat's administrators and when they were fetched.
+    pub admins: Option<(Instant, Vec<ChatMember>)>,
+    pub api: Arc<dyn BotClient>,
+    pub clock: Arc<dyn Clock>,
+    /// Where the chat's settings are kept for its next challenge. `None`
+    /// keeps them only in this one.
+    pub chat_config_dir: Option<String>,
+    /// Where to post the challenge's events, if anywhere.
+    pub event_webhook_url: Option<String>,
+    /// A `/stop` or `/reset` waiting for its button.
+    pub pending_confirmation: Option<PendingConfirmation>,
 }
 
+/// The running challenges, shared by the update loop and the handlers.
+///
+/// Kept behind a `std::sync::Mutex` rather than an async one: every critical
+/// section is a lookup, insert or removal, and no lock is held across an
+/// `.await` (the crate denies `clippy::await_holding_lock`). Senders are
+/// cloned out before sending, so chats never wait on each other.
 pub struct Contexts {
-    pub api: Api,
-    pub txs: HashMap<i64, Sender<ContextCommand>>,
+    pub api: Arc<dyn BotClient>,
+    pub config: Config,
+    pub store: Arc<dyn Store>,
+    pub txs: HashMap<ChallengeKey, Sender<ContextCommand>>,
+    pub seen_updates: RecentUpdates,
+    /// Without the `@`. `None` until fetched, and then every `/command@bot`
+    /// is taken as ours.
+    pub bot_username: Option<String>,
+    /// See `ContextData::chat_config_dir`.
+    pub chat_config_dir: Option<String>,
+    pub event_webhook_url: Option<String>,
+    /// Who may list every chat's challenge, from `OWNER_ID`.
+    pub owner_id: Option<u64>,
 }
 
 impl Contexts {
-    pub fn new(api: Api) -> Self {
+    pub fn new(api: Arc<dyn BotClient>, config: Config, store: Arc<dyn Store>) -> Self {
         Self {
             api,
+            config,
+            store,
             txs: HashMap::new(),
+            seen_updates: RecentUpdates::default(),
+            bot_username: None,
+            chat_config_dir: None,
+            event_webhook_url: None,
+            owner_id: None,
         }
     }
+
+    /// The settings a new challenge in the chat starts with. A broken file
+    /// is logged and the defaults are used.
+    pub fn chat_config(&self, chat_id: i64) -> ChatConfig {
+        let dir = match &self.chat_config_dir {
+            Some(dir) => dir,
+            None => return ChatConfig::default(),
+        };
+
+        ChatConfig::load(dir, chat_id).unwrap_or_else(|err| {
+            error!("{}", err);
+            ChatConfig::default()
+        })
+    }
+
+    /// Locks the registry even if a thread panicked while holding it. The
+    /// registry is only a map of senders and is never left half-updated, so
+    /// carrying on is safe, and it keeps one panic from taking every chat
+    /// down with it.
+    pub fn lock(contexts: &Mutex<Contexts>) -> MutexGuard<'_, Contexts> {
+        contexts.lock().unwrap_or_else(|poisoned| {
+            warn!("Recovering the contexts lock after a panic");
+            poisoned.into_inner()
+        })
+    }
+
+    /// The chat's running challenges, oldest first.
+    pub fn challenges(&self, chat_id: i64) -> Vec<(u32, Sender<ContextCommand>)> {
+        let mut challenges: Vec<_> = self
+            .txs
+            .iter()
+            .filter(|((chat, _), tx)| *chat == chat_id && !tx.is_closed())
+            .map(|((_, challenge_id), tx)| (*challenge_id, tx.clone()))
+            .collect();
+        challenges.sort_by_key(|(challenge_id, _)| *challenge_id);
+
+        challenges
+    }
+}
+
+/// How many update ids to remember. Redeliveries come soon after the
+/// original, so a few thousand is plenty.
+const RECENT_UPDATES: usize = 4096;
+
+/// The ids of the last handled updates, oldest first, so that an update
+/// delivered twice is only handled once.
+#[derive(Debug, Default)]
+pub struct RecentUpdates {
+    order: VecDeque<u32>,
+    ids: HashSet<u32>,
+}
+
+impl RecentUpdates {
+    /// Remembers the id. Returns false if it was already seen.
+    pub fn insert(&mut self, update_id: u32) -> bool {
+        if !self.ids.insert(update_id) {
+            return false;
+        }
+
+        self.order.push_back(update_id);
+
+        if self.order.len() > RECENT_UPDATES {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+
+        true
+    }
 }
 
 impl ContextData {
-    pub fn new(api: Api, chat_id: i64) -> Self {
+    pub fn new(api: Arc<dyn BotClient>, chat_id: i64, config: &Config) -> Self {
+        let mut context = Self::from_state(api, WorkoutState::new(chat_id, config));
+        context.edit_debounce = config.edit_debounce();
+
+        context
+    }
+
+    /// Wraps a restored state; edit timing starts from scratch.
+    pub fn from_state(api: Arc<dyn BotClient>, mut state: WorkoutState) -> Self {
+        state.ensure_day_slots();
+
         Self {
+            state,
+            pending_edit: false,
+            edits_blocked_until: None,
+            last_edit_at: None,
+            edit_debounce: Config::default().edit_debounce(),
+            day_length: None,
+            next_reminder: None,
+            admins: None,
             api,
-            chat_id,
-            daily_message_id: None,
-            cycle_increase: 25,
-            cycle_length: 7,
-            current_day: 0,
-            progress: vec![HashMap::new()],
-            duration: 35,
-            repeats: 100,
-            users: vec![],
+            clock: Arc::new(SystemClock),
+            chat_config_dir: None,
+            event_webhook_url: None,
+            pending_confirmation: None,
+        }
+    }
+
+    pub fn post_event(&self, event: Event) {
+        if let Some(url) = &self.event_webhook_url {
+            events::post(url, event);
+        }
+    }
+
+    pub fn save_chat_config(&self) {
+        if let Some(dir) = &self.chat_config_dir {
+            if let Err(err) = ChatConfig::from_state(&self.state).save(dir, self.state.chat_id) {
+                error!("{}", err);
+            }
         }
     }
 
     pub fn get_chat_id(&self) -> i64 {
-        self.chat_id
+        self.state.chat_id
     }
 
-    pub fn is_user_done(&self, username: String) -> bool {
-        self.progress[self.current_day].get(&username).unwrap_or(&0) >= &self.repeats
+    pub fn schedule_next_reset(&mut self) {
+        let now = self.clock.now();
+
+        self.state.next_reset = Some(match self.day_length {
+            Some(day_length) => now + day_length,
+            None => self.state.next_reset_after(now),
+        });
     }
 
-    pub fn is_all_users_done(&self) -> bool {
-        for username in &self.users {
-            if !self.is_user_done(username.clone()) {
-                return false;
+    /// Moves through the days whose resets all passed while the bot was down.
+    /// The last missed reset stays due, so the handler posts today's message
+    /// as usual. Returns how many days were skipped over.
+    pub fn catch_up_missed_resets(&mut self) -> usize {
+        let now = self.clock.now();
+        let mut due = match self.state.next_reset {
+            Some(due) => due,
+            None => return 0,
+        };
+        let mut skipped = 0;
+
+        loop {
+            let next = self.state.next_reset_after(due);
+
+            if next > now || self.state.is_workout_over() {
+                break;
             }
+
+            self.state.resting = self.state.started && self.state.is_rest_day(due);
+
+            if self.state.paused {
+                self.state.skip_paused_day();
+            } else if !self.state.resting {
+                self.state.start_next_day();
+            }
+            due = next;
+            skipped += 1;
         }
 
-        true
+        self.state.next_reset = Some(due);
+
+        skipped
     }
 
-    pub fn add_user_progress(&mut self, username: String, count: usize) {
-        let current_day = self.current_day;
+    /// How long until the next daily message is due; zero when it's overdue.
+    pub fn time_until_reset(&self) -> Option<Duration> {
+        self.state.next_reset.map(|at| {
+            at.signed_duration_since(self.clock.now())
+                .to_std()
+                .unwrap_or_default()
+        })
+    }
 
-        if !self.users.contains(&username) {
-            self.users.push(username.clone());
-        }
+    pub fn schedule_next_reminder(&mut self) {
+        self.next_reminder = self.state.next_reminder_after(self.clock.now());
+    }
 
-        *self.progress[current_day].entry(username).or_insert(0) += count;
+    pub fn time_until_reminder(&self) -> Option<Duration> {
+        self.next_reminder.map(|at| {
+            at.signed_duration_since(self.clock.now())
+                .to_std()
+                .unwrap_or_default()
+        })
     }
 
-    pub fn init_next_day(&mut self) -> bool {
-        self.current_day += 1;
-        self.progress.push(HashMap::new());
+    /// Sends `text` to the chat without a notification.
+    pub async fn send_message(&self, text: String) -> Result<Message, WorkoutError> {
+        self.send_with_notification(text, false).await
+    }
 
-        if self.current_day != 1 && (self.current_day - 1) % self.cycle_length == 0 {
-            self.repeats += self.cycle_increase;
+    /// Sends `text` where a failure is only worth a log line, as with
+    /// replies to commands.
+    pub async fn reply(&self, text: String) {
+        if let Err(err) = self.send_message(text).await {
+            error!("Failed to send message: {}", err);
+            metrics::increment(Counter::ApiErrors, 1);
+        }
+    }
 
-            return true;
+    /// Sends `text` in as many messages as Telegram's length limit needs,
+    /// splitting between lines.
+    pub async fn send_long_message(&self, text: String) {
+        for chunk in split_message(&text, MAX_MESSAGE_LENGTH) {
+            self.reply(chunk).await;
         }
+    }
 
-        false
+    /// Sends a message worth a notification, unless the chat asked for
+    /// silence.
+    pub async fn send_important_message(&self, text: String) {
+        for chunk in split_message(&text, MAX_MESSAGE_LENGTH) {
+            if let Err(err) = self.send_with_notification(chunk, !self.state.silent).await {
+                error!("Failed to send message: {}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+            }
+        }
     }
 
-    pub fn is_workout_over(&self) -> bool {
-        self.current_day >= self.duration
+    async fn send_with_notification(
+        &self,
+        text: String,
+        notify: bool,
+    ) -> Result<Message, WorkoutError> {
+        let send_message_params: SendMessageParams = SendMessageParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .text(text)
+            .disable_notification(!notify)
+            .build()
+            .unwrap();
+
+        Ok(self.api.send_message(&send_message_params).await?.result)
     }
 
-    pub fn generate_daily_message(&self) -> String {
-        let mut text = "".to_string();
+    /// Asks for `action` to be confirmed with a button. A newer request
+    /// replaces an older one.
+    pub async fn ask_confirmation(&mut self, action: Destructive, user_id: u64) {
+        let button = |text: Text, data: &str| -> InlineKeyboardButton {
+            InlineKeyboardButtonBuilder::default()
+                .text(self.state.text(text))
+                .callback_data(format!("{}:{}", data, self.state.challenge_id))
+                .build()
+                .unwrap()
+        };
+        let keyboard = InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![
+                button(Text::ConfirmButton, "confirm"),
+                button(Text::CancelButton, "cancel"),
+            ]],
+        };
+        let send_message_params: SendMessageParams = SendMessageParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .text(self.state.text(action.question()))
+            .disable_notification(true)
+            .reply_markup(ReplyMarkup::InlineKeyboardMarkup(keyboard))
+            .build()
+            .unwrap();
 
-        for username in &self.users {
-            text += &format!(
-                "{}: {}\n",
-                username,
-                self.progress[self.current_day].get(username).unwrap_or(&0),
-            );
+        match self.api.send_message(&send_message_params).await {
+            Ok(response) => {
+                self.pending_confirmation = Some(PendingConfirmation {
+                    action,
+                    user_id,
+                    message_id: response.result.message_id,
+                    expires_at: Instant::now() + CONFIRMATION_TTL,
+                });
+            }
+            Err(err) => {
+                error!("Failed to ask for confirmation: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+            }
         }
+    }
 
-        text += &format!(
-            "День {} из {}. {} повторений\n",
-            self.current_day, self.duration, self.repeats
-        );
+    /// The confirmed command, if `user_id` pressed the confirm button on the
+    /// pending request in time and may answer it. Otherwise the press is
+    /// answered with the reason.
+    pub async fn answer_confirmation(
+        &mut self,
+        query_id: &str,
+        user_id: u64,
+        message_id: i32,
+        confirmed: bool,
+    ) -> Option<ContextCommand> {
+        let pending = match self.pending_confirmation {
+            Some(pending) if pending.message_id == message_id => pending,
+            _ => {
+                self.answer_callback(query_id, Some(Text::ConfirmationGone))
+                    .await;
+                return None;
+            }
+        };
 
-        text
-    }
+        if pending.expires_at <= Instant::now() {
+            self.pending_confirmation = None;
+            self.answer_callback(query_id, Some(Text::ConfirmationExpired))
+                .await;
+            self.edit_text(message_id, Text::ConfirmationExpired).await;
+            return None;
+        }
 
-    pub fn generate_final_message(&self) -> String {
-        let mut users_progress = HashMap::new();
-        let mut total_progress = 0;
+        if user_id != pending.user_id && !self.is_chat_admin(user_id).await {
+            self.answer_callback(query_id, Some(Text::NotYourConfirmation))
+                .await;
+            return None;
+        }
 
-        for day_progress in &self.progress {
-            for (username, count) in day_progress.into_iter() {
-                *users_progress.entry(username).or_insert(0) += count;
-                total_progress += count;
-            }
+        self.pending_confirmation = None;
+        self.answer_callback(query_id, None).await;
+
+        if !confirmed {
+            self.edit_text(message_id, Text::Cancelled).await;
+            return None;
         }
 
-        let mut text = "".to_string();
-        text += &format!(
-            "Тренировка окончена! Мы прозанимались {} дней и отжались {} раз на всех.\n",
-            self.duration, total_progress
-        );
+        self.edit_text(message_id, Text::Confirmed).await;
 
-        for (username, count) in users_progress.into_iter() {
-            text += &format!("{}: {}\n", username, count);
+        Some(pending.action.command())
+    }
+
+    async fn answer_callback(&self, query_id: &str, text: Option<Text<'_>>) {
+        let mut builder = AnswerCallbackQueryParamsBuilder::default();
+        builder.callback_query_id(query_id);
+        if let Some(text) = text {
+            builder.text(self.state.text(text));
         }
+        let params: AnswerCallbackQueryParams = builder.build().unwrap();
 
-        text
+        if let Err(err) = self.api.answer_callback_query(&params).await {
+            error!("Failed to answer callback query: {:?}", err);
+            metrics::increment(Counter::ApiErrors, 1);
+        }
     }
 
-    pub fn generate_end_of_cycle_message(&self) -> String {
-        format!(
-            "Очередной цикл завершён! Увеличиваем повторения с {} до {}.",
-            self.repeats - self.cycle_increase,
-            self.repeats
-        )
+    /// Replaces the text of `message_id`, which also drops its buttons.
+    async fn edit_text(&self, message_id: i32, text: Text<'_>) {
+        let params: EditMessageTextParams = EditMessageTextParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .message_id(message_id)
+            .text(self.state.text(text))
+            .build()
+            .unwrap();
+
+        if let Err(err) = self.api.edit_message_text(&params).await {
+            error!("Failed to edit message: {:?}", err);
+            metrics::increment(Counter::ApiErrors, 1);
+        }
     }
 
-    pub fn send_message(&self, text: String) -> Option<Message> {
-        let send_message_params: SendMessageParams = SendMessageParamsBuilder::default()
-            .chat_id(self.chat_id)
-            .text(text)
+    /// Sends `contents` as a file named `file_name`. frankenstein 0.10's
+    /// `InputFile` only takes a path, so it goes through a temporary file, in
+    /// a directory of the challenge's own so that challenges sharing a chat
+    /// don't overwrite each other's.
+    pub async fn send_document(
+        &self,
+        file_name: &str,
+        contents: &str,
+    ) -> Result<Message, WorkoutError> {
+        let dir = std::env::temp_dir().join(format!(
+            "workout-{}-{}",
+            self.state.chat_id, self.state.challenge_id
+        ));
+        let file = TempFile {
+            path: dir.join(file_name),
+            dir,
+        };
+
+        std::fs::create_dir_all(&file.dir)?;
+        std::fs::write(&file.path, contents)?;
+
+        let send_document_params: SendDocumentParams = SendDocumentParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .document(api_params::File::InputFile(InputFile {
+                path: file.path.clone(),
+            }))
             .disable_notification(true)
             .build()
             .unwrap();
 
-        return match self.api.send_message(&send_message_params) {
-            Ok(response) => Some(response.result),
+        Ok(self.api.send_document(&send_document_params).await?.result)
+    }
+
+    /// Posts and pins the daily message, replacing the previous one. When that fails the old id is
+    /// cleared all the same, so edits don't go to the previous day's message.
+    pub async fn post_daily_message(&mut self) -> Result<(), WorkoutError> {
+        let text = self.state.generate_daily_message();
+        warn_if_daily_message_is_long(&text);
+        self.pending_edit = false;
+
+        match self.send_message(text).await {
+            Ok(message) => {
+                self.state.daily_message_id = Some(message.message_id);
+                self.pin_daily_message().await;
+
+                Ok(())
+            }
             Err(err) => {
-                println!("Failed to send message: {:?}", err);
-                None
+                self.state.daily_message_id = None;
+
+                Err(err)
             }
-        };
+        }
+    }
+
+    pub fn save(&self, store: &dyn Store) -> Result<(), WorkoutError> {
+        Ok(store.save_context(self)?)
     }
 
-    pub fn pin_daily_message(&self) {
-        if let Some(daily_message_id) = self.daily_message_id {
+    pub async fn pin_daily_message(&self) {
+        if let Some(daily_message_id) = self.state.daily_message_id {
             let pin_message_params: PinChatMessageParams = PinChatMessageParamsBuilder::default()
-                .chat_id(self.chat_id)
+                .chat_id(self.state.chat_id)
                 .message_id(daily_message_id)
                 .disable_notification(true)
                 .build()
                 .unwrap();
 
-            let result = self.api.pin_chat_message(&pin_message_params);
+            let result = self.api.pin_chat_message(&pin_message_params).await;
 
             if let Err(err) = result {
-                println!("Error pining daily message: {:?}", err);
+                error!("Error pining daily message: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
             }
         }
     }
 
-    pub fn unpin_daily_message(&self) {
-        if let Some(daily_message_id) = self.daily_message_id {
+    pub async fn unpin_daily_message(&self) {
+        if let Some(daily_message_id) = self.state.daily_message_id {
             let unpin_message_params: UnpinChatMessageParams =
                 UnpinChatMessageParamsBuilder::default()
-                    .chat_id(self.chat_id)
+                    .chat_id(self.state.chat_id)
                     .message_id(daily_message_id)
                     .build()
                     .unwrap();
 
-            let result = self.api.unpin_chat_message(&unpin_message_params);
+            let result = self.api.unpin_chat_message(&unpin_message_params).await;
 
             if let Err(err) = result {
-                println!("Error unpining daily message: {:?}", err);
+                error!("Error unpining daily message: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+            }
+        }
+    }
+
+    /// Asks Telegram at most once per `ADMINS_TTL`; a failed request isn't
+    /// cached.
+    async fn get_chat_administrators(&mut self) -> Vec<ChatMember> {
+        if let Some((fetched_at, admins)) = &self.admins {
+            if fetched_at.elapsed() < ADMINS_TTL {
+                return admins.clone();
+            }
+        }
+
+        let params: GetChatAdministratorsParams = GetChatAdministratorsParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .build()
+            .unwrap();
+
+        match self.api.get_chat_administrators(&params).await {
+            Ok(response) => {
+                self.admins = Some((Instant::now(), response.result.clone()));
+                response.result
+            }
+            Err(err) => {
+                error!("Failed to get chat administrators: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+                vec![]
+            }
+        }
+    }
+
+    pub async fn is_chat_admin(&mut self, user_id: u64) -> bool {
+        self.get_chat_administrators()
+            .await
+            .iter()
+            .any(|member| match member {
+                ChatMember::Owner(owner) => owner.user.id == user_id,
+                ChatMember::Administrator(admin) => admin.user.id == user_id,
+                _ => false,
+            })
+    }
+
+    /// Whether messages other than commands and replies reach the bot. With
+    /// privacy mode on, only admin rights let the bot see them in a group;
+    /// private chats (positive ids) always see everything.
+    pub async fn sees_all_messages(&mut self) -> bool {
+        if self.state.chat_id > 0 {
+            return true;
+        }
+
+        let bot = match self.api.get_me().await {
+            Ok(response) => response.result,
+            Err(err) => {
+                error!("Failed to get bot user: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+                return true;
+            }
+        };
+
+        bot.can_read_all_group_messages != Some(false) || self.is_chat_admin(bot.id).await
+    }
+
+    pub async fn can_delete_messages(&mut self) -> bool {
+        let bot_id = match self.api.get_me().await {
+            Ok(response) => response.result.id,
+            Err(err) => {
+                error!("Failed to get bot user: {:?}", err);
+                metrics::increment(Counter::ApiErrors, 1);
+                return false;
             }
+        };
+
+        self.get_chat_administrators()
+            .await
+            .iter()
+            .any(|member| match member {
+                ChatMember::Administrator(admin) => {
+                    admin.user.id == bot_id && admin.can_delete_messages
+                }
+                _ => false,
+            })
+    }
+
+    pub async fn delete_message(&self, message_id: i32) {
+        let delete_message_params: DeleteMessageParams = DeleteMessageParamsBuilder::default()
+            .chat_id(self.state.chat_id)
+            .message_id(message_id)
+            .build()
+            .unwrap();
+
+        if let Err(err) = self.api.delete_message(&delete_message_params).await {
+            error!("Error deleting message: {:?}", err);
+            metrics::increment(Counter::ApiErrors, 1);
         }
     }
 
-    pub fn update_daily_message(&self) -> Result<EditMessageResponse, frankenstein::Error> {
-        if self.daily_message_id.is_none() {
-            return Err(Error::DecodeError("No daily message ID".to_string()));
+    /// Marks the daily message as out of date and edits it unless an edit
+    /// went out less than `edit_debounce` ago or Telegram is rate limiting us.
+    /// Reps logged during that window only keep the edit pending, so a burst
+    /// of logs ends in a single edit once the window is over instead of a
+    /// storm of edits.
+    pub async fn refresh_daily_message(&mut self) {
+        self.pending_edit = true;
+
+        if self.edit_retry_deadline() > Some(Instant::now()) {
+            return;
         }
 
-        let text = self.generate_daily_message();
+        self.flush_daily_message().await;
+    }
+
+    /// Sends the pending edit now, ignoring the debounce. Only a rate limit
+    /// keeps it pending.
+    pub async fn flush_daily_message(&mut self) {
+        if matches!(self.edits_blocked_until, Some(until) if until > Instant::now()) {
+            return;
+        }
+
+        self.last_edit_at = Some(Instant::now());
+
+        match self.update_daily_message().await {
+            Ok(response) => {
+                debug!("Edit ok: {:?}", response);
+                self.pending_edit = false;
+            }
+            // Refreshes can come in before the first daily message is posted.
+            Err(WorkoutError::NoDailyMessage) => {
+                debug!("No daily message to update yet");
+                self.pending_edit = false;
+            }
+            // Someone deleted the daily message; post it again. If that fails
+            // too the id stays cleared, so the next edit doesn't try again and
+            // the next day posts a fresh one.
+            Err(WorkoutError::Api(err)) if is_message_not_found(&err) => {
+                warn!("Daily message is gone, posting it again");
+                metrics::increment(Counter::ApiErrors, 1);
+                self.state.daily_message_id = None;
+                self.pending_edit = false;
+
+                if let Err(err) = self.post_daily_message().await {
+                    error!("Failed to post daily message again: {}", err);
+                    metrics::increment(Counter::ApiErrors, 1);
+                }
+            }
+            Err(WorkoutError::Api(err)) => match retry_after(&err) {
+                Some(seconds) => {
+                    warn!("Rate limited, retrying edit in {}s", seconds);
+                    metrics::increment(Counter::ApiErrors, 1);
+                    self.edits_blocked_until = Some(Instant::now() + Duration::from_secs(seconds));
+                }
+                None => {
+                    error!("Failed to update daily message: {:?}", err);
+                    metrics::increment(Counter::ApiErrors, 1);
+                    self.pending_edit = false;
+                }
+            },
+            Err(err) => {
+                error!("Failed to update daily message: {}", err);
+                self.pending_edit = false;
+            }
+        }
+    }
+
+    /// When the pending edit may go out, if there is one.
+    pub fn edit_retry_deadline(&self) -> Option<Instant> {
+        if !self.pending_edit {
+            return None;
+        }
+
+        let debounced_until = self.last_edit_at.map(|at| at + self.edit_debounce);
+
+        self.edits_blocked_until.max(debounced_until)
+    }
+
+    pub async fn update_daily_message(&self) -> Result<EditMessageResponse, WorkoutError> {
+        let daily_message_id = self
+            .state
+            .daily_message_id
+            .ok_or(WorkoutError::NoDailyMessage)?;
+        let text = self.state.generate_daily_message();
+        warn_if_daily_message_is_long(&text);
 
         let update_message_params: EditMessageTextParams = EditMessageTextParamsBuilder::default()
-            .chat_id(self.chat_id)
-            .message_id(self.daily_message_id.unwrap())
+            .chat_id(self.state.chat_id)
+            .message_id(daily_message_id)
             .text(text)
             .build()
             .unwrap();
 
-        self.api.edit_message_text(&update_message_params)
+        Ok(self.api.edit_message_text(&update_message_params).await?)
+    }
+}
+
+/// A file `send_document` uploads from. Dropping it removes the file and
+/// its directory, however the upload ended.
+struct TempFile {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove {}: {}", self.path.display(), err);
+            }
+        }
+        let _ = std::fs::remove_dir(&self.dir);
+    }
+}
+
+/// How long the list of chat administrators is trusted before asking again.
+const ADMINS_TTL: Duration = Duration::from_secs(60);
+
+/// Telegram rejects messages longer than this many characters.
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+/// The daily message is edited in place, so it can't be split. Warns when a
+/// growing group is about to push it over the limit.
+fn warn_if_daily_message_is_long(text: &str) {
+    let length = text.chars().count();
+
+    if length > MAX_MESSAGE_LENGTH * 9 / 10 {
+        warn!(
+            "Daily message is {} of {} characters long",
+            length, MAX_MESSAGE_LENGTH
+        );
+    }
+}
+
+/// Cuts `text` into pieces of at most `limit` characters. Pieces end at line
+/// breaks; only a single line longer than `limit` is cut in the middle.
+pub fn split_message(text: &str, limit: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut chunk = String::new();
+    let mut chunk_length = 0;
+
+    for line in text.split_inclusive('\n') {
+        let mut line: Vec<char> = line.chars().collect();
+
+        if chunk_length + line.len() > limit && chunk_length > 0 {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_length = 0;
+        }
+
+        while line.len() > limit {
+            chunks.push(line.drain(..limit).collect());
+        }
+
+        chunk_length += line.len();
+        chunk.extend(line);
+    }
+
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+
+    chunks
+}
+
+/// Seconds Telegram wants us to wait, for a 429 "Too Many Requests" error.
+/// The description reads "Too Many Requests: retry after 35".
+fn retry_after(err: &Error) -> Option<u64> {
+    match err {
+        Error::ApiError(response) if response.error_code == 429 => response
+            .description
+            .rsplit(' ')
+            .next()
+            .and_then(|seconds| seconds.parse().ok()),
+        _ => None,
+    }
+}
+
+/// Whether Telegram says the message to edit no longer exists.
+fn is_message_not_found(err: &Error) -> bool {
+    matches!(err, Error::ApiError(response)
+        if response.error_code == 400 && response.description.contains("message to edit not found"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{Call, MockClient};
+
+    #[test]
+    fn oldest_updates_are_forgotten() {
+        let mut seen = RecentUpdates::default();
+
+        assert!(seen.insert(0));
+        assert!(!seen.insert(0));
+
+        for update_id in 1..=RECENT_UPDATES as u32 {
+            assert!(seen.insert(update_id));
+        }
+
+        assert!(seen.insert(0));
+        assert!(!seen.insert(RECENT_UPDATES as u32));
+    }
+
+    #[test]
+    fn retry_after_is_read_from_rate_limit_errors() {
+        let error = |error_code, description: &str| {
+            Error::ApiError(frankenstein::ErrorResponse {
+                ok: false,
+                description: description.to_string(),
+                error_code,
+            })
+        };
+
+        assert_eq!(
+            retry_after(&error(429, "Too Many Requests: retry after 35")),
+            Some(35)
+        );
+        assert_eq!(
+            retry_after(&error(400, "Bad Request: message is not modified")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_daily_message_is_posted_again() {
+        let client = Arc::new(MockClient {
+            deleted: vec![1],
+            ..MockClient::default()
+        });
+        let mut context = ContextData::new(client.clone(), 1, &Config::default());
+        context.post_daily_message().await.unwrap();
+        assert_eq!(context.state.daily_message_id, Some(1));
+
+        context.flush_daily_message().await;
+
+        assert_eq!(context.state.daily_message_id, Some(3));
+        assert!(!context.pending_edit);
+        assert_eq!(client.calls().last(), Some(&Call::Pin(3)));
+    }
+
+    #[tokio::test]
+    async fn documents_go_through_a_file_of_the_challenges_own() {
+        let client = Arc::new(MockClient::default());
+        let first = ContextData::new(client.clone(), -7, &Config::default());
+        let mut second = ContextData::new(client.clone(), -7, &Config::default());
+        second.state.challenge_id = 1;
+
+        first.send_document("progress.csv", "first").await.unwrap();
+        second
+            .send_document("progress.csv", "second")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            client.calls(),
+            vec![
+                Call::Document("first".to_string()),
+                Call::Document("second".to_string())
+            ]
+        );
+        for challenge_id in 0..2 {
+            let dir = std::env::temp_dir().join(format!("workout--7-{}", challenge_id));
+            assert!(!dir.exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_documents_leave_no_file_behind() {
+        let client = Arc::new(MockClient {
+            offline: true,
+            ..MockClient::default()
+        });
+        let mut context = ContextData::new(client.clone(), -8, &Config::default());
+        let dir = |context: &ContextData| {
+            std::env::temp_dir().join(format!("workout--8-{}", context.state.challenge_id))
+        };
+
+        assert!(context.send_document("progress.csv", "x").await.is_err());
+        assert!(!dir(&context).exists());
+
+        context.state.challenge_id = 1;
+        // No such subdirectory, so the write itself fails.
+        assert!(context
+            .send_document("missing/progress.csv", "x")
+            .await
+            .is_err());
+        assert!(!dir(&context).exists());
+        assert!(client.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_daily_message_leaves_no_stale_id() {
+        let client = Arc::new(MockClient {
+            offline: true,
+            ..MockClient::default()
+        });
+        let mut context = ContextData::new(client.clone(), 1, &Config::default());
+        context.state.daily_message_id = Some(1);
+        context.pending_edit = true;
+
+        assert!(context.post_daily_message().await.is_err());
+
+        assert_eq!(context.state.daily_message_id, None);
+        assert!(!context.pending_edit);
+        assert!(client.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn edits_within_debounce_are_coalesced() {
+        let client = Arc::new(MockClient::default());
+        let mut context = ContextData::new(client.clone(), 1, &Config::default());
+        context.state.daily_message_id = Some(1);
+
+        context.refresh_daily_message().await;
+        context.refresh_daily_message().await;
+        context.refresh_daily_message().await;
+
+        assert_eq!(client.calls().len(), 1);
+        assert!(context.pending_edit);
+        assert!(context.edit_retry_deadline().is_some());
+
+        context.flush_daily_message().await;
+
+        assert_eq!(client.calls().len(), 2);
+        assert!(!context.pending_edit);
+    }
+
+    #[tokio::test]
+    async fn refresh_without_daily_message_is_dropped() {
+        let client = Arc::new(MockClient::default());
+        let mut context = ContextData::new(client.clone(), 1, &Config::default());
+
+        assert!(matches!(
+            context.update_daily_message().await,
+            Err(WorkoutError::NoDailyMessage)
+        ));
+
+        context.refresh_daily_message().await;
+
+        assert!(client.calls().is_empty());
+        assert!(!context.pending_edit);
+    }
+
+    #[test]
+    fn long_messages_are_split_between_lines() {
+        assert_eq!(split_message("ab\ncd\nef\n", 6), vec!["ab\ncd\n", "ef\n"]);
+        assert_eq!(split_message("abcdefgh", 3), vec!["abc", "def", "gh"]);
+        assert_eq!(split_message("ab\nабвгде", 3), vec!["ab\n", "абв", "где"]);
+        assert!(split_message("", 3).is_empty());
     }
 }
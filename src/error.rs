@@ -0,0 +1,41 @@
+use crate::storage::StorageError;
+use std::fmt;
+
+/// What can go wrong while a chat context handles a command.
+#[derive(Debug)]
+pub enum WorkoutError {
+    /// There is no daily message to edit yet. Nothing a retry would fix.
+    NoDailyMessage,
+    Api(frankenstein::Error),
+    Storage(StorageError),
+    Io(std::io::Error),
+}
+
+impl fmt::Display for WorkoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NoDailyMessage => write!(f, "no daily message"),
+            Self::Api(err) => write!(f, "telegram: {:?}", err),
+            Self::Storage(err) => write!(f, "storage: {}", err),
+            Self::Io(err) => write!(f, "io: {}", err),
+        }
+    }
+}
+
+impl From<frankenstein::Error> for WorkoutError {
+    fn from(err: frankenstein::Error) -> Self {
+        Self::Api(err)
+    }
+}
+
+impl From<StorageError> for WorkoutError {
+    fn from(err: StorageError) -> Self {
+        Self::Storage(err)
+    }
+}
+
+impl From<std::io::Error> for WorkoutError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,169 @@
+//! Workout events posted as JSON to `EVENT_WEBHOOK_URL`, for feeding them
+//! into dashboards of one's own. Delivery is best effort: a failed post is
+//! retried a few times and then dropped, and the chat never waits for it.
+
+use crate::workout::WorkoutState;
+use log::{error, warn};
+use serde::Serialize;
+use std::env;
+use std::time::Duration;
+use tokio::{task, time};
+
+const ATTEMPTS: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// Someone logged reps; negative counts are corrections.
+    Reps,
+    DayStarted,
+    Finished,
+}
+
+/// One event. `user` and `count` are only there for `reps`, and so is the
+/// meaning of `total`: the user's reps that day. For the other events it's
+/// the group's reps so far. Days count from 1.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub chat_id: i64,
+    pub challenge_id: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<i64>,
+    pub day: usize,
+    pub total: usize,
+}
+
+impl Event {
+    fn new(event: EventKind, state: &WorkoutState) -> Self {
+        Self {
+            event,
+            chat_id: state.chat_id,
+            challenge_id: state.challenge_id,
+            user: None,
+            count: None,
+            day: WorkoutState::display_day(state.current_day),
+            total: state.group_total(),
+        }
+    }
+
+    pub fn reps(state: &WorkoutState, user_id: i64, count: i64) -> Self {
+        Self {
+            user: state
+                .users
+                .iter()
+                .find(|user| user.id == user_id)
+                .map(|user| user.display_name.clone()),
+            count: Some(count),
+            total: state.count_on(user_id, state.current_day),
+            ..Self::new(EventKind::Reps, state)
+        }
+    }
+
+    pub fn day_started(state: &WorkoutState) -> Self {
+        Self::new(EventKind::DayStarted, state)
+    }
+
+    pub fn finished(state: &WorkoutState) -> Self {
+        Self {
+            day: state.days_done(),
+            ..Self::new(EventKind::Finished, state)
+        }
+    }
+}
+
+pub fn url() -> Option<String> {
+    env::var("EVENT_WEBHOOK_URL")
+        .ok()
+        .filter(|url| !url.is_empty())
+}
+
+/// Posts `event` to `url` in the background.
+pub fn post(url: &str, event: Event) {
+    let body = match serde_json::to_string(&event) {
+        Ok(body) => body,
+        Err(err) => {
+            error!("Failed to serialize {:?}: {}", event, err);
+            return;
+        }
+    };
+    let url = url.to_string();
+
+    tokio::spawn(async move {
+        for attempt in 1..=ATTEMPTS {
+            let (url, body) = (url.clone(), body.clone());
+            let sent = task::spawn_blocking(move || {
+                ureq::post(&url)
+                    .timeout(TIMEOUT)
+                    .set("Content-Type", "application/json")
+                    .send_string(&body)
+                    .map(|_| ())
+                    .map_err(|err| err.to_string())
+            })
+            .await;
+
+            match sent {
+                Ok(Ok(_)) => return,
+                Ok(Err(err)) => warn!("Failed to post {:?} event: {}", event.event, err),
+                Err(err) => {
+                    error!("Event post panicked: {}", err);
+                    return;
+                }
+            }
+
+            if attempt < ATTEMPTS {
+                time::sleep(RETRY_DELAY * attempt).await;
+            }
+        }
+
+        error!(
+            "Dropping {:?} event for {} after {} attempts",
+            event.event, event.chat_id, ATTEMPTS
+        );
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::workout::Participant;
+
+    #[test]
+    fn events_serialize_to_the_documented_schema() {
+        let mut state = WorkoutState::new(-100, &Config::default());
+        let alice = Participant {
+            id: 7,
+            display_name: "alice".to_string(),
+        };
+        state.add_user_progress(alice.clone(), 30);
+        state.add_user_progress(alice, 20);
+
+        assert_eq!(
+            serde_json::to_value(Event::reps(&state, 7, 20)).unwrap(),
+            serde_json::json!({
+                "event": "reps",
+                "chat_id": -100,
+                "challenge_id": 0,
+                "user": "alice",
+                "count": 20,
+                "day": 1,
+                "total": 50,
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(Event::day_started(&state)).unwrap(),
+            serde_json::json!({
+                "event": "day_started",
+                "chat_id": -100,
+                "challenge_id": 0,
+                "day": 1,
+                "total": 50,
+            })
+        );
+    }
+}
@@ -1,216 +1,3334 @@
+// `Contexts` sits behind a std mutex that is only ever held for a few map
+// operations; holding it across an `.await` would stall the runtime.
+#![deny(clippy::await_holding_lock)]
+
+pub mod client;
+pub mod clock;
+pub mod config;
 pub mod context;
-pub mod lib;
+pub mod error;
+pub mod events;
+pub mod macros;
+pub mod messages;
+pub mod metrics;
+pub mod storage;
+pub mod webhook;
+pub mod workout;
 
-use crate::context::{ContextCommand, ContextData, Contexts};
-use chrono::prelude::*;
-use chrono::Duration;
-use frankenstein::{Api, GetUpdatesParams, GetUpdatesParamsBuilder, TelegramApi, Update};
+use crate::client::BotClient;
+use crate::config::chat::ChatConfig;
+use crate::config::Config;
+use crate::context::{
+    split_message, ContextCommand, ContextData, Contexts, Destructive, MAX_MESSAGE_LENGTH,
+};
+use crate::events::Event;
+use crate::messages::{Locale, Text};
+use crate::metrics::Counter;
+use crate::storage::Store;
+use crate::workout::{ChallengeKey, Layout, Participant, ScheduleEnd, WorkoutMode, WorkoutState};
+use chrono::{Duration, Weekday};
+use chrono_tz::Tz;
+use frankenstein::{
+    AsyncApi, CallbackQuery, ChatMember, ChatType, GetUpdatesParams, GetUpdatesParamsBuilder,
+    Message, SendMessageParams, SendMessageParamsBuilder, Update, User,
+};
+use futures::future;
+use log::{debug, error, info, trace, warn};
 use std::env;
 use std::sync::{Arc, Mutex};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc;
-use tokio::sync::mpsc::Receiver;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::oneshot;
 use tokio::time;
 
+const MAX_WEIGHT: f64 = 10.0;
+const MAX_EXERCISE_LENGTH: usize = 32;
+const MAX_DURATION: usize = 365;
+const SHUTDOWN_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(10);
+const MAX_UPDATE_BACKOFF: core::time::Duration = core::time::Duration::from_secs(60);
+const DESCRIBE_TIMEOUT: core::time::Duration = core::time::Duration::from_secs(5);
+
+/// Every command the bot answers to. Anything else starting with `/` gets a
+/// pointer to `/help`.
+const COMMANDS: &[&str] = &[
+    "/start",
+    "/new",
+    "/help",
+    "/add",
+    "/status",
+    "/winners",
+    "/history",
+    "/leaderboard",
+    "/me",
+    "/stats",
+    "/top",
+    "/export",
+    "/config",
+    "/join",
+    "/leave",
+    "/undo",
+    "/give",
+    "/note",
+    "/target",
+    "/stop",
+    "/reset",
+    "/duration",
+    "/setday",
+    "/goal",
+    "/start_reps",
+    "/cycle_length",
+    "/cycle_increase",
+    "/schedule",
+    "/reset_time",
+    "/reminder",
+    "/exercise",
+    "/layout",
+    "/notify",
+    "/lang",
+    "/remove",
+    "/clean_chat",
+    "/gifts",
+    "/strict",
+    "/pause",
+    "/resume",
+    "/pause_extends",
+    "/rest",
+];
+
 #[tokio::main]
 async fn main() {
+    env_logger::init();
+
     let token = env::var("TELEGRAM_BOT_TOKEN").expect("TELEGRAM_BOT_TOKEN not set");
-    let api = Api::new(&token);
-    let contexts = Arc::new(Mutex::new(Contexts::new(api.clone())));
+    let api: Arc<dyn BotClient> = Arc::new(AsyncApi::new(&token));
+    let config = Config::load(&Config::path()).expect("Failed to load config");
+    let store = storage::open_store().expect("Failed to open storage");
+    let contexts = Arc::new(Mutex::new(Contexts::new(
+        Arc::clone(&api),
+        config,
+        Arc::clone(&store),
+    )));
+    Contexts::lock(&contexts).bot_username = bot_username(api.as_ref()).await;
+    Contexts::lock(&contexts).chat_config_dir = Some(ChatConfig::dir());
+    Contexts::lock(&contexts).event_webhook_url = events::url();
+    Contexts::lock(&contexts).owner_id = owner_id();
+
+    for context_data in store.load_all(Arc::clone(&api)) {
+        info!("Restoring context {}", context_data.state.chat_id);
+        // Shutdown unpins the daily message, pin it back.
+        context_data.pin_daily_message().await;
+        spawn_context(&contexts, context_data);
+    }
+
+    let allowed_updates = allowed_updates();
+    let poll_interval = poll_interval();
 
     let cloned_contexts = Arc::clone(&contexts);
-    let updates_handler = tokio::spawn(async move {
-        get_all_updates(api, cloned_contexts).await;
+    // Polling unless `UPDATES_MODE=webhook`.
+    let mut updates_handler = tokio::spawn(async move {
+        match env::var("UPDATES_MODE").as_deref() {
+            Ok("webhook") => webhook::serve_webhook(api, cloned_contexts, allowed_updates).await,
+            _ => get_all_updates(api, cloned_contexts, poll_interval, allowed_updates).await,
+        }
     });
 
+    #[cfg(feature = "metrics")]
+    tokio::spawn(metrics::serve_metrics());
+
     let cloned_contexts = Arc::clone(&contexts);
-    let daily_message_handler = tokio::spawn(async move {
-        send_daily_messages(cloned_contexts).await;
+    let mut reload_handler = tokio::spawn(async move {
+        reload_config_on_hangup(cloned_contexts).await;
     });
 
-    tokio::try_join!(updates_handler, daily_message_handler).unwrap();
+    tokio::select! {
+        result = &mut updates_handler => result.unwrap(),
+        result = &mut reload_handler => result.unwrap(),
+        _ = wait_for_shutdown_signal() => info!("Shutting down"),
+    }
+
+    updates_handler.abort();
+    reload_handler.abort();
+    shut_down_contexts(&contexts).await;
 }
 
-async fn send_daily_messages(contexts: Arc<Mutex<Contexts>>) {
-    loop {
-        let txs = &contexts.lock().unwrap().txs.clone();
+async fn wait_for_shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
 
-        for (chat_id, context_tx) in txs {
-            if context_tx.is_closed() {
-                contexts.lock().unwrap().txs.remove_entry(chat_id);
-            } else {
-                context_tx
-                    .send(ContextCommand::SendDailyMessage)
-                    .await
-                    .unwrap_or_else(|err| {
-                        println!("Error sending SendDailyMessage command: {}", err);
-                    })
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
+}
+
+/// Asks every chat to flush and save its state, waiting at most
+/// `SHUTDOWN_TIMEOUT` so that a hung API call can't block the exit.
+async fn shut_down_contexts(contexts: &Arc<Mutex<Contexts>>) {
+    let txs: Vec<Sender<ContextCommand>> = Contexts::lock(contexts).txs.values().cloned().collect();
+    let mut acknowledgements = vec![];
+
+    for tx in txs {
+        let (done, acknowledgement) = oneshot::channel();
+
+        if tx.send(ContextCommand::Shutdown { done }).await.is_ok() {
+            acknowledgements.push(acknowledgement);
+        }
+    }
+
+    let count = acknowledgements.len();
+
+    if time::timeout(SHUTDOWN_TIMEOUT, future::join_all(acknowledgements))
+        .await
+        .is_err()
+    {
+        warn!("Not all of {} chats shut down in time", count);
+    }
+}
+
+async fn reload_config_on_hangup(contexts: Arc<Mutex<Contexts>>) {
+    let mut hangups = signal(SignalKind::hangup()).expect("Failed to listen for SIGHUP");
+
+    while hangups.recv().await.is_some() {
+        let path = Config::path();
+
+        let new_config = match Config::load(&path) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Keeping previous config, reload failed: {}", err);
+                continue;
             }
+        };
+
+        apply_config(&contexts, new_config, &path).await;
+    }
+}
+
+/// Makes `new_config` the one new chats start with, and hands the new edit
+/// debounce to chats that are already running.
+async fn apply_config(contexts: &Arc<Mutex<Contexts>>, new_config: Config, path: &str) {
+    let debounce = new_config.edit_debounce();
+    let txs: Vec<Sender<ContextCommand>> = {
+        let mut contexts = Contexts::lock(contexts);
+        let changes = contexts.config.changes(&new_config);
+
+        if changes.is_empty() {
+            info!("Config reloaded from {}, nothing changed", path);
+        } else {
+            info!("Config reloaded from {}: {}", path, changes.join(", "));
+        }
+
+        let debounce_changed = contexts.config.edit_debounce() != debounce;
+        contexts.config = new_config;
+
+        if debounce_changed {
+            contexts.txs.values().cloned().collect()
+        } else {
+            vec![]
         }
+    };
+
+    for tx in txs {
+        let _ = tx.send(ContextCommand::SetEditDebounce(debounce)).await;
+    }
+}
+
+const DEFAULT_POLL_INTERVAL_MS: u64 = 1000;
+/// How long Telegram holds a poll open waiting for updates. Must stay below
+/// the HTTP client's own 60 second timeout.
+const LONG_POLL_TIMEOUT_SECS: u32 = 30;
+const DEFAULT_ALLOWED_UPDATES: [&str; 4] = [
+    "message",
+    "edited_message",
+    "callback_query",
+    "my_chat_member",
+];
 
-        time::sleep(get_day_duration()).await;
+/// Every kind of update the Bot API can be asked for.
+const UPDATE_KINDS: [&str; 14] = [
+    "message",
+    "edited_message",
+    "channel_post",
+    "edited_channel_post",
+    "inline_query",
+    "chosen_inline_result",
+    "callback_query",
+    "shipping_query",
+    "pre_checkout_query",
+    "poll",
+    "poll_answer",
+    "my_chat_member",
+    "chat_member",
+    "chat_join_request",
+];
+
+/// `POLL_INTERVAL_MS` is the pause before retrying a failed poll, doubled for
+/// every failure in a row.
+fn poll_interval() -> std::time::Duration {
+    let millis = match env::var("POLL_INTERVAL_MS") {
+        Ok(value) => parse_poll_interval(&value).unwrap_or_else(|| {
+            warn!(
+                "Invalid POLL_INTERVAL_MS {:?}, polling every {} ms",
+                value, DEFAULT_POLL_INTERVAL_MS
+            );
+            DEFAULT_POLL_INTERVAL_MS
+        }),
+        Err(_) => DEFAULT_POLL_INTERVAL_MS,
+    };
+
+    std::time::Duration::from_millis(millis)
+}
+
+fn parse_poll_interval(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .filter(|millis| *millis > 0)
+}
+
+/// `ALLOWED_UPDATES` is a comma-separated list of the update kinds to ask
+/// Telegram for, both when polling and for the webhook.
+fn allowed_updates() -> Vec<String> {
+    match env::var("ALLOWED_UPDATES") {
+        Ok(value) => parse_allowed_updates(&value).unwrap_or_else(|| {
+            warn!(
+                "Invalid ALLOWED_UPDATES {:?}, using {}",
+                value,
+                DEFAULT_ALLOWED_UPDATES.join(",")
+            );
+            DEFAULT_ALLOWED_UPDATES.map(str::to_string).to_vec()
+        }),
+        Err(_) => DEFAULT_ALLOWED_UPDATES.map(str::to_string).to_vec(),
     }
 }
 
-async fn get_all_updates(api: Api, contexts: Arc<Mutex<Contexts>>) {
-    let update_delay = Duration::seconds(1).to_std().unwrap();
+fn parse_allowed_updates(value: &str) -> Option<Vec<String>> {
+    let kinds: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|kind| !kind.is_empty())
+        .map(str::to_string)
+        .collect();
+
+    let known = kinds
+        .iter()
+        .all(|kind| UPDATE_KINDS.contains(&kind.as_str()));
+
+    (known && !kinds.is_empty()).then_some(kinds)
+}
+
+/// Long-polls Telegram for updates: each request returns as soon as there's an
+/// update, or empty after `LONG_POLL_TIMEOUT_SECS`, so there's no pause between
+/// requests unless they fail. The offset of the next update is read from the
+/// store on start and written back for every update.
+async fn get_all_updates(
+    api: Arc<dyn BotClient>,
+    contexts: Arc<Mutex<Contexts>>,
+    update_delay: std::time::Duration,
+    allowed_updates: Vec<String>,
+) {
+    let mut consecutive_failures = 0;
+
+    let store = Arc::clone(&Contexts::lock(&contexts).store);
 
     let mut update_params: GetUpdatesParams = GetUpdatesParamsBuilder::default()
-        .allowed_updates(strings_vec!["message", "edited_message"])
+        .allowed_updates(allowed_updates)
+        .timeout(LONG_POLL_TIMEOUT_SECS)
         .build()
         .unwrap();
+    update_params.offset = store.load_update_offset();
 
     loop {
-        time::sleep(update_delay).await;
+        if consecutive_failures > 0 {
+            time::sleep(backoff_delay(update_delay, consecutive_failures)).await;
+        }
 
-        let result = api.get_updates(&update_params);
+        let result = api.get_updates(&update_params).await;
 
-        println!("result: {:?}", result);
+        trace!("result: {:?}", result);
 
         match result {
             Ok(response) => {
+                consecutive_failures = 0;
+
                 for update in response.result {
-                    update_params.offset = Some(update.update_id + 1);
-                    let (update, chat_id) = get_chat_id_from_update(update);
-
-                    let chat_id = match chat_id {
-                        Some(chat_id) => chat_id,
-                        None => continue,
-                    };
-
-                    let txs = contexts.lock().unwrap().txs.clone();
-
-                    if !txs.contains_key(&chat_id) {
-                        if let Some(message) = update.message.clone() {
-                            if let Some(text) = message.text {
-                                if text == "/start" {
-                                    println!("Initializing context {}", &chat_id);
-                                    init_context(Arc::clone(&contexts), chat_id, api.clone());
-                                }
-                            }
-                        }
+                    let offset = update.update_id + 1;
+                    update_params.offset = Some(offset);
+
+                    // Saved before handling: if we crash midway the update is
+                    // lost rather than counted twice after the restart.
+                    if let Err(err) = store.save_update_offset(offset) {
+                        error!("Failed to save the update offset: {}", err);
                     }
 
-                    let txs = contexts.lock().unwrap().txs.clone();
+                    process_update(update, &contexts, &api).await;
+                }
+            }
+            Err(error) => {
+                metrics::increment(Counter::ApiErrors, 1);
+                consecutive_failures += 1;
+                error!(
+                    "Failed to get updates ({} in a row): {:?}",
+                    consecutive_failures, error
+                );
+            }
+        }
+    }
+}
 
-                    if txs.contains_key(&chat_id) {
-                        let message = match update.message {
-                            Some(message) => message,
-                            None => continue,
-                        };
+/// Handles one update from Telegram end to end: starts challenges, routes the
+/// message to the right one and turns it into a command.
+async fn process_update(update: Update, contexts: &Arc<Mutex<Contexts>>, api: &Arc<dyn BotClient>) {
+    if !Contexts::lock(contexts)
+        .seen_updates
+        .insert(update.update_id)
+    {
+        debug!("Skipping update {} seen before", update.update_id);
+        return;
+    }
 
-                        if message.text.is_none() {
-                            continue;
-                        }
+    metrics::increment(Counter::UpdatesProcessed, 1);
 
-                        let text = message.text.unwrap();
-                        let count = text.parse::<usize>();
+    if let Some(member) = &update.my_chat_member {
+        if is_removal(&member.new_chat_member) {
+            forget_chat(contexts, member.chat.id).await;
+        }
+        return;
+    }
 
-                        let count = match count {
-                            Ok(count) => count,
-                            Err(err) => {
-                                println!("Error parsing count: {:?}", err);
-                                continue;
-                            }
-                        };
+    let (mut update, chat_id) = get_chat_id_from_update(update);
 
-                        let username = message.from.unwrap().username.unwrap();
+    let chat_id = match chat_id {
+        Some(chat_id) => chat_id,
+        None => return,
+    };
 
-                        let tx = txs[&chat_id].clone();
-                        tokio::spawn(async move {
-                            tx.send(ContextCommand::AddPushups { username, count })
-                                .await
-                                .unwrap_or_else(|err| {
-                                    println!("Error sending AddPushups command: {}", err);
-                                })
-                        });
-                    }
-                }
-            }
-            Err(error) => {
-                println!("Failed to get updates: {:?}", error);
+    if let Some(message) = update.message.as_mut() {
+        let bot_username = Contexts::lock(contexts).bot_username.clone();
+
+        if let Some(text) = &message.text {
+            match strip_bot_username(text, bot_username.as_deref()) {
+                Some(text) => message.text = Some(text),
+                None => return,
             }
         }
     }
-}
 
-fn init_context(contexts: Arc<Mutex<Contexts>>, chat_id: i64, api: Api) {
-    let (tx, rx) = mpsc::channel(2048);
-    let cloned_tx = tx.clone();
-    contexts.lock().unwrap().txs.insert(chat_id, cloned_tx);
+    let text = update
+        .message
+        .as_ref()
+        .and_then(|message| message.text.as_ref());
 
-    let context_data = ContextData::new(api, chat_id);
+    // Anyone else gets no answer at all, not even that the command exists.
+    if text.is_some_and(|text| text == "/admin_list") {
+        let from = update
+            .message
+            .as_ref()
+            .and_then(|message| message.from.as_ref())
+            .map(|from| from.id);
 
-    tokio::spawn(async move { handle_commands(context_data, rx).await });
+        if from.is_some() && from == Contexts::lock(contexts).owner_id {
+            let locale = Contexts::lock(contexts)
+                .chat_config(chat_id)
+                .locale
+                .unwrap_or_default();
+            list_challenges(contexts, api.as_ref(), chat_id, locale).await;
+        }
+        return;
+    }
 
-    tokio::spawn(async move { tx.send(ContextCommand::SendDailyMessage).await });
-}
+    if text.is_some_and(|text| text == "/start")
+        && init_context(contexts, chat_id, Arc::clone(api), None)
+    {
+        info!("Initialized context {}", &chat_id);
+    }
 
-pub async fn handle_commands(mut context_data: ContextData, mut rx: Receiver<ContextCommand>) {
-    while let Some(command) = rx.recv().await {
-        match command {
-            ContextCommand::SendDailyMessage => {
-                context_data.unpin_daily_message();
+    if let Some(exercise) = text.and_then(|text| {
+        (text == "/new" || text.starts_with("/new ")).then(|| text["/new".len()..].trim())
+    }) {
+        if exercise.is_empty() {
+            let locale = Contexts::lock(contexts)
+                .chat_config(chat_id)
+                .locale
+                .unwrap_or_default();
+            send_text(api.as_ref(), chat_id, &Text::NewUsage.format(locale)).await;
+            return;
+        }
+        let exercise = exercise.to_string();
 
-                if context_data.is_workout_over() {
-                    context_data.send_message(context_data.generate_final_message());
-                    context_data.unpin_daily_message();
-                    rx.close();
+        if init_context(contexts, chat_id, Arc::clone(api), Some(exercise)) {
+            info!("Added a challenge to {}", &chat_id);
+        }
+        return;
+    }
 
-                    return;
-                }
+    // Closed senders belong to challenges that have just finished
+    // or stopped; their handlers are gone.
+    let challenges = Contexts::lock(contexts).challenges(chat_id);
 
-                let cycle_ended = context_data.init_next_day();
-                if cycle_ended {
-                    context_data.send_message(context_data.generate_end_of_cycle_message());
-                }
+    if challenges.is_empty() {
+        if let Some(text) = update.message.and_then(|message| message.text) {
+            answer_without_challenge(contexts, api.as_ref(), chat_id, &text).await;
+        }
+        return;
+    }
+
+    if let Some(message) = update.edited_message {
+        edit_reps(message, challenges).await;
+        return;
+    }
+
+    if let Some(query) = update.callback_query {
+        answer_confirmation(query, challenges);
+        return;
+    }
 
-                let text = context_data.generate_daily_message();
+    let message = match update.message {
+        Some(message) => message,
+        None => return,
+    };
 
-                if let Some(message) = context_data.send_message(text) {
-                    context_data.daily_message_id = Some(message.message_id);
-                    context_data.pin_daily_message();
-                }
+    if message.text.is_none() {
+        return;
+    }
+
+    let reply_to = message
+        .reply_to_message
+        .as_ref()
+        .map(|reply| reply.message_id);
+    let text = message.text.unwrap();
+    let tx = route_message(challenges, reply_to).await;
+
+    if text == "/help" {
+        let template = Contexts::lock(contexts).config.greeting.clone();
+        send_command(tx, ContextCommand::Greet { template });
+        return;
+    }
+
+    if text == "/status" {
+        send_command(tx, ContextCommand::ShowStatus);
+        return;
+    }
+
+    if text == "/winners" {
+        send_command(tx, ContextCommand::ShowWinners);
+        return;
+    }
+
+    if text == "/config" {
+        send_command(tx, ContextCommand::ShowConfig);
+        return;
+    }
+
+    if text == "/join" || text == "/leave" {
+        if let Some(from) = &message.from {
+            let participant = Participant::from_user(from);
+            let command = if text == "/join" {
+                ContextCommand::Join { participant }
+            } else {
+                ContextCommand::Leave { participant }
+            };
+            send_command(tx, command);
+        }
+        return;
+    }
+
+    if text == "/undo" {
+        send_command(tx, ContextCommand::Undo);
+        return;
+    }
+
+    if text == "/stop" || text == "/reset" {
+        if let Some(from) = &message.from {
+            let action = if text == "/stop" {
+                Destructive::Stop
+            } else {
+                Destructive::Reset
+            };
+            let command = ContextCommand::Confirm {
+                action,
+                user_id: from.id,
+            };
+            send_admin_command(tx, Some(from), command);
+        }
+        return;
+    }
+
+    if text == "/pause" || text == "/resume" {
+        let paused = text == "/pause";
+        send_admin_command(
+            tx,
+            message.from.as_ref(),
+            ContextCommand::SetPaused { paused },
+        );
+        return;
+    }
+
+    if text == "/history" {
+        send_command(tx, ContextCommand::ShowHistory);
+        return;
+    }
+
+    if text == "/leaderboard" {
+        send_command(tx, ContextCommand::ShowLeaderboard);
+        return;
+    }
+
+    if text == "/stats" {
+        send_command(tx, ContextCommand::ShowStats);
+        return;
+    }
+
+    if text == "/me" {
+        if let Some(from) = &message.from {
+            let user_id = Participant::from_user(from).id;
+            send_command(tx, ContextCommand::ShowMe { user_id });
+        }
+        return;
+    }
+
+    if text == "/export" {
+        send_command(tx, ContextCommand::Export);
+        return;
+    }
+
+    if text == "/top" {
+        send_command(tx, ContextCommand::ShowTop { day: None });
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/top ") {
+        // Days are numbered from 1 in messages.
+        let day = argument
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|day| day.checked_sub(1));
+
+        let command = match day {
+            Some(day) => ContextCommand::ShowTop { day: Some(day) },
+            None => ContextCommand::NoSuchDay,
+        };
+        send_command(tx, command);
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/clean_chat ") {
+        if let Some(enabled) = parse_toggle(argument) {
+            let command = ContextCommand::SetCleanChat { enabled };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/cycle_length ") {
+        if let Ok(days) = argument.trim().parse::<usize>() {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetCycleLength { days },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/start_reps ") {
+        if let Ok(repeats) = argument.trim().parse::<usize>() {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetStartRepeats { repeats },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/goal ") {
+        let target = match argument.trim() {
+            "off" => Some(None),
+            argument => argument.parse::<usize>().ok().map(Some),
+        };
+
+        if let Some(target) = target {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetGoal { target },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/cycle_increase ") {
+        if let Ok(increase) = argument.trim().parse::<isize>() {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetCycleIncrease { increase },
+            );
+        }
+        return;
+    }
+
+    if let Some(arguments) = text.strip_prefix("/schedule ") {
+        if let Some((schedule, end)) = parse_schedule(arguments) {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetSchedule { schedule, end },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/setday ") {
+        if let Ok(day) = argument.trim().parse::<usize>() {
+            send_admin_command(tx, message.from.as_ref(), ContextCommand::SetDay { day });
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/duration ") {
+        if let Ok(days) = argument.trim().parse::<usize>() {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetDuration { days },
+            );
+        }
+        return;
+    }
+
+    if let Some(arguments) = text.strip_prefix("/reset_time ") {
+        if let Some((hour, timezone)) = parse_reset_time(arguments) {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetResetTime { hour, timezone },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/reminder ") {
+        if let Some(hour) = parse_reminder(argument) {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetReminder { hour },
+            );
+        }
+        return;
+    }
+
+    if let Some(name) = text.strip_prefix("/exercise ") {
+        let name = name.trim().to_string();
+        send_admin_command(
+            tx,
+            message.from.as_ref(),
+            ContextCommand::SetExercise { name },
+        );
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/layout ") {
+        if let Some(layout) = parse_layout(argument) {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetLayout { layout },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/gifts ") {
+        if let Some(enabled) = parse_toggle(argument) {
+            let command = ContextCommand::SetGifts { enabled };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/strict ") {
+        if let Some(enabled) = parse_toggle(argument) {
+            let command = ContextCommand::SetStrictInput { enabled };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/lang ") {
+        if let Some(locale) = Locale::parse(argument) {
+            send_admin_command(
+                tx,
+                message.from.as_ref(),
+                ContextCommand::SetLocale { locale },
+            );
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/rest ") {
+        if let Some(weekday) = parse_rest_weekday(argument) {
+            let command = ContextCommand::SetRestWeekday { weekday };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/pause_extends ") {
+        if let Some(enabled) = parse_toggle(argument) {
+            let command = ContextCommand::SetPauseExtends { enabled };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/notify ") {
+        if let Some(enabled) = parse_toggle(argument) {
+            let command = ContextCommand::SetNotifications { enabled };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(arguments) = text.strip_prefix("/target ") {
+        if let Some((name, target)) = parse_user_target(arguments) {
+            let command = ContextCommand::SetUserTarget { name, target };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(argument) = text.strip_prefix("/remove ") {
+        let name = argument.trim().trim_start_matches('@');
+
+        if !name.is_empty() && !name.contains(char::is_whitespace) {
+            let command = ContextCommand::RemoveUser {
+                name: name.to_string(),
+            };
+            send_admin_command(tx, message.from.as_ref(), command);
+        }
+        return;
+    }
+
+    if let Some(arguments) = text.strip_prefix("/give ") {
+        let from = message.from.as_ref().map(Participant::from_user);
+
+        if let (Some(from), Some((to, count))) = (from, parse_gift(arguments)) {
+            send_command(tx, ContextCommand::GiveReps { from, to, count });
+        }
+        return;
+    }
+
+    if let Some(note) = text.strip_prefix("/note ") {
+        let text = note.trim().to_string();
+        send_command(tx, ContextCommand::AddNote { text });
+        return;
+    }
+
+    // `/add 20` also reaches bots that privacy mode keeps from seeing plain
+    // messages.
+    let reps = if is_command(&text, "/add") {
+        parse_add(&text)
+    } else {
+        parse_reps(&text)
+    };
+
+    let (count, weight) = match reps {
+        Some(reps) => reps,
+        None => {
+            debug!("Error parsing count: {:?}", text);
+
+            if is_unknown_command(&text) {
+                send_command(tx, ContextCommand::UnknownCommand);
+                return;
             }
-            ContextCommand::AddPushups { username, count } => {
-                context_data.add_user_progress(username.clone(), count);
 
-                match context_data.update_daily_message() {
-                    Ok(response) => println!("Edit ok: {:?}", response),
-                    Err(err) => println!("Failed to update daily message: {:?}", err),
+            if let Some(from) = &message.from {
+                if !from.is_bot && !text.starts_with('/') {
+                    send_command(
+                        tx,
+                        ContextCommand::CleanUpMessage {
+                            user_id: from.id,
+                            message_id: message.message_id,
+                        },
+                    );
                 }
+            }
 
-                if context_data.is_user_done(username.clone()) {
-                    context_data.send_message("🥳".to_string());
-                }
+            return;
+        }
+    };
 
-                if context_data.is_all_users_done() {
-                    context_data.send_message("На сегодня всё 🎉".to_string());
-                }
+    let participant = match &message.from {
+        Some(from) => Participant::from_user(from),
+        None => return,
+    };
+
+    send_command(
+        tx,
+        ContextCommand::AddPushups {
+            participant,
+            count,
+            weight,
+            reply_to,
+            message_id: Some(message.message_id),
+        },
+    );
+}
+
+/// An edited rep log replaces what it counted. Edited to anything that isn't
+/// reps, it counts nothing. Edits of other messages do nothing.
+async fn edit_reps(message: Message, challenges: Vec<(u32, Sender<ContextCommand>)>) {
+    let text = match &message.text {
+        Some(text) => text,
+        None => return,
+    };
+
+    let reps = if is_command(text, "/add") {
+        parse_add(text)
+    } else {
+        parse_reps(text)
+    };
+    let (count, weight) = reps.unwrap_or((0, 1.0));
+
+    let reply_to = message
+        .reply_to_message
+        .as_ref()
+        .map(|reply| reply.message_id);
+    let tx = route_message(challenges, reply_to).await;
+
+    send_command(
+        tx,
+        ContextCommand::EditPushups {
+            message_id: message.message_id,
+            count,
+            weight,
+        },
+    );
+}
+
+/// Hands a press of a "Подтвердить" or "Отмена" button to the challenge
+/// named in its data.
+fn answer_confirmation(query: CallbackQuery, challenges: Vec<(u32, Sender<ContextCommand>)>) {
+    let (confirmed, challenge_id) = match query.data.as_deref().and_then(parse_confirmation) {
+        Some(answer) => answer,
+        None => return,
+    };
+    let message_id = match &query.message {
+        Some(message) => message.message_id,
+        None => return,
+    };
+
+    if let Some((_, tx)) = challenges.into_iter().find(|(id, _)| *id == challenge_id) {
+        send_command(
+            tx,
+            ContextCommand::ConfirmationAnswer {
+                query_id: query.id,
+                user_id: query.from.id,
+                message_id,
+                confirmed,
+            },
+        );
+    }
+}
+
+/// Reads `confirm:<challenge id>` or `cancel:<challenge id>`.
+fn parse_confirmation(data: &str) -> Option<(bool, u32)> {
+    let (answer, challenge_id) = data.split_once(':')?;
+    let confirmed = match answer {
+        "confirm" => true,
+        "cancel" => false,
+        _ => return None,
+    };
+
+    Some((confirmed, challenge_id.parse().ok()?))
+}
+
+/// Picks the challenge a message is meant for. With one challenge that's the
+/// one. With several, a reply to a challenge's daily message goes to that
+/// challenge, and everything else, bare rep counts included, goes to the
+/// oldest one.
+async fn route_message(
+    mut challenges: Vec<(u32, Sender<ContextCommand>)>,
+    reply_to: Option<i32>,
+) -> Sender<ContextCommand> {
+    if let (Some(message_id), true) = (reply_to, challenges.len() > 1) {
+        for (_, tx) in &challenges {
+            let (answer, is_daily_message) = oneshot::channel();
+
+            if tx
+                .send(ContextCommand::IsDailyMessage { message_id, answer })
+                .await
+                .is_ok()
+                && is_daily_message.await.unwrap_or(false)
+            {
+                return tx.clone();
             }
         }
     }
+
+    challenges.swap_remove(0).1
 }
 
-fn get_chat_id_from_update(update: Update) -> (Update, Option<i64>) {
-    if update.message.is_some() {
-        let chat_id = update.message.clone().unwrap().chat.id;
+/// Doubles the polling delay for every failed request in a row, up to
+/// `MAX_UPDATE_BACKOFF`.
+fn backoff_delay(
+    update_delay: core::time::Duration,
+    consecutive_failures: u32,
+) -> core::time::Duration {
+    let factor = 2_u32.saturating_pow(consecutive_failures);
 
-        (update, Some(chat_id))
-    } else if update.edited_message.is_some() {
-        let chat_id = update.edited_message.clone().unwrap().chat.id;
+    update_delay
+        .saturating_mul(factor)
+        .min(MAX_UPDATE_BACKOFF)
+        .max(update_delay)
+}
 
-        (update, Some(chat_id))
-    } else {
-        (update, None)
+/// Parses a rep log: either a bare count (`20`), a count with a difficulty
+/// multiplier (`20 x1.5`) or several sets (`10 15 20`), which are summed. A
+/// negative count (`-5`) corrects an earlier log. Anything else falls back to
+/// the only number in the message.
+fn parse_reps(text: &str) -> Option<(i64, f64)> {
+    let text = text.trim();
+
+    if text.starts_with('/') {
+        return None;
+    }
+
+    parse_weighted_reps(text)
+        .or_else(|| Some((parse_sets(text)?, 1.0)))
+        .or_else(|| Some((parse_count(text)?, 1.0)))
+}
+
+/// Sums a message made of positive numbers only, one per set.
+fn parse_sets(text: &str) -> Option<i64> {
+    text.split_whitespace()
+        .map(|word| word.parse::<i64>().ok().filter(|count| *count > 0))
+        .sum::<Option<i64>>()
+        .filter(|total| *total > 0)
+}
+
+/// Whether `text` is `command`, with arguments or addressed to the bot as
+/// `/command@bot`.
+fn is_command(text: &str, command: &str) -> bool {
+    text.split_whitespace()
+        .next()
+        .and_then(|word| word.strip_prefix(command))
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('@'))
+}
+
+/// Whether `text` is a command none of `COMMANDS` is.
+fn is_unknown_command(text: &str) -> bool {
+    match text.split_whitespace().next() {
+        Some(word) if word.starts_with('/') => !COMMANDS.contains(&word),
+        _ => false,
+    }
+}
+
+/// Turns `/command@bot ...` into `/command ...`, so groups can address the
+/// bot either way. `None` if the command is for some other bot in the chat.
+fn strip_bot_username(text: &str, bot_username: Option<&str>) -> Option<String> {
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+
+    let (command, username) = match word.split_once('@') {
+        Some(parts) if word.starts_with('/') => parts,
+        _ => return Some(text.to_string()),
+    };
+
+    if bot_username.is_some_and(|bot| !bot.eq_ignore_ascii_case(username)) {
+        return None;
+    }
+
+    Some(format!("{}{}", command, rest))
+}
+
+/// Whether the bot's new status in a chat (`my_chat_member`) means it is out
+/// of it: it left, was kicked (`banned`), or is restricted without being a
+/// member.
+fn is_removal(status: &ChatMember) -> bool {
+    match status {
+        ChatMember::Left(_) | ChatMember::Banned(_) => true,
+        ChatMember::Restricted(member) => !member.is_member,
+        _ => false,
+    }
+}
+
+/// Stops the chat's challenges once the bot is out of it, since every send
+/// there fails from now on. They leave the registry right away, so nothing
+/// else gets routed to them.
+async fn forget_chat(contexts: &Arc<Mutex<Contexts>>, chat_id: i64) {
+    let txs: Vec<Sender<ContextCommand>> = {
+        let mut locked = Contexts::lock(contexts);
+        let keys: Vec<ChallengeKey> = locked
+            .txs
+            .keys()
+            .filter(|(chat, _)| *chat == chat_id)
+            .copied()
+            .collect();
+        let txs = keys
+            .iter()
+            .filter_map(|key| locked.txs.remove(key))
+            .collect();
+        metrics::set_active_contexts(locked.txs.len());
+
+        txs
+    };
+
+    if !txs.is_empty() {
+        info!(
+            "Removed from {}, stopping {} challenges",
+            chat_id,
+            txs.len()
+        );
+    }
+
+    for tx in txs {
+        let _ = tx.send(ContextCommand::Removed).await;
+    }
+}
+
+fn owner_id() -> Option<u64> {
+    let owner_id = env::var("OWNER_ID").ok()?;
+
+    match owner_id.trim().parse() {
+        Ok(owner_id) => Some(owner_id),
+        Err(_) => {
+            warn!("Ignoring OWNER_ID {:?}, it isn't a user id", owner_id);
+            None
+        }
     }
 }
 
-fn get_day_duration() -> core::time::Duration {
-    let now = Utc::now();
-    let tomorrow_midnight = (now + Duration::days(1)).date().and_hms(0, 0, 0);
+/// Sends the bot's owner every running challenge with its day. The lock is
+/// only held to copy the senders: the challenges answer after it's released,
+/// and one that doesn't within `DESCRIBE_TIMEOUT` is left out.
+async fn list_challenges(
+    contexts: &Arc<Mutex<Contexts>>,
+    api: &dyn BotClient,
+    chat_id: i64,
+    locale: Locale,
+) {
+    let mut txs: Vec<(ChallengeKey, Sender<ContextCommand>)> = Contexts::lock(contexts)
+        .txs
+        .iter()
+        .filter(|(_, tx)| !tx.is_closed())
+        .map(|(key, tx)| (*key, tx.clone()))
+        .collect();
+    txs.sort_by_key(|(key, _)| *key);
 
-    tomorrow_midnight
-        .signed_duration_since(now)
-        .to_std()
-        .unwrap()
+    let descriptions = txs.into_iter().map(|(_, tx)| async move {
+        let (answer, description) = oneshot::channel();
+        tx.send(ContextCommand::Describe { locale, answer })
+            .await
+            .ok()?;
+
+        time::timeout(DESCRIBE_TIMEOUT, description)
+            .await
+            .ok()?
+            .ok()
+    });
+    let lines: Vec<String> = future::join_all(descriptions)
+        .await
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let text = if lines.is_empty() {
+        Text::NoChallenges.format(locale)
+    } else {
+        Text::ChallengesHeader(lines.len()).format(locale) + &lines.join("\n") + "\n"
+    };
+
+    send_text(api, chat_id, &text).await;
+}
+
+/// `/help` and unknown commands are answered in chats without a challenge
+/// too, where there's no handler to do it. The answer is filled in with the
+/// settings, and given in the language, a new challenge in the chat would
+/// start with.
+async fn answer_without_challenge(
+    contexts: &Arc<Mutex<Contexts>>,
+    api: &dyn BotClient,
+    chat_id: i64,
+    text: &str,
+) {
+    if text != "/help" && !is_unknown_command(text) {
+        return;
+    }
+
+    let text = {
+        let locked = Contexts::lock(contexts);
+        let chat_config = locked.chat_config(chat_id);
+        let mut state = WorkoutState::new(chat_id, &chat_config.over(&locked.config));
+        chat_config.apply(&mut state);
+
+        if text == "/help" {
+            state.generate_greeting_message(locked.config.greeting.as_deref(), COMMANDS)
+        } else {
+            state.text(Text::UnknownCommand)
+        }
+    };
+
+    send_text(api, chat_id, &text).await;
+}
+
+/// Sends `text` outside any challenge's handler, split to Telegram's limit.
+async fn send_text(api: &dyn BotClient, chat_id: i64, text: &str) {
+    for chunk in split_message(text, MAX_MESSAGE_LENGTH) {
+        let send_message_params: SendMessageParams = SendMessageParamsBuilder::default()
+            .chat_id(chat_id)
+            .text(chunk)
+            .build()
+            .unwrap();
+
+        if let Err(err) = api.send_message(&send_message_params).await {
+            error!("Failed to send message: {:?}", err);
+            metrics::increment(Counter::ApiErrors, 1);
+        }
+    }
+}
+
+async fn bot_username(api: &dyn BotClient) -> Option<String> {
+    match api.get_me().await {
+        Ok(response) => response.result.username,
+        Err(err) => {
+            error!("Failed to get bot user: {:?}", err);
+            None
+        }
+    }
+}
+
+/// Parses `/add 20`, `/add 20 x1.5` or `/add 10 15 20`. Unlike a bare
+/// message, the argument has to be the reps and nothing else.
+fn parse_add(text: &str) -> Option<(i64, f64)> {
+    let argument = text.trim().split_once(char::is_whitespace)?.1.trim();
+
+    parse_weighted_reps(argument).or_else(|| Some((parse_sets(argument)?, 1.0)))
+}
+
+fn parse_weighted_reps(text: &str) -> Option<(i64, f64)> {
+    let (count, weight) = match text.split_once(['x', 'х', '×']) {
+        Some((count, weight)) => (count.trim(), weight.trim().parse::<f64>().ok()?),
+        None => (text, 1.0),
+    };
+
+    if !(weight > 0.0 && weight <= MAX_WEIGHT) {
+        return None;
+    }
+
+    Some((count.parse::<i64>().ok()?, weight))
+}
+
+/// Pulls the standalone number out of a message, so that "сделал 15"
+/// counts. Numbers glued to a word ("10раз") and commands are ignored, and so
+/// are messages with several numbers among words, which could mean anything.
+fn parse_count(text: &str) -> Option<i64> {
+    if text.trim_start().starts_with('/') {
+        return None;
+    }
+
+    let mut numbers = text
+        .split_whitespace()
+        .map(|word| word.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+        .filter_map(|word| word.parse::<i64>().ok());
+
+    match (numbers.next(), numbers.next()) {
+        (Some(count), None) => Some(count),
+        _ => None,
+    }
+}
+
+/// Parses `@username 20` into the recipient and the number of reps.
+fn parse_gift(text: &str) -> Option<(String, usize)> {
+    let mut arguments = text.split_whitespace();
+    let to = arguments.next()?.trim_start_matches('@').to_string();
+    let count = arguments.next()?.parse::<usize>().ok()?;
+
+    if to.is_empty() || arguments.next().is_some() {
+        return None;
+    }
+
+    Some((to, count))
+}
+
+/// Parses `@username 50` into a user and their personal target, or
+/// `@username off` into the user and no target.
+fn parse_user_target(text: &str) -> Option<(String, Option<usize>)> {
+    let mut arguments = text.split_whitespace();
+    let name = arguments.next()?.trim_start_matches('@').to_string();
+    let target = match arguments.next()? {
+        "off" => None,
+        target => Some(target.parse::<usize>().ok().filter(|target| *target > 0)?),
+    };
+
+    if name.is_empty() || arguments.next().is_some() {
+        return None;
+    }
+
+    Some((name, target))
+}
+
+/// Parses `6` or `6 Europe/Moscow` into the reset hour and an optional
+/// timezone.
+fn parse_reset_time(text: &str) -> Option<(u32, Option<Tz>)> {
+    let mut arguments = text.split_whitespace();
+    let hour = arguments.next()?.parse::<u32>().ok()?;
+    let timezone = match arguments.next() {
+        Some(name) => Some(name.parse::<Tz>().ok()?),
+        None => None,
+    };
+
+    if hour > 23 || arguments.next().is_some() {
+        return None;
+    }
+
+    Some((hour, timezone))
+}
+
+/// Parses `20 25 30 40`, optionally followed by `hold` or `finish`, into a
+/// target schedule. `off` clears it.
+fn parse_schedule(text: &str) -> Option<(Option<Vec<usize>>, ScheduleEnd)> {
+    let mut arguments: Vec<&str> = text.split_whitespace().collect();
+
+    if arguments == ["off"] {
+        return Some((None, ScheduleEnd::Hold));
+    }
+
+    let end = match arguments.last() {
+        Some(&"finish") => ScheduleEnd::Finish,
+        _ => ScheduleEnd::Hold,
+    };
+    if matches!(arguments.last(), Some(&"hold") | Some(&"finish")) {
+        arguments.pop();
+    }
+
+    let schedule = arguments
+        .iter()
+        .map(|target| target.parse::<usize>().ok().filter(|target| *target > 0))
+        .collect::<Option<Vec<usize>>>()?;
+
+    if schedule.is_empty() || schedule.len() > MAX_DURATION {
+        return None;
+    }
+
+    Some((Some(schedule), end))
+}
+
+/// Parses `15` into a reminder hour, or `off` into no reminder.
+fn parse_reminder(text: &str) -> Option<Option<u32>> {
+    match text.trim() {
+        "off" => Some(None),
+        hour => hour
+            .parse::<u32>()
+            .ok()
+            .filter(|hour| *hour <= 23)
+            .map(Some),
+    }
+}
+
+fn parse_layout(text: &str) -> Option<Layout> {
+    match text.trim() {
+        "compact" => Some(Layout::Compact),
+        "detailed" => Some(Layout::Detailed),
+        _ => None,
+    }
+}
+
+fn parse_toggle(text: &str) -> Option<bool> {
+    match text.trim() {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Reads `/rest sunday` or `/rest off`. `None` when it's neither.
+fn parse_rest_weekday(text: &str) -> Option<Option<Weekday>> {
+    match text.trim() {
+        "off" => Some(None),
+        weekday => weekday.parse().ok().map(Some),
+    }
+}
+
+/// Sends a configuration or destructive command, which the handler only runs
+/// for chat administrators.
+fn send_admin_command(tx: Sender<ContextCommand>, from: Option<&User>, command: ContextCommand) {
+    if let Some(from) = from {
+        let command = ContextCommand::AdminOnly {
+            user_id: from.id,
+            command: Box::new(command),
+        };
+        send_command(tx, command);
+    }
+}
+
+fn send_command(tx: Sender<ContextCommand>, command: ContextCommand) {
+    tokio::spawn(async move {
+        tx.send(command).await.unwrap_or_else(|err| {
+            error!("Error sending command: {}", err);
+        })
+    });
+}
+
+/// Starts a challenge. Without an exercise that's `/start`, which does
+/// nothing if the chat already has a running challenge; with one it's `/new`,
+/// which adds a challenge next to the running ones. The check and the
+/// registration happen under one lock, so two `/start`s racing each other
+/// still create a single handler. Returns whether it started.
+fn init_context(
+    contexts: &Arc<Mutex<Contexts>>,
+    chat_id: i64,
+    api: Arc<dyn BotClient>,
+    exercise: Option<String>,
+) -> bool {
+    let mut locked = Contexts::lock(contexts);
+    let running = locked.challenges(chat_id);
+
+    if exercise.is_none() && !running.is_empty() {
+        return false;
+    }
+
+    let chat_config = locked.chat_config(chat_id);
+    let mut context_data = ContextData::new(api, chat_id, &chat_config.over(&locked.config));
+    chat_config.apply(&mut context_data.state);
+    context_data.state.challenge_id = running.last().map_or(0, |(id, _)| id + 1);
+    let template = locked.config.greeting.clone();
+    let tx = register_context(contexts, &mut locked, context_data);
+
+    tokio::spawn(async move {
+        if let Some(name) = exercise {
+            tx.send(ContextCommand::SetExercise { name }).await?;
+        }
+
+        tx.send(ContextCommand::Greet { template }).await?;
+        tx.send(ContextCommand::SendDailyMessage).await
+    });
+
+    true
+}
+
+/// Starts the command handler for a chat and registers its sender.
+fn spawn_context(
+    contexts: &Arc<Mutex<Contexts>>,
+    context_data: ContextData,
+) -> Sender<ContextCommand> {
+    let mut locked = Contexts::lock(contexts);
+
+    register_context(contexts, &mut locked, context_data)
+}
+
+/// `WORKOUT_DAY_SECONDS` shortens every day to that many seconds, so that a
+/// whole challenge can be run through by hand.
+fn day_length_override() -> Option<Duration> {
+    let seconds = env::var("WORKOUT_DAY_SECONDS").ok()?.parse::<i64>().ok()?;
+
+    (seconds > 0).then(|| Duration::seconds(seconds))
+}
+
+fn register_context(
+    contexts: &Arc<Mutex<Contexts>>,
+    locked: &mut Contexts,
+    mut context_data: ContextData,
+) -> Sender<ContextCommand> {
+    let (tx, rx) = mpsc::channel(2048);
+
+    let key = context_data.state.key();
+    let cloned_contexts = Arc::clone(contexts);
+    context_data.edit_debounce = locked.config.edit_debounce();
+    context_data.day_length = day_length_override();
+    context_data.chat_config_dir = locked.chat_config_dir.clone();
+    context_data.event_webhook_url = locked.event_webhook_url.clone();
+
+    if context_data.day_length.is_none() {
+        let skipped = context_data.catch_up_missed_resets();
+
+        if skipped > 0 {
+            info!("Caught up on {} days missed by {:?}", skipped, key);
+        }
+    }
+
+    // Chats saved before reset times were tracked have nothing scheduled, and
+    // a shortened day shouldn't wait for a reset planned with the real one.
+    if context_data.state.started
+        && (context_data.state.next_reset.is_none() || context_data.day_length.is_some())
+    {
+        context_data.schedule_next_reset();
+    }
+    context_data.schedule_next_reminder();
+    locked.txs.insert(key, tx.clone());
+    metrics::set_active_contexts(locked.txs.len());
+
+    let store = Arc::clone(&locked.store);
+    tokio::spawn(async move {
+        // A panicking handler only takes its own challenge down; the entry
+        // is still cleaned up so the chat can start a new one.
+        if let Err(err) = tokio::spawn(handle_commands(context_data, rx, store)).await {
+            error!("Handler for {:?} failed: {}", key, err);
+        }
+        remove_closed_context(&cloned_contexts, key);
+    });
+
+    tx
+}
+
+/// Forgets a challenge whose handler has stopped, so that `/start` can begin
+/// a new one there. A sender that is still open belongs to a newer handler.
+fn remove_closed_context(contexts: &Arc<Mutex<Contexts>>, key: ChallengeKey) {
+    let mut contexts = Contexts::lock(contexts);
+
+    if contexts.txs.get(&key).is_some_and(Sender::is_closed) {
+        contexts.txs.remove(&key);
+        metrics::set_active_contexts(contexts.txs.len());
+    }
+}
+
+pub async fn handle_commands(
+    mut context_data: ContextData,
+    mut rx: Receiver<ContextCommand>,
+    store: Arc<dyn Store>,
+) {
+    loop {
+        let edit_deadline = context_data.edit_retry_deadline();
+        let reset_delay = context_data.time_until_reset();
+        let reminder_delay = context_data.time_until_reminder();
+
+        // Each chat keeps its own schedule: the next day starts when this
+        // chat's reset time comes, not when some shared timer fires.
+        let command = tokio::select! {
+            command = rx.recv() => command,
+            _ = time::sleep_until(edit_deadline.unwrap_or_else(time::Instant::now)),
+                if edit_deadline.is_some() =>
+            {
+                context_data.flush_daily_message().await;
+                continue;
+            }
+            _ = time::sleep(reset_delay.unwrap_or_default()), if reset_delay.is_some() => {
+                Some(ContextCommand::SendDailyMessage)
+            }
+            _ = time::sleep(reminder_delay.unwrap_or_default()), if reminder_delay.is_some() => {
+                Some(ContextCommand::Remind)
+            }
+        };
+
+        let command = match command {
+            Some(command) => command,
+            None => {
+                context_data.flush_daily_message().await;
+                break;
+            }
+        };
+
+        let command = match command {
+            ContextCommand::AdminOnly { user_id, command } => {
+                if !context_data.is_chat_admin(user_id).await {
+                    context_data
+                        .reply(context_data.state.text(Text::AdminOnly))
+                        .await;
+                    continue;
+                }
+
+                *command
+            }
+            ContextCommand::ConfirmationAnswer {
+                query_id,
+                user_id,
+                message_id,
+                confirmed,
+            } => {
+                match context_data
+                    .answer_confirmation(&query_id, user_id, message_id, confirmed)
+                    .await
+                {
+                    Some(command) => command,
+                    None => continue,
+                }
+            }
+            command => command,
+        };
+
+        let changes_settings = command.changes_settings();
+
+        match command {
+            ContextCommand::UnknownCommand => {
+                context_data
+                    .reply(context_data.state.text(Text::UnknownCommand))
+                    .await;
+            }
+            ContextCommand::Greet { template } => {
+                context_data
+                    .reply(
+                        context_data
+                            .state
+                            .generate_greeting_message(template.as_deref(), COMMANDS),
+                    )
+                    .await;
+
+                if !context_data.sees_all_messages().await {
+                    context_data
+                        .reply(context_data.state.text(Text::CantSeeMessages))
+                        .await;
+                }
+            }
+            ContextCommand::SendDailyMessage => {
+                if context_data.pending_edit {
+                    context_data.flush_daily_message().await;
+                }
+
+                context_data.unpin_daily_message().await;
+
+                if context_data.state.is_workout_over() {
+                    finish_workout(&context_data, &mut rx, &store).await;
+
+                    return;
+                }
+
+                let now = context_data.clock.now();
+                context_data.state.resting =
+                    context_data.state.started && context_data.state.is_rest_day(now);
+
+                if context_data.state.paused {
+                    context_data.state.skip_paused_day();
+                } else if !context_data.state.resting {
+                    let previous_repeats = context_data.state.start_next_day();
+                    context_data.post_event(Event::day_started(&context_data.state));
+
+                    if let Some(previous_repeats) = previous_repeats {
+                        context_data
+                            .send_important_message(
+                                context_data
+                                    .state
+                                    .generate_end_of_cycle_message(previous_repeats),
+                            )
+                            .await;
+                    }
+
+                    if context_data.state.should_nudge() {
+                        context_data
+                            .reply(context_data.state.text(Text::Nudge))
+                            .await;
+                    }
+                }
+
+                context_data.schedule_next_reset();
+
+                // The day has moved on either way; the next reset posts a
+                // fresh message.
+                if let Err(err) = context_data.post_daily_message().await {
+                    error!("Failed to post daily message: {}", err);
+                    metrics::increment(Counter::ApiErrors, 1);
+                }
+            }
+            ContextCommand::Remind => {
+                context_data.schedule_next_reminder();
+
+                // A separate message, so the pinned daily message stays as it is.
+                if context_data.state.started
+                    && !context_data.state.paused
+                    && !context_data.state.resting
+                {
+                    if let Some(text) = context_data.state.generate_reminder_message() {
+                        context_data.reply(text).await;
+                    }
+                }
+            }
+            // Neither a paused day nor a rest day has a day of its own, so
+            // changes would land on the day they froze.
+            ContextCommand::AddPushups { .. }
+            | ContextCommand::EditPushups { .. }
+            | ContextCommand::GiveReps { .. }
+            | ContextCommand::Undo
+                if context_data.state.paused || context_data.state.resting =>
+            {
+                let text = context_data.state.text(if context_data.state.paused {
+                    Text::Paused
+                } else {
+                    Text::RestDay
+                });
+                context_data.reply(text.trim().to_string()).await;
+            }
+            ContextCommand::AddPushups { reply_to, .. }
+                if context_data.state.strict_input
+                    && reply_to != context_data.state.daily_message_id =>
+            {
+                debug!("Ignoring reps that don't reply to the daily message");
+            }
+            ContextCommand::AddPushups {
+                participant,
+                count,
+                weight,
+                message_id,
+                ..
+            } => {
+                let user_id = participant.id;
+                let was_done = context_data.state.is_user_done(user_id);
+                context_data
+                    .state
+                    .add_weighted_user_progress(participant, count, weight);
+                metrics::increment(Counter::RepsLogged, count.max(0) as u64);
+
+                if let Some(message_id) = message_id {
+                    context_data.state.remember_message(message_id);
+                }
+
+                context_data.post_event(Event::reps(&context_data.state, user_id, count));
+                announce_progress(&mut context_data, user_id, was_done).await;
+
+                if context_data.state.is_goal_reached() {
+                    finish_workout(&context_data, &mut rx, &store).await;
+
+                    return;
+                }
+            }
+            ContextCommand::EditPushups {
+                message_id,
+                count,
+                weight,
+            } => {
+                let user_id = match context_data.state.logged_messages.get(&message_id) {
+                    Some(logged) => logged.user_id,
+                    None => {
+                        debug!("Ignoring an edit of message {}", message_id);
+                        continue;
+                    }
+                };
+                let was_done = context_data.state.is_user_done(user_id);
+                context_data
+                    .state
+                    .edit_logged_message(message_id, count, weight);
+
+                announce_progress(&mut context_data, user_id, was_done).await;
+
+                if context_data.state.is_goal_reached() {
+                    finish_workout(&context_data, &mut rx, &store).await;
+
+                    return;
+                }
+            }
+            ContextCommand::AddNote { text } => {
+                context_data.state.add_day_note(text);
+                context_data
+                    .reply(
+                        context_data
+                            .state
+                            .text(Text::NoteSaved(WorkoutState::display_day(
+                                context_data.state.current_day,
+                            ))),
+                    )
+                    .await;
+            }
+            ContextCommand::ShowStatus => {
+                let now = context_data.clock.now();
+                context_data
+                    .reply(context_data.state.generate_status_message(now))
+                    .await;
+            }
+            ContextCommand::ShowWinners => {
+                context_data
+                    .send_long_message(context_data.state.generate_winners_message())
+                    .await;
+            }
+            ContextCommand::ShowHistory => {
+                context_data
+                    .send_long_message(context_data.state.generate_history_message())
+                    .await;
+            }
+            ContextCommand::ShowLeaderboard => {
+                context_data
+                    .reply(context_data.state.generate_leaderboard())
+                    .await;
+            }
+            ContextCommand::ShowStats => {
+                context_data
+                    .reply(context_data.state.generate_stats_message())
+                    .await;
+            }
+            ContextCommand::ShowMe { user_id } => {
+                context_data
+                    .reply(context_data.state.generate_me_message(user_id))
+                    .await;
+            }
+            ContextCommand::Export => {
+                let csv = context_data.state.generate_progress_csv();
+
+                if let Err(err) = context_data.send_document("progress.csv", &csv).await {
+                    error!("Failed to send the export: {}", err);
+                    metrics::increment(Counter::ApiErrors, 1);
+                }
+            }
+            ContextCommand::ShowTop { day } => {
+                let day = day.unwrap_or(context_data.state.current_day);
+                context_data
+                    .reply(context_data.state.generate_daily_top(day))
+                    .await;
+            }
+            ContextCommand::NoSuchDay => {
+                let days = context_data.state.progress.len();
+                context_data
+                    .reply(context_data.state.text(Text::NoSuchDay(days)))
+                    .await;
+            }
+            ContextCommand::ShowConfig => {
+                context_data
+                    .reply(context_data.state.generate_config_message())
+                    .await;
+            }
+            ContextCommand::Join { participant } => {
+                let name = participant.display_name.clone();
+
+                if context_data.state.join(participant) {
+                    context_data.refresh_daily_message().await;
+                    context_data
+                        .reply(context_data.state.text(Text::Joined(&name)))
+                        .await;
+                } else {
+                    context_data
+                        .reply(context_data.state.text(Text::AlreadyJoined(&name)))
+                        .await;
+                }
+            }
+            ContextCommand::Leave { participant } => {
+                if context_data.state.leave(participant.id) {
+                    context_data.refresh_daily_message().await;
+                    context_data
+                        .reply(
+                            context_data
+                                .state
+                                .text(Text::Left(&participant.display_name)),
+                        )
+                        .await;
+                } else {
+                    context_data
+                        .reply(
+                            context_data
+                                .state
+                                .text(Text::AlreadyLeft(&participant.display_name)),
+                        )
+                        .await;
+                }
+            }
+            ContextCommand::Undo => match context_data.state.undo_last_entry() {
+                Some(entry) => {
+                    context_data.refresh_daily_message().await;
+                    let name = context_data.state.display_name(entry.user_id);
+                    context_data
+                        .reply(context_data.state.text(Text::Undone {
+                            name: &name,
+                            count: entry.raw_count,
+                        }))
+                        .await;
+                }
+                None => {
+                    context_data
+                        .reply(context_data.state.text(Text::NothingToUndo))
+                        .await;
+                }
+            },
+            ContextCommand::AdminOnly { .. } => {
+                warn!("Ignoring a nested admin-only command");
+            }
+            ContextCommand::ConfirmationAnswer { .. } => {
+                warn!("Ignoring a confirmation that confirms another");
+            }
+            ContextCommand::Confirm { action, user_id } => {
+                context_data.ask_confirmation(action, user_id).await;
+            }
+            ContextCommand::Stop => {
+                finish_workout(&context_data, &mut rx, &store).await;
+
+                return;
+            }
+            ContextCommand::Reset => {
+                context_data.unpin_daily_message().await;
+                context_data.state.reset();
+                context_data.pending_edit = false;
+                context_data
+                    .reply(context_data.state.text(Text::Restarted))
+                    .await;
+
+                context_data.state.start_next_day();
+                context_data.schedule_next_reset();
+
+                if let Err(err) = context_data.post_daily_message().await {
+                    error!("Failed to post daily message: {}", err);
+                    metrics::increment(Counter::ApiErrors, 1);
+                }
+            }
+            ContextCommand::GiveReps { from, to, count } => {
+                let recipient = context_data
+                    .state
+                    .find_user_by_name(&to)
+                    .map(|user| user.id);
+                let was_done = recipient.is_some_and(|to| context_data.state.is_user_done(to));
+
+                let result = match recipient {
+                    _ if !context_data.state.allow_gifts => {
+                        Err(context_data.state.text(Text::GiftsDisabled))
+                    }
+                    Some(to) => context_data
+                        .state
+                        .give_reps(from.id, to, count)
+                        .map(|()| to),
+                    None => Err(context_data.state.text(Text::NotJoinedYet(&to))),
+                };
+
+                match result {
+                    Ok(recipient) => {
+                        if !was_done && context_data.state.is_user_done(recipient) {
+                            let now = context_data.clock.now();
+                            context_data.state.mark_user_finished(recipient, now);
+                        }
+
+                        context_data.refresh_daily_message().await;
+
+                        context_data
+                            .reply(context_data.state.text(Text::Gift {
+                                from: &from.display_name,
+                                to: &to,
+                                count,
+                            }))
+                            .await;
+                    }
+                    Err(text) => {
+                        context_data.reply(text).await;
+                    }
+                }
+            }
+            ContextCommand::SetGifts { enabled } => {
+                context_data.state.allow_gifts = enabled;
+                context_data
+                    .reply(context_data.state.text(Text::GiftsSet(enabled)))
+                    .await;
+            }
+            ContextCommand::SetStrictInput { enabled } => {
+                context_data.state.strict_input = enabled;
+                context_data
+                    .reply(context_data.state.text(Text::StrictInputSet(enabled)))
+                    .await;
+            }
+            ContextCommand::SetLocale { locale } => {
+                context_data.state.set_locale(locale);
+                context_data
+                    .reply(context_data.state.text(Text::LocaleSet))
+                    .await;
+                context_data.refresh_daily_message().await;
+            }
+            ContextCommand::SetPaused { paused } => {
+                let text = if context_data.state.paused == paused {
+                    Text::PauseUnchanged(paused)
+                } else {
+                    Text::PauseSet(paused)
+                };
+                context_data.state.paused = paused;
+                context_data.reply(context_data.state.text(text)).await;
+                context_data.refresh_daily_message().await;
+            }
+            ContextCommand::SetRestWeekday { weekday } => {
+                context_data.state.rest_weekday = weekday;
+                context_data
+                    .reply(context_data.state.text(Text::RestDaySet(weekday)))
+                    .await;
+            }
+            ContextCommand::SetPauseExtends { enabled } => {
+                context_data.state.pause_extends = enabled;
+                context_data
+                    .reply(context_data.state.text(Text::PauseExtendsSet(enabled)))
+                    .await;
+            }
+            ContextCommand::SetNotifications { enabled } => {
+                context_data.state.silent = !enabled;
+                context_data
+                    .reply(context_data.state.text(Text::NotificationsSet(enabled)))
+                    .await;
+            }
+            ContextCommand::SetUserTarget { name, target } => {
+                let participant = context_data.state.find_user_by_name(&name).cloned();
+
+                if let Some(participant) = participant {
+                    match target {
+                        Some(target) => {
+                            context_data
+                                .state
+                                .targets_per_user
+                                .insert(participant.id, target);
+                        }
+                        None => {
+                            context_data.state.targets_per_user.remove(&participant.id);
+                        }
+                    }
+
+                    context_data
+                        .reply(context_data.state.text(Text::UserTargetSet {
+                            name: &participant.display_name,
+                            target,
+                        }))
+                        .await;
+                    context_data.refresh_daily_message().await;
+                } else {
+                    context_data
+                        .reply(context_data.state.text(Text::NotJoinedYet(&name)))
+                        .await;
+                }
+            }
+            ContextCommand::RemoveUser { name } => {
+                match context_data
+                    .state
+                    .find_user_by_name(&name)
+                    .map(|user| user.id)
+                {
+                    Some(user_id) => {
+                        context_data.state.remove_participant(user_id);
+                        context_data.refresh_daily_message().await;
+                        context_data
+                            .reply(context_data.state.text(Text::UserRemoved(&name)))
+                            .await;
+                    }
+                    None => {
+                        context_data
+                            .reply(context_data.state.text(Text::NotInWorkout(&name)))
+                            .await;
+                    }
+                }
+            }
+            ContextCommand::SetLayout { layout } => {
+                context_data.state.layout = layout;
+
+                context_data.refresh_daily_message().await;
+            }
+            ContextCommand::SetExercise { name } => {
+                if name.is_empty() || name.chars().count() > MAX_EXERCISE_LENGTH {
+                    context_data
+                        .reply(
+                            context_data
+                                .state
+                                .text(Text::ExerciseLength(MAX_EXERCISE_LENGTH)),
+                        )
+                        .await;
+                } else {
+                    context_data.state.exercise = name;
+                    context_data
+                        .reply(
+                            context_data
+                                .state
+                                .text(Text::ExerciseSet(&context_data.state.exercise)),
+                        )
+                        .await;
+
+                    context_data.refresh_daily_message().await;
+                }
+            }
+            ContextCommand::SetCycleLength { days } => {
+                if days == 0 || days > MAX_DURATION {
+                    context_data
+                        .reply(
+                            context_data
+                                .state
+                                .text(Text::CycleLengthRange(MAX_DURATION)),
+                        )
+                        .await;
+                } else if !context_data.state.started {
+                    context_data.state.cycle_length = days;
+                    context_data
+                        .reply(context_data.state.text(Text::CycleLengthSet(days)))
+                        .await;
+                } else {
+                    context_data.state.next_cycle_length = Some(days);
+                    context_data
+                        .reply(context_data.state.text(Text::NextCycleLengthSet(days)))
+                        .await;
+                }
+            }
+            ContextCommand::SetCycleIncrease { increase } => {
+                context_data.state.cycle_increase = increase;
+                context_data
+                    .reply(context_data.state.text(Text::CycleIncreaseSet(increase)))
+                    .await;
+            }
+            ContextCommand::SetStartRepeats { repeats } => {
+                match context_data.state.set_start_repeats(repeats) {
+                    Ok(()) => {
+                        context_data
+                            .reply(context_data.state.text(Text::StartRepeatsSet {
+                                repeats,
+                                increase: context_data.state.cycle_increase,
+                                cycle_length: context_data.state.cycle_length,
+                            }))
+                            .await;
+                        context_data.refresh_daily_message().await;
+                    }
+                    Err(text) => {
+                        context_data.reply(text).await;
+                    }
+                }
+            }
+            ContextCommand::SetGoal { target } => {
+                let text = match target {
+                    Some(0) => context_data.state.text(Text::GoalZero),
+                    Some(target) => {
+                        context_data.state.mode = WorkoutMode::TotalGoal { target };
+                        context_data.state.text(Text::GoalSet {
+                            target,
+                            duration: context_data.state.duration,
+                        })
+                    }
+                    None => {
+                        context_data.state.mode = WorkoutMode::Daily;
+                        context_data.state.text(Text::DailyTargets)
+                    }
+                };
+
+                context_data.reply(text).await;
+                context_data.refresh_daily_message().await;
+
+                if context_data.state.is_goal_reached() {
+                    finish_workout(&context_data, &mut rx, &store).await;
+
+                    return;
+                }
+            }
+            ContextCommand::SetSchedule { schedule, end } => {
+                let text = match (&schedule, end) {
+                    (None, _) => context_data.state.text(Text::CycleTargets),
+                    (Some(schedule), end) => {
+                        let targets: Vec<String> = schedule.iter().map(usize::to_string).collect();
+
+                        context_data.state.text(Text::ScheduleSet {
+                            targets: &targets.join(", "),
+                            finish: end == ScheduleEnd::Finish,
+                        })
+                    }
+                };
+
+                context_data.state.set_schedule(schedule, end);
+                context_data.reply(text).await;
+                context_data.refresh_daily_message().await;
+            }
+            ContextCommand::SetDuration { days } => {
+                if days == 0 || days > MAX_DURATION {
+                    context_data
+                        .reply(context_data.state.text(Text::DurationRange(MAX_DURATION)))
+                        .await;
+                } else if context_data.state.started && context_data.state.current_day >= days {
+                    context_data.state.duration = days;
+                    finish_workout(&context_data, &mut rx, &store).await;
+
+                    return;
+                } else {
+                    context_data.state.duration = days;
+                    context_data
+                        .reply(context_data.state.text(Text::DurationSet(days)))
+                        .await;
+
+                    context_data.refresh_daily_message().await;
+                }
+            }
+            ContextCommand::SetDay { day } => match context_data.state.set_day(day) {
+                Ok(()) => {
+                    context_data
+                        .reply(context_data.state.text(Text::DaySet {
+                            day,
+                            duration: context_data.state.duration,
+                        }))
+                        .await;
+                    context_data.refresh_daily_message().await;
+                }
+                Err(text) => {
+                    context_data.reply(text).await;
+                }
+            },
+            ContextCommand::SetResetTime { hour, timezone } => {
+                context_data.state.reset_hour = hour;
+
+                if let Some(timezone) = timezone {
+                    context_data.state.timezone = timezone;
+                }
+
+                if context_data.state.started {
+                    context_data.schedule_next_reset();
+                }
+                context_data.schedule_next_reminder();
+
+                context_data
+                    .reply(context_data.state.text(Text::ResetTimeSet {
+                        hour: context_data.state.reset_hour,
+                        timezone: context_data.state.timezone.name(),
+                    }))
+                    .await;
+            }
+            ContextCommand::SetReminder { hour } => {
+                context_data.state.reminder_hour = hour;
+                context_data.schedule_next_reminder();
+
+                context_data
+                    .reply(context_data.state.text(Text::ReminderSet {
+                        hour,
+                        timezone: context_data.state.timezone.name(),
+                    }))
+                    .await;
+            }
+            ContextCommand::IsDailyMessage { message_id, answer } => {
+                let _ = answer.send(context_data.state.daily_message_id == Some(message_id));
+
+                continue;
+            }
+            ContextCommand::Describe { locale, answer } => {
+                let state = &context_data.state;
+                let _ = answer.send(
+                    Text::Description {
+                        chat_id: state.chat_id,
+                        challenge_id: state.challenge_id,
+                        exercise: &state.exercise,
+                        day: WorkoutState::display_day(state.current_day),
+                        duration: state.duration,
+                        users: state.users.len(),
+                    }
+                    .format(locale),
+                );
+
+                continue;
+            }
+            ContextCommand::SetEditDebounce(debounce) => {
+                context_data.edit_debounce = debounce;
+            }
+            ContextCommand::Removed => {
+                rx.close();
+
+                if let Err(err) = store.delete_context(context_data.state.key()) {
+                    error!("Failed to delete context: {:?}", err);
+                }
+
+                return;
+            }
+            ContextCommand::Shutdown { done } => {
+                context_data.flush_daily_message().await;
+                context_data.unpin_daily_message().await;
+                save_context(&store, &context_data);
+                let _ = done.send(());
+
+                return;
+            }
+            ContextCommand::SetCleanChat { enabled } => {
+                if enabled && !context_data.can_delete_messages().await {
+                    context_data
+                        .reply(context_data.state.text(Text::NoDeleteRights))
+                        .await;
+                } else {
+                    context_data.state.clean_chat = enabled;
+                    context_data
+                        .reply(context_data.state.text(Text::CleanChatSet(enabled)))
+                        .await;
+                }
+            }
+            ContextCommand::CleanUpMessage {
+                user_id,
+                message_id,
+            } => {
+                if context_data.state.clean_chat
+                    && !context_data.is_chat_admin(user_id).await
+                    && context_data.can_delete_messages().await
+                {
+                    context_data.delete_message(message_id).await;
+                }
+            }
+        }
+
+        if changes_settings {
+            context_data.save_chat_config();
+        }
+        save_context(&store, &context_data);
+    }
+}
+
+fn save_context(store: &Arc<dyn Store>, context_data: &ContextData) {
+    if let Err(err) = context_data.save(store.as_ref()) {
+        error!("Failed to save context: {}", err);
+    }
+}
+
+/// Updates the daily message after a user's count changed, and congratulates
+/// them if that got them to the target.
+async fn announce_progress(context_data: &mut ContextData, user_id: i64, was_done: bool) {
+    let finished = !was_done && context_data.state.is_user_done(user_id);
+
+    if finished {
+        let now = context_data.clock.now();
+        context_data.state.mark_user_finished(user_id, now);
+    }
+
+    context_data.refresh_daily_message().await;
+
+    // Only the log that crosses the target is congratulated.
+    if finished {
+        context_data
+            .reply(context_data.state.generate_user_done_message(user_id))
+            .await;
+    }
+
+    if context_data.state.announce_all_done() {
+        context_data
+            .reply(context_data.state.generate_all_done_message())
+            .await;
+    }
+}
+
+/// Posts the final summary and shuts the chat's handler down.
+async fn finish_workout(
+    context_data: &ContextData,
+    rx: &mut Receiver<ContextCommand>,
+    store: &Arc<dyn Store>,
+) {
+    context_data
+        .send_important_message(context_data.state.generate_final_message())
+        .await;
+    context_data.unpin_daily_message().await;
+    context_data.post_event(Event::finished(&context_data.state));
+    rx.close();
+
+    if let Err(err) = store.delete_context(context_data.state.key()) {
+        error!("Failed to delete context: {:?}", err);
+    }
+}
+
+/// The workout chat an update belongs to. Channels have no one to log reps,
+/// so their messages are skipped.
+fn get_chat_id_from_update(update: Update) -> (Update, Option<i64>) {
+    let message = update
+        .message
+        .as_ref()
+        .or(update.edited_message.as_ref())
+        .or_else(|| update.callback_query.as_ref()?.message.as_ref());
+
+    let chat = match message {
+        Some(message) => &message.chat,
+        None => return (update, None),
+    };
+
+    match chat.type_field {
+        ChatType::Private | ChatType::Group | ChatType::Supergroup => {
+            let chat_id = chat.id;
+
+            (update, Some(chat_id))
+        }
+        ChatType::Channel => {
+            info!(
+                "Skipping update from unsupported chat {} ({:?})",
+                chat.id, chat.type_field
+            );
+
+            (update, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::{Call, MockClient};
+    use crate::clock::mock::MockClock;
+    use crate::storage::SqliteStore;
+    use chrono::{TimeZone, Utc};
+    use frankenstein::{
+        ChatBuilder, ChatMemberBanned, ChatMemberMember, ChatMemberUpdatedBuilder, MessageBuilder,
+        UpdateBuilder, UserBuilder,
+    };
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// A registry over an empty in-memory store, with the mock client behind
+    /// it both as the registry's API and for inspecting calls.
+    fn setup() -> (Arc<Mutex<Contexts>>, Arc<dyn BotClient>, Arc<MockClient>) {
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let client = Arc::new(MockClient::default());
+        let api: Arc<dyn BotClient> = client.clone();
+        let contexts = Arc::new(Mutex::new(Contexts::new(
+            api.clone(),
+            Config::default(),
+            store,
+        )));
+
+        (contexts, api, client)
+    }
+
+    /// Registers a bare channel as chat 1's challenge, to see what gets
+    /// routed to it.
+    fn register_channel(contexts: &Mutex<Contexts>) -> Receiver<ContextCommand> {
+        let (tx, rx) = mpsc::channel(16);
+        contexts.lock().unwrap().txs.insert((1, 0), tx);
+
+        rx
+    }
+
+    /// Runs a handler for `context_data` over its own in-memory store.
+    fn spawn_handler(
+        context_data: ContextData,
+    ) -> (Sender<ContextCommand>, tokio::task::JoinHandle<()>) {
+        let (tx, rx) = mpsc::channel(16);
+        let store = Arc::new(SqliteStore::open(":memory:").unwrap());
+
+        (tx, tokio::spawn(handle_commands(context_data, rx, store)))
+    }
+
+    async fn send(tx: &Sender<ContextCommand>, command: ContextCommand) {
+        tx.send(command).await.unwrap();
+        // Let the handler drain the channel before the test moves on.
+        tokio::task::yield_now().await;
+    }
+
+    fn add(id: i64, display_name: &str, count: i64) -> ContextCommand {
+        ContextCommand::AddPushups {
+            participant: Participant {
+                id,
+                display_name: display_name.to_string(),
+            },
+            count,
+            weight: 1.0,
+            reply_to: None,
+            message_id: None,
+        }
+    }
+
+    static NEXT_UPDATE_ID: AtomicU32 = AtomicU32::new(1);
+
+    fn text_update(chat_id: i64, user_id: u64, text: &str) -> Update {
+        let chat = ChatBuilder::default()
+            .id(chat_id)
+            .type_field(ChatType::Group)
+            .build()
+            .unwrap();
+        let from = UserBuilder::default()
+            .id(user_id)
+            .is_bot(false)
+            .first_name("alice")
+            .username("alice")
+            .build()
+            .unwrap();
+        let message = MessageBuilder::default()
+            .message_id(1)
+            .date(0_u64)
+            .chat(chat)
+            .from(from)
+            .text(text)
+            .build()
+            .unwrap();
+
+        UpdateBuilder::default()
+            .update_id(NEXT_UPDATE_ID.fetch_add(1, Ordering::Relaxed))
+            .message(message)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn polling_settings_are_validated() {
+        assert_eq!(parse_poll_interval(" 250 "), Some(250));
+        assert_eq!(parse_poll_interval("0"), None);
+        assert_eq!(parse_poll_interval("fast"), None);
+
+        assert_eq!(
+            parse_allowed_updates("message, edited_message,callback_query"),
+            Some(strings_vec!["message", "edited_message", "callback_query"])
+        );
+        assert_eq!(parse_allowed_updates("message,reactions"), None);
+        assert_eq!(parse_allowed_updates(" , "), None);
+    }
+
+    #[tokio::test]
+    async fn reps_can_be_sent_as_a_command() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        process_update(text_update(1, 7, "/add 20 x1.5"), &contexts, &api).await;
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::AddPushups { count: 20, weight, .. }) if weight == 1.5
+        ));
+    }
+
+    #[tokio::test]
+    async fn top_refuses_what_isnt_a_day() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        for text in ["/top 0", "/top abc"] {
+            process_update(text_update(1, 7, text), &contexts, &api).await;
+            assert!(matches!(rx.recv().await, Some(ContextCommand::NoSuchDay)));
+        }
+
+        process_update(text_update(1, 7, "/top 2"), &contexts, &api).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::ShowTop { day: Some(1) })
+        ));
+    }
+
+    #[test]
+    fn rest_weekday_is_parsed() {
+        assert_eq!(parse_rest_weekday(" sunday"), Some(Some(Weekday::Sun)));
+        assert_eq!(parse_rest_weekday("Sat"), Some(Some(Weekday::Sat)));
+        assert_eq!(parse_rest_weekday("off"), Some(None));
+        assert_eq!(parse_rest_weekday("someday"), None);
+    }
+
+    #[test]
+    fn unknown_commands_are_told_apart() {
+        assert!(is_unknown_command("/pushups 20"));
+        assert!(!is_unknown_command("/status"));
+        assert!(!is_unknown_command("/add 20"));
+        assert!(!is_unknown_command("20"));
+    }
+
+    #[tokio::test]
+    async fn reloaded_debounce_reaches_running_chats() {
+        let (contexts, _, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        apply_config(&contexts, Config::default(), "config.toml").await;
+        assert!(rx.try_recv().is_err());
+
+        let config = Config {
+            edit_debounce_secs: 5,
+            ..Config::default()
+        };
+        apply_config(&contexts, config, "config.toml").await;
+
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(ContextCommand::SetEditDebounce(debounce)) if debounce == time::Duration::from_secs(5)
+        ));
+        assert_eq!(contexts.lock().unwrap().config.edit_debounce_secs, 5);
+    }
+
+    #[tokio::test]
+    async fn only_the_owner_can_list_challenges() {
+        let (contexts, api, client) = setup();
+        contexts.lock().unwrap().owner_id = Some(7);
+        process_update(text_update(-100, 8, "/start"), &contexts, &api).await;
+        tokio::task::yield_now().await;
+        let sent = client.sent_messages().len();
+
+        process_update(text_update(-100, 8, "/admin_list"), &contexts, &api).await;
+        assert_eq!(client.sent_messages().len(), sent);
+
+        process_update(text_update(5, 7, "/admin_list"), &contexts, &api).await;
+        assert_eq!(
+            client.sent_messages().last().unwrap(),
+            "Тренировки (1):\n-100 #0 отжиманий: день 1 из 35, участников: 0\n"
+        );
+    }
+
+    fn bot_status_update(chat_id: i64, status: ChatMember) -> Update {
+        let chat = ChatBuilder::default()
+            .id(chat_id)
+            .type_field(ChatType::Group)
+            .build()
+            .unwrap();
+        let admin = UserBuilder::default()
+            .id(7_u64)
+            .is_bot(false)
+            .first_name("alice")
+            .build()
+            .unwrap();
+        let member = ChatMemberUpdatedBuilder::default()
+            .chat(chat)
+            .from(admin)
+            .date(0_u64)
+            .old_chat_member(ChatMember::Member(ChatMemberMember { user: bot() }))
+            .new_chat_member(status)
+            .build()
+            .unwrap();
+
+        UpdateBuilder::default()
+            .update_id(NEXT_UPDATE_ID.fetch_add(1, Ordering::Relaxed))
+            .my_chat_member(member)
+            .build()
+            .unwrap()
+    }
+
+    fn bot() -> User {
+        UserBuilder::default()
+            .id(1_u64)
+            .is_bot(true)
+            .first_name("bot")
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn kicked_bot_forgets_the_chat() {
+        let (contexts, api, client) = setup();
+        let store = Arc::clone(&contexts.lock().unwrap().store);
+        process_update(text_update(-100, 7, "/start"), &contexts, &api).await;
+        process_update(text_update(-200, 7, "/start"), &contexts, &api).await;
+        tokio::task::yield_now().await;
+
+        let promoted = ChatMember::Member(ChatMemberMember { user: bot() });
+        process_update(bot_status_update(-100, promoted), &contexts, &api).await;
+        assert_eq!(contexts.lock().unwrap().txs.len(), 2);
+        assert_eq!(store.load_all(api.clone()).len(), 2);
+
+        let sent = client.calls().len();
+        let kicked = ChatMember::Banned(ChatMemberBanned {
+            user: bot(),
+            until_date: 0,
+        });
+        process_update(bot_status_update(-100, kicked), &contexts, &api).await;
+
+        assert!(contexts.lock().unwrap().challenges(-100).is_empty());
+        assert_eq!(contexts.lock().unwrap().challenges(-200).len(), 1);
+
+        let deleted = async {
+            while store.load_all(api.clone()).len() > 1 {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(1), deleted)
+            .await
+            .expect("the challenge is still saved");
+        assert_eq!(client.calls().len(), sent);
+    }
+
+    async fn start_in(text: &str) -> bool {
+        let (contexts, api, _) = setup();
+        contexts.lock().unwrap().bot_username = Some("MyWorkoutBot".to_string());
+
+        process_update(text_update(1, 7, text), &contexts, &api).await;
+
+        let started = !contexts.lock().unwrap().challenges(1).is_empty();
+        started
+    }
+
+    #[tokio::test]
+    async fn start_works_with_and_without_the_bot_username() {
+        assert!(start_in("/start").await);
+        assert!(start_in("/start@MyWorkoutBot").await);
+        assert!(start_in("/start@myworkoutbot").await);
+        assert!(!start_in("/start@OtherBot").await);
+    }
+
+    #[test]
+    fn bot_username_is_stripped_from_commands() {
+        let bot = Some("MyWorkoutBot");
+
+        assert_eq!(
+            strip_bot_username("/add@MyWorkoutBot 20", bot).as_deref(),
+            Some("/add 20")
+        );
+        assert_eq!(strip_bot_username("/add@OtherBot 20", bot), None);
+        assert_eq!(
+            strip_bot_username("/add@OtherBot 20", None).as_deref(),
+            Some("/add 20")
+        );
+        assert_eq!(
+            strip_bot_username("20 for @friend", bot).as_deref(),
+            Some("20 for @friend")
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_command_points_to_help() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        process_update(text_update(1, 7, "/pushups"), &contexts, &api).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::UnknownCommand)
+        ));
+
+        process_update(text_update(1, 7, "/help"), &contexts, &api).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::Greet { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn new_chats_get_help_too() {
+        let (contexts, api, client) = setup();
+
+        process_update(text_update(1, 7, "/help"), &contexts, &api).await;
+        process_update(text_update(1, 7, "/pushups"), &contexts, &api).await;
+        process_update(text_update(1, 7, "20"), &contexts, &api).await;
+
+        let sent = client.sent_messages();
+        assert_eq!(sent.len(), 2);
+        assert!(COMMANDS
+            .iter()
+            .all(|command| sent[0].contains(&format!("{},", command))
+                || sent[0].contains(&format!("{}.", command))));
+        assert_eq!(sent[1], "Неизвестная команда, попробуйте /help");
+        assert!(contexts.lock().unwrap().txs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn new_without_an_exercise_explains_itself() {
+        let (contexts, api, client) = setup();
+
+        process_update(text_update(1, 7, "/new"), &contexts, &api).await;
+        process_update(text_update(1, 7, "/new   "), &contexts, &api).await;
+
+        assert_eq!(
+            client.sent_messages(),
+            vec!["Укажите, что считать: /new приседаний".to_string(); 2]
+        );
+        assert!(contexts.lock().unwrap().txs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn updates_go_through_after_a_panic_under_the_lock() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        let poisoner = Arc::clone(&contexts);
+        let _ = std::thread::spawn(move || {
+            let _locked = poisoner.lock().unwrap();
+            panic!("handler bug");
+        })
+        .join();
+        assert!(contexts.is_poisoned());
+
+        process_update(text_update(1, 7, "25"), &contexts, &api).await;
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::AddPushups { count: 25, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn edited_logs_correct_the_count() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        let edited = |text: &str| {
+            let mut update = text_update(1, 7, text);
+            update.edited_message = update.message.take();
+            update
+        };
+
+        process_update(edited("15"), &contexts, &api).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::EditPushups {
+                message_id: 1,
+                count: 15,
+                ..
+            })
+        ));
+
+        process_update(edited("oops"), &contexts, &api).await;
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::EditPushups { count: 0, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn repeated_updates_are_handled_once() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        let update = text_update(1, 7, "сделал 25");
+        process_update(update.clone(), &contexts, &api).await;
+        process_update(update, &contexts, &api).await;
+
+        assert!(matches!(
+            rx.recv().await,
+            Some(ContextCommand::AddPushups { count: 25, .. })
+        ));
+        let repeated = tokio::time::timeout(std::time::Duration::from_millis(50), rx.recv()).await;
+        assert!(repeated.is_err(), "unexpected command: {:?}", repeated);
+    }
+
+    #[tokio::test]
+    async fn channel_posts_are_skipped() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        let mut update = text_update(1, 7, "25");
+        update.message.as_mut().unwrap().chat.type_field = ChatType::Channel;
+        process_update(update, &contexts, &api).await;
+
+        let command = tokio::time::timeout(std::time::Duration::from_millis(50), rx.recv()).await;
+        assert!(command.is_err(), "unexpected command: {:?}", command);
+    }
+
+    #[tokio::test]
+    async fn updates_are_dispatched_to_their_chat() {
+        let (contexts, api, _) = setup();
+        let mut rx = register_channel(&contexts);
+
+        process_update(text_update(1, 7, "сделал 25"), &contexts, &api).await;
+
+        match rx.recv().await {
+            Some(ContextCommand::AddPushups {
+                participant, count, ..
+            }) => {
+                assert_eq!(participant.id, 7);
+                assert_eq!(count, 25);
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        process_update(text_update(1, 7, "/status"), &contexts, &api).await;
+        assert!(matches!(rx.recv().await, Some(ContextCommand::ShowStatus)));
+
+        // Chats without a challenge are ignored.
+        process_update(text_update(2, 7, "25"), &contexts, &api).await;
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn count_is_found_in_free_text() {
+        assert_eq!(parse_count("+10"), Some(10));
+        assert_eq!(parse_count("10"), Some(10));
+        assert_eq!(parse_count("я сделал 10 раз"), Some(10));
+        assert_eq!(parse_count("сделал 15!"), Some(15));
+        assert_eq!(parse_count("сделал 10раз"), None);
+        assert_eq!(parse_count("/status"), None);
+        assert_eq!(parse_count("/give @bob 10"), None);
+    }
+
+    #[test]
+    fn add_takes_nothing_but_reps() {
+        assert_eq!(parse_add("/add 20"), Some((20, 1.0)));
+        assert_eq!(parse_add("/add@workout_bot  20 "), Some((20, 1.0)));
+        assert_eq!(parse_add("/add 20 x1.5"), Some((20, 1.5)));
+        assert_eq!(parse_add("/add 10 15 20"), Some((45, 1.0)));
+        assert_eq!(parse_add("/add -5"), Some((-5, 1.0)));
+        assert_eq!(parse_add("/add"), None);
+        assert_eq!(parse_add("/add abc"), None);
+        assert_eq!(parse_add("/add 20 squats"), None);
+
+        assert!(is_command("/add@workout_bot 20", "/add"));
+        assert!(!is_command("/address 20", "/add"));
+    }
+
+    #[test]
+    fn sets_are_summed() {
+        assert_eq!(parse_reps("10 15 20"), Some((45, 1.0)));
+        assert_eq!(parse_reps("10 apples 20"), None);
+        assert_eq!(parse_reps("10 -5"), None);
+        assert_eq!(parse_reps("   "), None);
+        assert_eq!(parse_reps("20"), Some((20, 1.0)));
+        assert_eq!(parse_reps("-5"), Some((-5, 1.0)));
+        assert_eq!(parse_reps("20 x1.5"), Some((20, 1.5)));
+        assert_eq!(parse_reps("сделал 15"), Some((15, 1.0)));
+    }
+
+    #[test]
+    fn schedule_is_parsed_with_an_optional_ending() {
+        assert_eq!(
+            parse_schedule("20 25 30 40"),
+            Some((Some(vec![20, 25, 30, 40]), ScheduleEnd::Hold))
+        );
+        assert_eq!(
+            parse_schedule("20 25 finish"),
+            Some((Some(vec![20, 25]), ScheduleEnd::Finish))
+        );
+        assert_eq!(parse_schedule("off"), Some((None, ScheduleEnd::Hold)));
+        assert_eq!(parse_schedule("20 0 30"), None);
+        assert_eq!(parse_schedule("finish"), None);
+    }
+
+    #[test]
+    fn user_target_is_parsed() {
+        assert_eq!(
+            parse_user_target("@bob 50"),
+            Some(("bob".to_string(), Some(50)))
+        );
+        assert_eq!(
+            parse_user_target("bob off"),
+            Some(("bob".to_string(), None))
+        );
+        assert_eq!(parse_user_target("@bob 0"), None);
+        assert_eq!(parse_user_target("@bob"), None);
+    }
+
+    #[test]
+    fn backoff_grows_until_cap() {
+        let second = core::time::Duration::from_secs(1);
+        let delays: Vec<u64> = [0, 1, 2, 5, 6, 40]
+            .iter()
+            .map(|failures| backoff_delay(second, *failures).as_secs())
+            .collect();
+
+        assert_eq!(delays, vec![1, 2, 4, 32, 60, 60]);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_starts_create_one_context() {
+        let (contexts, api, _) = setup();
+
+        let starts: Vec<_> = (0..2)
+            .map(|_| {
+                let contexts = Arc::clone(&contexts);
+                let api = api.clone();
+                tokio::spawn(async move { init_context(&contexts, 1, api, None) })
+            })
+            .collect();
+
+        let mut started = 0;
+        for start in starts {
+            if start.await.unwrap() {
+                started += 1;
+            }
+        }
+
+        assert_eq!(started, 1);
+        assert_eq!(contexts.lock().unwrap().txs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn stopped_challenges_leave_the_registry() {
+        let (contexts, api, _) = setup();
+
+        assert!(init_context(&contexts, 1, api, None));
+        let (_, tx) = contexts.lock().unwrap().challenges(1).remove(0);
+        send(&tx, ContextCommand::Stop).await;
+
+        let removed = async {
+            while !contexts.lock().unwrap().txs.is_empty() {
+                tokio::task::yield_now().await;
+            }
+        };
+        tokio::time::timeout(std::time::Duration::from_secs(1), removed)
+            .await
+            .expect("the stopped challenge is still registered");
+    }
+
+    #[tokio::test]
+    async fn replies_go_to_the_challenge_they_answer() {
+        let client = Arc::new(MockClient::default());
+        let mut challenges = vec![];
+
+        for challenge_id in 0..2 {
+            let mut context_data = ContextData::new(client.clone(), 1, &Config::default());
+            context_data.state.challenge_id = challenge_id;
+            let (tx, _) = spawn_handler(context_data);
+            send(&tx, ContextCommand::SendDailyMessage).await;
+            challenges.push((challenge_id, tx));
+        }
+
+        // The mock numbers messages by call: the first daily message is 1, its
+        // pin is 2 and the second challenge's daily message is 3.
+        let second = challenges[1].1.clone();
+
+        assert!(route_message(challenges.clone(), Some(3))
+            .await
+            .same_channel(&second));
+        assert!(!route_message(challenges.clone(), Some(1))
+            .await
+            .same_channel(&second));
+        assert!(!route_message(challenges, None).await.same_channel(&second));
+    }
+
+    #[tokio::test]
+    async fn celebrations_are_sent_once_per_day() {
+        let client = Arc::new(MockClient::default());
+        let (tx, _) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 100)).await;
+        send(&tx, add(1, "alice", 10)).await;
+        send(&tx, add(1, "alice", 5)).await;
+
+        let count = |prefix: &str| {
+            client
+                .sent_messages()
+                .iter()
+                .filter(|text| text.starts_with(prefix))
+                .count()
+        };
+        assert_eq!(count("🥳"), 1);
+        assert_eq!(count("На сегодня всё"), 1);
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 100)).await;
+
+        assert_eq!(count("🥳"), 2);
+        assert_eq!(count("На сегодня всё"), 2);
+    }
+
+    #[tokio::test]
+    async fn stop_ends_the_challenge_once() {
+        let client = Arc::new(MockClient::default());
+        let (tx, handler) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 20)).await;
+        send(&tx, ContextCommand::Stop).await;
+        handler.await.unwrap();
+
+        assert!(tx.send(ContextCommand::Stop).await.is_err());
+
+        let messages = client.sent_messages();
+        assert!(messages.last().unwrap().starts_with(
+            "Тренировка окончена! Мы прозанимались 1 дней и сделали 20 отжиманий на всех.\n"
+        ));
+        assert!(client.calls().contains(&Call::Unpin(1)));
+    }
+
+    #[tokio::test]
+    async fn stop_waits_for_the_issuer_or_an_admin_to_confirm() {
+        let client = Arc::new(MockClient {
+            admins: vec![10],
+            ..MockClient::default()
+        });
+        let (tx, handler) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+        let answer = |user_id, message_id, confirmed| ContextCommand::ConfirmationAnswer {
+            query_id: "query".to_string(),
+            user_id,
+            message_id,
+            confirmed,
+        };
+        let stop = || ContextCommand::Confirm {
+            action: Destructive::Stop,
+            user_id: 20,
+        };
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, stop()).await;
+        let prompt = client.calls().len() as i32;
+        assert_eq!(
+            client.sent_messages().last().unwrap(),
+            "Завершить тренировку сейчас? Итоги подведём по сегодняшний день"
+        );
+
+        send(&tx, answer(30, prompt, true)).await;
+        assert_eq!(
+            client.calls().last(),
+            Some(&Call::Answer(Some(
+                "Ответить может тот, кто отправил команду, или администратор".to_string()
+            )))
+        );
+
+        send(&tx, answer(20, prompt, false)).await;
+        assert_eq!(
+            client.calls().last(),
+            Some(&Call::Edit(prompt, "Отменено".to_string()))
+        );
+        assert!(!tx.is_closed());
+
+        send(&tx, stop()).await;
+        let prompt = client.calls().len() as i32;
+        send(&tx, answer(10, prompt, true)).await;
+        handler.await.unwrap();
+
+        assert!(client
+            .sent_messages()
+            .last()
+            .unwrap()
+            .starts_with("Тренировка окончена!"));
+    }
+
+    #[tokio::test]
+    async fn confirmations_follow_the_chat_language() {
+        let client = Arc::new(MockClient::default());
+        let mut context_data = ContextData::new(client.clone(), 1, &Config::default());
+        context_data.state.locale = Locale::En;
+        let (tx, _) = spawn_handler(context_data);
+
+        send(
+            &tx,
+            ContextCommand::Confirm {
+                action: Destructive::Reset,
+                user_id: 20,
+            },
+        )
+        .await;
+        let prompt = client.calls().len() as i32;
+        assert_eq!(
+            client.sent_messages().last().unwrap(),
+            "Start over from day one? All reps will be deleted"
+        );
+
+        send(
+            &tx,
+            ContextCommand::ConfirmationAnswer {
+                query_id: "query".to_string(),
+                user_id: 20,
+                message_id: prompt,
+                confirmed: false,
+            },
+        )
+        .await;
+        assert_eq!(
+            client.calls().last(),
+            Some(&Call::Edit(prompt, "Cancelled".to_string()))
+        );
+    }
+
+    #[test]
+    fn confirmation_buttons_are_parsed() {
+        assert_eq!(parse_confirmation("confirm:2"), Some((true, 2)));
+        assert_eq!(parse_confirmation("cancel:0"), Some((false, 0)));
+        assert_eq!(parse_confirmation("confirm"), None);
+        assert_eq!(parse_confirmation("delete:1"), None);
+    }
+
+    #[tokio::test]
+    async fn reaching_the_goal_ends_the_challenge() {
+        let client = Arc::new(MockClient::default());
+        let (tx, handler) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, ContextCommand::SetGoal { target: Some(150) }).await;
+        send(&tx, add(1, "alice", 100)).await;
+        send(&tx, add(2, "bob", 60)).await;
+        handler.await.unwrap();
+
+        let messages = client.sent_messages();
+        assert!(!messages.iter().any(|text| text.starts_with("🥳")));
+        assert!(messages.last().unwrap().starts_with(
+            "Тренировка окончена! Мы прозанимались 1 дней и сделали 160 отжиманий на всех.\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn export_sends_the_progress_as_a_file() {
+        let client = Arc::new(MockClient::default());
+        let (tx, _) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 20)).await;
+        send(&tx, ContextCommand::Export).await;
+
+        assert_eq!(
+            client.calls().last(),
+            Some(&Call::Document("day,name,count\n1,alice,20\n".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn configuration_is_left_to_admins() {
+        let client = Arc::new(MockClient {
+            admins: vec![1],
+            ..MockClient::default()
+        });
+        let (tx, handler) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+        let set_duration = |user_id, days| ContextCommand::AdminOnly {
+            user_id,
+            command: Box::new(ContextCommand::SetDuration { days }),
+        };
+
+        send(&tx, set_duration(2, 10)).await;
+        send(&tx, set_duration(1, 20)).await;
+        drop(tx);
+        handler.await.unwrap();
+
+        assert_eq!(
+            client.sent_messages(),
+            vec![
+                "Эту команду могут выполнять только администраторы".to_string(),
+                "Длительность тренировки: 20 дней".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn replies_follow_the_chat_language() {
+        let client = Arc::new(MockClient::default());
+        let mut context_data = ContextData::new(client.clone(), 1, &Config::default());
+        context_data.state.locale = Locale::En;
+        let (tx, handler) = spawn_handler(context_data);
+
+        send(
+            &tx,
+            ContextCommand::AdminOnly {
+                user_id: 2,
+                command: Box::new(ContextCommand::SetDuration { days: 10 }),
+            },
+        )
+        .await;
+        send(&tx, ContextCommand::SetGifts { enabled: false }).await;
+        send(&tx, ContextCommand::UnknownCommand).await;
+        drop(tx);
+        handler.await.unwrap();
+
+        assert_eq!(
+            client.sent_messages(),
+            vec![
+                "Only admins can use this command".to_string(),
+                "Giving reps away is off".to_string(),
+                "Unknown command, try /help".to_string(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn the_paused_day_stays_as_it_was() {
+        let client = Arc::new(MockClient::default());
+        let (tx, handler) = spawn_handler(ContextData::new(client.clone(), 1, &Config::default()));
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 10)).await;
+        send(&tx, add(2, "bob", 10)).await;
+        send(&tx, ContextCommand::SetPaused { paused: true }).await;
+        send(&tx, add(1, "alice", 20)).await;
+        send(&tx, ContextCommand::Undo).await;
+        send(
+            &tx,
+            ContextCommand::GiveReps {
+                from: Participant {
+                    id: 1,
+                    display_name: "alice".to_string(),
+                },
+                to: "bob".to_string(),
+                count: 5,
+            },
+        )
+        .await;
+        send(&tx, ContextCommand::ShowMe { user_id: 1 }).await;
+        drop(tx);
+        handler.await.unwrap();
+
+        let messages = client.sent_messages();
+        let refusals = &messages[messages.len() - 4..messages.len() - 1];
+        assert!(refusals.iter().all(|text| text.starts_with("⏸ На паузе")));
+        assert!(messages.last().unwrap().contains("всего 10"));
+    }
+
+    #[tokio::test]
+    async fn reps_are_refused_on_a_rest_day() {
+        let client = Arc::new(MockClient::default());
+        let mut context_data = ContextData::new(client.clone(), 1, &Config::default());
+        context_data.state.started = true;
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+        context_data.state.add_user_progress(alice, 10);
+        context_data.state.resting = true;
+        let (tx, handler) = spawn_handler(context_data);
+
+        send(&tx, add(1, "alice", 20)).await;
+        send(&tx, ContextCommand::ShowMe { user_id: 1 }).await;
+        drop(tx);
+        handler.await.unwrap();
+
+        // The day before the rest day keeps what it had.
+        let messages = client.sent_messages();
+        assert!(messages[0].starts_with("🛌 День отдыха"));
+        assert!(messages[1].contains("всего 10"), "{}", messages[1]);
+    }
+
+    #[tokio::test]
+    async fn restart_catches_up_on_missed_days() {
+        let (contexts, api, client) = setup();
+        let store = Arc::clone(&contexts.lock().unwrap().store);
+
+        // Went down during day 3, on 1 March, and came back two resets later.
+        let mut context_data = ContextData::new(api.clone(), 1, &Config::default());
+        context_data.clock = Arc::new(MockClock::new(Utc.ymd(2022, 3, 3).and_hms(8, 0, 0)));
+        context_data.state.started = true;
+        context_data.state.current_day = 2;
+        context_data.state.next_reset = Some(Utc.ymd(2022, 3, 2).and_hms(0, 0, 0));
+        spawn_context(&contexts, context_data);
+
+        // The last missed reset is overdue and goes through at once.
+        time::timeout(core::time::Duration::from_secs(1), async {
+            while client.sent_messages().is_empty() {
+                time::sleep(core::time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        shut_down_contexts(&contexts).await;
+
+        let restored = store.load_all(api);
+        assert_eq!(restored[0].state.current_day, 4);
+        assert_eq!(
+            restored[0].state.next_reset,
+            Some(Utc.ymd(2022, 3, 4).and_hms(0, 0, 0))
+        );
+        assert_eq!(
+            client.sent_messages().last().unwrap(),
+            "День 5 из 35. 100 повторений (отжиманий)\n📈 ▁▁▁▁▁\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn full_challenge_lifecycle() {
+        let client = Arc::new(MockClient::default());
+        let clock = Arc::new(MockClock::new(Utc.ymd(2022, 3, 1).and_hms(8, 0, 0)));
+        let config = Config {
+            duration: 3,
+            repeats: 10,
+            cycle_length: 1,
+            cycle_increase: 5,
+            min_repeats: 1,
+            edit_debounce_secs: 0,
+            ..Config::default()
+        };
+
+        let mut context_data = ContextData::new(client.clone(), 1, &config);
+        context_data.clock = clock.clone();
+
+        let (tx, handler) = spawn_handler(context_data);
+
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 10)).await;
+        clock.advance(Duration::hours(1));
+        send(&tx, add(2, "bob", 12)).await;
+
+        clock.advance(Duration::days(1));
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        clock.advance(Duration::hours(2));
+        send(&tx, add(2, "bob", 15)).await;
+
+        clock.advance(Duration::days(1));
+        send(&tx, ContextCommand::SendDailyMessage).await;
+        send(&tx, add(1, "alice", 5)).await;
+        send(&tx, ContextCommand::ShowWinners).await;
+        send(&tx, ContextCommand::SendDailyMessage).await;
+
+        handler.await.unwrap();
+        assert!(tx.is_closed());
+
+        let messages = client.sent_messages();
+        assert_eq!(messages[0], "День 1 из 3. 10 повторений (отжиманий)\n");
+        assert!(messages.contains(
+            &"На сегодня всё 🎉 Норма отжиманий выполнена всеми\n🏆 Быстрее всех: alice"
+                .to_string()
+        ));
+        assert!(messages
+            .contains(&"Очередной цикл завершён! Увеличиваем повторения с 10 до 15.".to_string()));
+        assert!(messages
+            .contains(&"Очередной цикл завершён! Увеличиваем повторения с 15 до 20.".to_string()));
+        assert!(messages.contains(
+            &"Первыми норму выполнили:\nДень 1: alice (08:00 UTC)\nДень 2: bob (11:00 UTC)\nДень 3: никто\n"
+                .to_string()
+        ));
+
+        let final_message = messages.last().unwrap();
+        assert!(final_message.starts_with(
+            "Тренировка окончена! Мы прозанимались 3 дней и сделали 42 отжиманий на всех.\n"
+        ));
+        assert!(final_message.contains("alice: 15 (норма 1 из 3 дней)\n"));
+        assert!(final_message.contains("bob: 27 (норма 2 из 3 дней)\n"));
+
+        let calls = client.calls();
+        assert!(calls.contains(&Call::Pin(1)));
+        // Logs edit the pinned daily message in place.
+        assert!(calls.contains(&Call::Edit(
+            1,
+            "alice: 10/10 🏆 🔥1\nbob: 12/10 ✅ 🔥1\nДень 1 из 3. 10 повторений (отжиманий)\n"
+                .to_string()
+        )));
+
+        let last_daily_message_id = calls
+            .iter()
+            .rev()
+            .find_map(|call| match call {
+                Call::Pin(message_id) => Some(*message_id),
+                _ => None,
+            })
+            .unwrap();
+        assert!(calls.contains(&Call::Unpin(last_daily_message_id)));
+    }
 }
@@ -0,0 +1,1052 @@
+//! The text of the challenge's messages in every supported language. Each
+//! `Text` is one message or line, with its values; `format` renders it.
+
+use chrono::Weekday;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Locale {
+    #[default]
+    Ru,
+    En,
+}
+
+impl Locale {
+    /// Reads a `/lang` argument.
+    pub fn parse(code: &str) -> Option<Self> {
+        match code.trim() {
+            "ru" => Some(Locale::Ru),
+            "en" => Some(Locale::En),
+            _ => None,
+        }
+    }
+}
+
+pub enum Text<'a> {
+    Greeting {
+        exercise: &'a str,
+        repeats: usize,
+        duration: usize,
+        /// Every command, comma separated.
+        commands: &'a str,
+    },
+    /// What a new challenge counts, in the genitive plural in Russian.
+    DefaultExercise,
+    DailyFooter {
+        day: usize,
+        duration: usize,
+        repeats: usize,
+        exercise: &'a str,
+    },
+    GoalUserLine {
+        name: &'a str,
+        rank_mark: &'a str,
+        total: usize,
+        today: usize,
+    },
+    GoalFooter {
+        day: usize,
+        duration: usize,
+        total: usize,
+        target: usize,
+        exercise: &'a str,
+        bar: &'a str,
+    },
+    DeltaToYesterday(i64),
+    RawCountNote(usize),
+    WorkoutOver {
+        days: usize,
+        total: usize,
+        exercise: &'a str,
+    },
+    FinalUserLine {
+        name: &'a str,
+        count: usize,
+        raw_note: &'a str,
+        days_met: usize,
+        days: usize,
+    },
+    DaysHeader,
+    /// `target` is left out in total-goal mode, where days have none.
+    FinalDayLine {
+        day: usize,
+        total: usize,
+        target: Option<usize>,
+    },
+    BestDaysHeader,
+    UserBestDay {
+        name: &'a str,
+        day: usize,
+        count: usize,
+    },
+    GroupBestDay {
+        day: usize,
+        total: usize,
+        exercise: &'a str,
+    },
+    GiftsHeader,
+    GiftLine {
+        day: usize,
+        from: &'a str,
+        to: &'a str,
+        count: usize,
+    },
+    NotesHeader,
+    DayNote {
+        day: usize,
+        note: &'a str,
+    },
+    WinnersHeader,
+    DayWinner {
+        day: usize,
+        name: &'a str,
+        time: &'a str,
+    },
+    DayWithoutWinner(usize),
+    NoFinishedDays,
+    HistoryHeader,
+    MoreDays(usize),
+    HistoryLine {
+        day: usize,
+        total: usize,
+        exercise: &'a str,
+        mark: &'a str,
+    },
+    NobodyYet,
+    LeaderboardHeader,
+    DailyTopHeader(usize),
+    NobodyOnDay(usize),
+    NoSuchDay(usize),
+    ConfigHeader,
+    ConfigExercise(&'a str),
+    ConfigDuration(usize),
+    ConfigToday(usize),
+    ConfigRepeats(usize),
+    ConfigGoal(usize),
+    ConfigCycleLength(usize),
+    ConfigNextCycleLength {
+        current: usize,
+        next: usize,
+    },
+    ConfigSchedule {
+        targets: &'a str,
+        finish: bool,
+    },
+    ConfigCycleIncrease(isize),
+    ConfigMinRepeats(usize),
+    ConfigReset {
+        hour: u32,
+        timezone: &'a str,
+    },
+    ConfigReminder(u32),
+    ConfigNotify,
+    ConfigPaused,
+    ConfigRestDay(Weekday),
+    ConfigPauseExtends(bool),
+    ConfigUsers(usize),
+    ReminderEntry {
+        name: &'a str,
+        count: usize,
+        target: usize,
+    },
+    Reminder(&'a str),
+    /// Heads the daily message while the challenge is paused.
+    Paused,
+    UserDone {
+        name: &'a str,
+        exercise: &'a str,
+    },
+    AllDone(&'a str),
+    MeStats {
+        name: &'a str,
+        today: usize,
+        target: usize,
+        total: usize,
+        streak: usize,
+        place: usize,
+        participants: usize,
+    },
+    /// `/me` from someone who hasn't logged yet.
+    MeNotJoined(&'a str),
+    Stats {
+        days: usize,
+        total: usize,
+        exercise: &'a str,
+        average: f64,
+        /// In percent.
+        participation: f64,
+    },
+    MostConsistent {
+        name: &'a str,
+        days: usize,
+    },
+    /// Follows `AllDone`, crowning who got there first.
+    FirstFinisher(&'a str),
+    CycleUp {
+        from: usize,
+        to: usize,
+    },
+    CycleDown {
+        from: usize,
+        to: usize,
+    },
+    AtMinimum,
+    LocaleSet,
+    /// `None` when rest days were turned off.
+    RestDaySet(Option<Weekday>),
+    /// Heads the daily message on the rest day.
+    RestDay,
+    TimeLeft {
+        hours: i64,
+        minutes: i64,
+    },
+    AdminOnly,
+    UnknownCommand,
+    /// Privacy mode keeps plain messages from the bot.
+    CantSeeMessages,
+    NoteSaved(usize),
+    Joined(&'a str),
+    AlreadyJoined(&'a str),
+    Left(&'a str),
+    AlreadyLeft(&'a str),
+    Undone {
+        name: &'a str,
+        count: isize,
+    },
+    NothingToUndo,
+    Restarted,
+    NotJoinedYet(&'a str),
+    GiftsDisabled,
+    GiftToSelf,
+    NothingToGive,
+    GiftTooLarge(usize),
+    Gift {
+        from: &'a str,
+        to: &'a str,
+        count: usize,
+    },
+    GiftsSet(bool),
+    StrictInputSet(bool),
+    NotificationsSet(bool),
+    /// `None` when the user went back to the common target.
+    UserTargetSet {
+        name: &'a str,
+        target: Option<usize>,
+    },
+    /// `/new` without the exercise.
+    NewUsage,
+    ExerciseLength(usize),
+    ExerciseSet(&'a str),
+    CycleLengthRange(usize),
+    CycleLengthSet(usize),
+    NextCycleLengthSet(usize),
+    CycleIncreaseSet(isize),
+    StartRepeatsZero,
+    StartRepeatsLate,
+    StartRepeatsScheduled,
+    StartRepeatsSet {
+        repeats: usize,
+        increase: isize,
+        cycle_length: usize,
+    },
+    GoalZero,
+    GoalSet {
+        target: usize,
+        duration: usize,
+    },
+    DailyTargets,
+    CycleTargets,
+    ScheduleSet {
+        targets: &'a str,
+        finish: bool,
+    },
+    DurationRange(usize),
+    DurationSet(usize),
+    ResetTimeSet {
+        hour: u32,
+        timezone: &'a str,
+    },
+    /// `None` when reminders were turned off.
+    ReminderSet {
+        hour: Option<u32>,
+        timezone: &'a str,
+    },
+    NoDeleteRights,
+    CleanChatSet(bool),
+    /// A line of `/admin_list`.
+    Description {
+        chat_id: i64,
+        challenge_id: u32,
+        exercise: &'a str,
+        day: usize,
+        duration: usize,
+        users: usize,
+    },
+    NoChallenges,
+    ChallengesHeader(usize),
+    ConfirmStop,
+    ConfirmReset,
+    ConfirmButton,
+    CancelButton,
+    /// A button of a confirmation that was replaced or already answered.
+    ConfirmationGone,
+    ConfirmationExpired,
+    NotYourConfirmation,
+    Confirmed,
+    Cancelled,
+    Nudge,
+    /// `true` once paused, `false` once resumed.
+    PauseSet(bool),
+    /// `/pause` or `/resume` when the challenge already is that way.
+    PauseUnchanged(bool),
+    PauseExtendsSet(bool),
+    UserRemoved(&'a str),
+    NotInWorkout(&'a str),
+    NotStarted,
+    DayRange(usize),
+    DaySet {
+        day: usize,
+        duration: usize,
+    },
+}
+
+impl Text<'_> {
+    pub fn format(&self, locale: Locale) -> String {
+        match locale {
+            Locale::Ru => self.ru(),
+            Locale::En => self.en(),
+        }
+    }
+
+    fn ru(&self) -> String {
+        match *self {
+            Text::Greeting {
+                exercise,
+                repeats,
+                duration,
+                commands,
+            } => format!(
+                "👋 Привет! Считаю {} в этом чате.
+
+Чтобы записать подход, отправь число, например 20, или несколько через пробел: 10 15 20. Ошибся — отправь число с минусом или /undo.
+
+Норма на сегодня: {}, тренировка продлится {} дней.
+
+Команды: {}.",
+                exercise, repeats, duration, commands
+            ),
+            Text::DefaultExercise => "отжиманий".to_string(),
+            Text::DailyFooter {
+                day,
+                duration,
+                repeats,
+                exercise,
+            } => format!(
+                "День {} из {}. {} повторений ({})\n",
+                day, duration, repeats, exercise
+            ),
+            Text::GoalUserLine {
+                name,
+                rank_mark,
+                total,
+                today,
+            } => format!("{}{}: {} (сегодня {})\n", name, rank_mark, total, today),
+            Text::GoalFooter {
+                day,
+                duration,
+                total,
+                target,
+                exercise,
+                bar,
+            } => format!(
+                "День {} из {}. Цель: {} из {} {} {}\n",
+                day, duration, total, target, exercise, bar
+            ),
+            Text::DeltaToYesterday(delta) => format!(" ({:+} к вчера)", delta),
+            Text::RawCountNote(raw_count) => format!(" ({} без учёта сложности)", raw_count),
+            Text::WorkoutOver {
+                days,
+                total,
+                exercise,
+            } => format!(
+                "Тренировка окончена! Мы прозанимались {} дней и сделали {} {} на всех.\n",
+                days, total, exercise
+            ),
+            Text::FinalUserLine {
+                name,
+                count,
+                raw_note,
+                days_met,
+                days,
+            } => format!(
+                "{}: {}{} (норма {} из {} дней)\n",
+                name, count, raw_note, days_met, days
+            ),
+            Text::DaysHeader => "\nПо дням:\n".to_string(),
+            Text::FinalDayLine { day, total, target } => match target {
+                Some(target) => format!("День {}: {}, норма {}\n", day, total, target),
+                None => format!("День {}: {}\n", day, total),
+            },
+            Text::BestDaysHeader => "\nЛучшие дни:\n".to_string(),
+            Text::UserBestDay { name, day, count } => {
+                format!("{}: день {}, {}\n", name, day, count)
+            }
+            Text::GroupBestDay {
+                day,
+                total,
+                exercise,
+            } => format!("Вся группа: день {}, {} {}\n", day, total, exercise),
+            Text::GiftsHeader => "\nПодарки:\n".to_string(),
+            Text::GiftLine {
+                day,
+                from,
+                to,
+                count,
+            } => format!("День {}: {} → {}: {}\n", day, from, to, count),
+            Text::NotesHeader => "\nЗаметки:\n".to_string(),
+            Text::DayNote { day, note } => format!("День {}: {}\n", day, note),
+            Text::WinnersHeader => "Первыми норму выполнили:\n".to_string(),
+            Text::DayWinner { day, name, time } => {
+                format!("День {}: {} ({})\n", day, name, time)
+            }
+            Text::DayWithoutWinner(day) => format!("День {}: никто\n", day),
+            Text::NoFinishedDays => "Пока нет завершённых дней".to_string(),
+            Text::HistoryHeader => "История:\n".to_string(),
+            Text::MoreDays(days) => format!("...и ещё {} дней\n", days),
+            Text::HistoryLine {
+                day,
+                total,
+                exercise,
+                mark,
+            } => format!("День {}: {} {} {}", day, total, exercise, mark),
+            Text::NobodyYet => "Пока никто не участвует".to_string(),
+            Text::LeaderboardHeader => "Общий зачёт:\n".to_string(),
+            Text::DailyTopHeader(day) => format!("Лучшие за день {}:\n", day),
+            Text::NobodyOnDay(day) => format!("За день {} пока никто не занимался", day),
+            Text::NoSuchDay(days) => format!("Такого дня нет, пока прошло дней: {}", days),
+            Text::ConfigHeader => "Настройки тренировки:\n".to_string(),
+            Text::ConfigExercise(exercise) => format!("Упражнение: {}\n", exercise),
+            Text::ConfigDuration(days) => format!("Длительность: {} дней\n", days),
+            Text::ConfigToday(day) => format!("Сегодня: день {}\n", day),
+            Text::ConfigRepeats(repeats) => format!("Норма: {} повторений\n", repeats),
+            Text::ConfigGoal(target) => format!("Общая цель: {} повторений\n", target),
+            Text::ConfigCycleLength(days) => format!("Длина цикла: {} дней\n", days),
+            Text::ConfigNextCycleLength { current, next } => format!(
+                "Длина цикла: {} дней (со следующего цикла {})\n",
+                current, next
+            ),
+            Text::ConfigSchedule { targets, finish } => format!(
+                "Расписание нормы: {} ({})\n",
+                targets,
+                if finish {
+                    "потом конец"
+                } else {
+                    "потом держим последнюю"
+                }
+            ),
+            Text::ConfigCycleIncrease(increase) => format!("Изменение за цикл: {:+}\n", increase),
+            Text::ConfigMinRepeats(repeats) => format!("Минимальная норма: {}\n", repeats),
+            Text::ConfigReset { hour, timezone } => {
+                format!("Новый день: {:02}:00 ({})\n", hour, timezone)
+            }
+            Text::ConfigReminder(hour) => format!("Напоминание: {:02}:00\n", hour),
+            Text::ConfigNotify => "Итоги приходят с уведомлением\n".to_string(),
+            Text::ConfigPaused => "Сейчас на паузе\n".to_string(),
+            Text::ConfigRestDay(weekday) => format!("День отдыха: {}\n", ru_weekday(weekday)),
+            Text::ConfigPauseExtends(true) => "Дни на паузе продлевают тренировку\n".to_string(),
+            Text::ConfigPauseExtends(false) => {
+                "Дни на паузе вычитаются из длительности\n".to_string()
+            }
+            Text::Paused => {
+                "⏸ На паузе: дни не идут, серии не прерываются. /resume — продолжить\n\n"
+                    .to_string()
+            }
+            Text::ConfigUsers(users) => format!("Участников: {}\n", users),
+            Text::ReminderEntry {
+                name,
+                count,
+                target,
+            } => format!("{} ({} из {})", name, count, target),
+            Text::Reminder(users) => format!("⏰ Ещё не выполнили норму: {}", users),
+            Text::UserDone { name, exercise } => {
+                format!("🥳 {}: норма {} на сегодня выполнена", name, exercise)
+            }
+            Text::AllDone(exercise) => {
+                format!("На сегодня всё 🎉 Норма {} выполнена всеми", exercise)
+            }
+            Text::FirstFinisher(name) => format!("\n🏆 Быстрее всех: {}", name),
+            Text::MeStats {
+                name,
+                today,
+                target,
+                total,
+                streak,
+                place,
+                participants,
+            } => format!(
+                "{}: сегодня {}/{}, всего {}\n🔥 Серия: {} дн.\n🏅 Место: {} из {}",
+                name, today, target, total, streak, place, participants
+            ),
+            Text::MeNotJoined(exercise) => format!(
+                "Тебя пока нет в списке. Отправь число, например 20, и я начну считать твои {}",
+                exercise
+            ),
+            Text::Stats {
+                days,
+                total,
+                exercise,
+                average,
+                participation,
+            } => format!(
+                "📊 Статистика за {} дн.\nВсего: {} {}\nВ среднем за день: {:.1}\nДни с занятиями: {:.1}%\n",
+                days, total, exercise, average, participation
+            ),
+            Text::MostConsistent { name, days } => {
+                format!("Самый постоянный: {}, {} дн. с занятиями\n", name, days)
+            }
+            Text::CycleUp { from, to } => format!(
+                "Очередной цикл завершён! Увеличиваем повторения с {} до {}.",
+                from, to
+            ),
+            Text::CycleDown { from, to } => format!(
+                "Очередной цикл завершён! Уменьшаем повторения с {} до {}.",
+                from, to
+            ),
+            Text::AtMinimum => " Это минимум, дальше без изменений.".to_string(),
+            Text::LocaleSet => "Язык: русский".to_string(),
+            Text::RestDaySet(Some(weekday)) => format!(
+                "День отдыха: {}. Он не входит в длительность тренировки и не прерывает серии",
+                ru_weekday(weekday)
+            ),
+            Text::RestDaySet(None) => "Дней отдыха больше нет".to_string(),
+            Text::RestDay => {
+                "🛌 День отдыха: повторения не нужны, серии не прерываются\n\n".to_string()
+            }
+            Text::TimeLeft { hours, minutes } => {
+                format!("\n⏳ До нового дня осталось {}ч {}м", hours, minutes)
+            }
+            Text::AdminOnly => "Эту команду могут выполнять только администраторы".to_string(),
+            Text::UnknownCommand => "Неизвестная команда, попробуйте /help".to_string(),
+            Text::CantSeeMessages => {
+                "Я не вижу обычные сообщения в этом чате, только команды и ответы мне. \
+                 Пишите повторения командой /add 20, или сделайте меня администратором, \
+                 или отключите privacy mode у @BotFather (/setprivacy)"
+                    .to_string()
+            }
+            Text::NoteSaved(day) => format!("📝 Заметка к дню {} сохранена", day),
+            Text::Joined(name) => format!("👋 {} в деле!", name),
+            Text::AlreadyJoined(name) => format!("{}, ты уже участвуешь", name),
+            Text::Left(name) => format!(
+                "{} больше не участвует. Вернуться можно через /join",
+                name
+            ),
+            Text::AlreadyLeft(name) => format!("{}, ты и так не участвуешь", name),
+            Text::Undone { name, count } => format!("↩️ {}: отменено {} повторений", name, count),
+            Text::NothingToUndo => "Сегодня ещё нечего отменять. Чтобы исправить число, напиши его с минусом, например -10".to_string(),
+            Text::Restarted => "🔄 Начинаем заново с первого дня".to_string(),
+            Text::NotJoinedYet(name) => format!("{} ещё не участвует в тренировке", name),
+            Text::GiftsDisabled => "Передавать повторения в этом чате нельзя".to_string(),
+            Text::GiftToSelf => "Нельзя передать повторения самому себе".to_string(),
+            Text::NothingToGive => "Нечего передавать".to_string(),
+            Text::GiftTooLarge(available) => format!(
+                "Сегодня у тебя только {} повторений, больше передать нельзя",
+                available
+            ),
+            Text::Gift { from, to, count } => {
+                format!("🎁 {} передаёт {} {} повторений", from, to, count)
+            }
+            Text::GiftsSet(true) => "Повторения снова можно передавать друг другу".to_string(),
+            Text::GiftsSet(false) => "Передача повторений отключена".to_string(),
+            Text::StrictInputSet(true) => {
+                "Считаю только ответы на закреплённое сообщение дня".to_string()
+            }
+            Text::StrictInputSet(false) => "Считаю все сообщения с повторениями".to_string(),
+            Text::NotificationsSet(true) => {
+                "🔔 Итоги цикла и тренировки будут приходить с уведомлением".to_string()
+            }
+            Text::NotificationsSet(false) => "🔕 Все сообщения приходят без уведомления".to_string(),
+            Text::UserTargetSet { name, target } => match target {
+                Some(target) => format!("{}: личная норма {}", name, target),
+                None => format!("{}: снова общая норма", name),
+            },
+            Text::NewUsage => "Укажите, что считать: /new приседаний".to_string(),
+            Text::ExerciseLength(max) => format!(
+                "Название упражнения должно быть от 1 до {} символов",
+                max
+            ),
+            Text::ExerciseSet(exercise) => format!("Теперь считаем {}", exercise),
+            Text::CycleLengthRange(max) => format!("Длина цикла должна быть от 1 до {} дней", max),
+            Text::CycleLengthSet(days) => format!("Длина цикла: {} дней", days),
+            Text::NextCycleLengthSet(days) => {
+                format!("Со следующего цикла длина цикла будет {} дней", days)
+            }
+            Text::CycleIncreaseSet(increase) => format!(
+                "В конце цикла норма изменится на {:+} повторений",
+                increase
+            ),
+            Text::StartRepeatsZero => "Начальная норма должна быть больше нуля".to_string(),
+            Text::StartRepeatsLate => {
+                "Начальную норму можно менять только в первый день".to_string()
+            }
+            Text::StartRepeatsScheduled => {
+                "Норму задаёт расписание, сначала отключите его: /schedule off".to_string()
+            }
+            Text::StartRepeatsSet {
+                repeats,
+                increase,
+                cycle_length,
+            } => format!(
+                "Начинаем с {} повторений, дальше норма меняется на {:+} каждые {} дней",
+                repeats, increase, cycle_length
+            ),
+            Text::GoalZero => "Цель должна быть больше нуля".to_string(),
+            Text::GoalSet { target, duration } => {
+                format!("🎯 Общая цель: {} повторений за {} дней", target, duration)
+            }
+            Text::DailyTargets => "Снова занимаемся по дневной норме".to_string(),
+            Text::CycleTargets => "Норма снова меняется по циклам".to_string(),
+            Text::ScheduleSet { targets, finish } => format!(
+                "Норма по дням: {}; {}",
+                targets,
+                if finish {
+                    "дальше тренировка заканчивается"
+                } else {
+                    "дальше держим последнюю"
+                }
+            ),
+            Text::DurationRange(max) => format!("Длительность должна быть от 1 до {} дней", max),
+            Text::DurationSet(days) => format!("Длительность тренировки: {} дней", days),
+            Text::ResetTimeSet { hour, timezone } => {
+                format!("Новый день начинается в {:02}:00 ({})", hour, timezone)
+            }
+            Text::ReminderSet { hour, timezone } => match hour {
+                Some(hour) => format!("Напомню отстающим в {:02}:00 ({})", hour, timezone),
+                None => "Напоминания выключены".to_string(),
+            },
+            Text::NoDeleteRights => {
+                "Чтобы чистить чат, мне нужно право на удаление сообщений".to_string()
+            }
+            Text::CleanChatSet(true) => {
+                "🧹 Буду удалять сообщения, которые не похожи на повторения или команды".to_string()
+            }
+            Text::CleanChatSet(false) => "Больше не удаляю сообщения".to_string(),
+            Text::Description {
+                chat_id,
+                challenge_id,
+                exercise,
+                day,
+                duration,
+                users,
+            } => format!(
+                "{} #{} {}: день {} из {}, участников: {}",
+                chat_id, challenge_id, exercise, day, duration, users
+            ),
+            Text::NoChallenges => "Сейчас нет ни одной тренировки".to_string(),
+            Text::ChallengesHeader(count) => format!("Тренировки ({}):\n", count),
+            Text::ConfirmStop => {
+                "Завершить тренировку сейчас? Итоги подведём по сегодняшний день".to_string()
+            }
+            Text::ConfirmReset => {
+                "Начать заново с первого дня? Все повторения будут удалены".to_string()
+            }
+            Text::ConfirmButton => "Подтвердить".to_string(),
+            Text::CancelButton => "Отмена".to_string(),
+            Text::ConfirmationGone => "Этот запрос уже не действует".to_string(),
+            Text::ConfirmationExpired => "Время на подтверждение вышло".to_string(),
+            Text::NotYourConfirmation => {
+                "Ответить может тот, кто отправил команду, или администратор".to_string()
+            }
+            Text::Confirmed => "Подтверждено".to_string(),
+            Text::Cancelled => "Отменено".to_string(),
+            Text::Nudge => {
+                "Никто ещё не занимался 🙂 Добавляйте повторения числом, например 20".to_string()
+            }
+            Text::PauseSet(true) => "⏸ Тренировка на паузе: дни не идут, пока не /resume".to_string(),
+            Text::PauseSet(false) => "▶️ Продолжаем!".to_string(),
+            Text::PauseUnchanged(true) => "Тренировка уже на паузе".to_string(),
+            Text::PauseUnchanged(false) => "Тренировка и так идёт".to_string(),
+            Text::PauseExtendsSet(true) => "Дни на паузе продлевают тренировку".to_string(),
+            Text::PauseExtendsSet(false) => "Дни на паузе вычитаются из длительности".to_string(),
+            Text::UserRemoved(name) => format!(
+                "{} удалён из тренировки вместе со всеми повторениями",
+                name
+            ),
+            Text::NotInWorkout(name) => format!("{} не участвует в тренировке", name),
+            Text::NotStarted => "Тренировка ещё не началась".to_string(),
+            Text::DayRange(max) => format!("День должен быть от 1 до {}", max),
+            Text::DaySet { day, duration } => format!("Теперь идёт день {} из {}", day, duration),
+        }
+    }
+
+    fn en(&self) -> String {
+        match *self {
+            Text::Greeting {
+                exercise,
+                repeats,
+                duration,
+                commands,
+            } => format!(
+                "👋 Hi! I'm counting {} in this chat.
+
+To log a set, send a number like 20, or several separated by spaces: 10 15 20. Made a mistake? Send the number with a minus or /undo.
+
+Today's target: {}, the workout lasts {} days.
+
+Commands: {}.",
+                exercise, repeats, duration, commands
+            ),
+            Text::DefaultExercise => "push-ups".to_string(),
+            Text::DailyFooter {
+                day,
+                duration,
+                repeats,
+                exercise,
+            } => format!(
+                "Day {} of {}. {} reps ({})\n",
+                day, duration, repeats, exercise
+            ),
+            Text::GoalUserLine {
+                name,
+                rank_mark,
+                total,
+                today,
+            } => format!("{}{}: {} (today {})\n", name, rank_mark, total, today),
+            Text::GoalFooter {
+                day,
+                duration,
+                total,
+                target,
+                exercise,
+                bar,
+            } => format!(
+                "Day {} of {}. Goal: {} of {} {} {}\n",
+                day, duration, total, target, exercise, bar
+            ),
+            Text::DeltaToYesterday(delta) => format!(" ({:+} vs yesterday)", delta),
+            Text::RawCountNote(raw_count) => format!(" ({} before difficulty)", raw_count),
+            Text::WorkoutOver {
+                days,
+                total,
+                exercise,
+            } => format!(
+                "The workout is over! We trained for {} days and did {} {} together.\n",
+                days, total, exercise
+            ),
+            Text::FinalUserLine {
+                name,
+                count,
+                raw_note,
+                days_met,
+                days,
+            } => format!(
+                "{}: {}{} (target met on {} of {} days)\n",
+                name, count, raw_note, days_met, days
+            ),
+            Text::DaysHeader => "\nBy day:\n".to_string(),
+            Text::FinalDayLine { day, total, target } => match target {
+                Some(target) => format!("Day {}: {}, target {}\n", day, total, target),
+                None => format!("Day {}: {}\n", day, total),
+            },
+            Text::BestDaysHeader => "\nBest days:\n".to_string(),
+            Text::UserBestDay { name, day, count } => {
+                format!("{}: day {}, {}\n", name, day, count)
+            }
+            Text::GroupBestDay {
+                day,
+                total,
+                exercise,
+            } => format!("The whole group: day {}, {} {}\n", day, total, exercise),
+            Text::GiftsHeader => "\nGifts:\n".to_string(),
+            Text::GiftLine {
+                day,
+                from,
+                to,
+                count,
+            } => format!("Day {}: {} → {}: {}\n", day, from, to, count),
+            Text::NotesHeader => "\nNotes:\n".to_string(),
+            Text::DayNote { day, note } => format!("Day {}: {}\n", day, note),
+            Text::WinnersHeader => "First to meet the target:\n".to_string(),
+            Text::DayWinner { day, name, time } => {
+                format!("Day {}: {} ({})\n", day, name, time)
+            }
+            Text::DayWithoutWinner(day) => format!("Day {}: nobody\n", day),
+            Text::NoFinishedDays => "No finished days yet".to_string(),
+            Text::HistoryHeader => "History:\n".to_string(),
+            Text::MoreDays(days) => format!("...and {} more days\n", days),
+            Text::HistoryLine {
+                day,
+                total,
+                exercise,
+                mark,
+            } => format!("Day {}: {} {} {}", day, total, exercise, mark),
+            Text::NobodyYet => "Nobody has joined yet".to_string(),
+            Text::LeaderboardHeader => "Leaderboard:\n".to_string(),
+            Text::DailyTopHeader(day) => format!("Top of day {}:\n", day),
+            Text::NobodyOnDay(day) => format!("Nobody has trained on day {} yet", day),
+            Text::NoSuchDay(days) => format!("There's no such day, {} days so far", days),
+            Text::ConfigHeader => "Workout settings:\n".to_string(),
+            Text::ConfigExercise(exercise) => format!("Exercise: {}\n", exercise),
+            Text::ConfigDuration(days) => format!("Duration: {} days\n", days),
+            Text::ConfigToday(day) => format!("Today: day {}\n", day),
+            Text::ConfigRepeats(repeats) => format!("Target: {} reps\n", repeats),
+            Text::ConfigGoal(target) => format!("Total goal: {} reps\n", target),
+            Text::ConfigCycleLength(days) => format!("Cycle length: {} days\n", days),
+            Text::ConfigNextCycleLength { current, next } => format!(
+                "Cycle length: {} days ({} from the next cycle)\n",
+                current, next
+            ),
+            Text::ConfigSchedule { targets, finish } => format!(
+                "Target schedule: {} ({})\n",
+                targets,
+                if finish {
+                    "then the end"
+                } else {
+                    "then keep the last one"
+                }
+            ),
+            Text::ConfigCycleIncrease(increase) => format!("Change per cycle: {:+}\n", increase),
+            Text::ConfigMinRepeats(repeats) => format!("Minimum target: {}\n", repeats),
+            Text::ConfigReset { hour, timezone } => {
+                format!("New day: {:02}:00 ({})\n", hour, timezone)
+            }
+            Text::ConfigReminder(hour) => format!("Reminder: {:02}:00\n", hour),
+            Text::ConfigNotify => "Results come with a notification\n".to_string(),
+            Text::ConfigPaused => "Paused right now\n".to_string(),
+            Text::ConfigRestDay(weekday) => format!("Rest day: {}\n", en_weekday(weekday)),
+            Text::ConfigPauseExtends(true) => "Paused days extend the challenge\n".to_string(),
+            Text::ConfigPauseExtends(false) => "Paused days come off the duration\n".to_string(),
+            Text::Paused => {
+                "⏸ Paused: days don't advance and streaks are safe. /resume to continue\n\n"
+                    .to_string()
+            }
+            Text::ConfigUsers(users) => format!("Participants: {}\n", users),
+            Text::ReminderEntry {
+                name,
+                count,
+                target,
+            } => format!("{} ({} of {})", name, count, target),
+            Text::Reminder(users) => format!("⏰ Still below the target: {}", users),
+            Text::UserDone { name, exercise } => {
+                format!("🥳 {}: today's {} done", name, exercise)
+            }
+            Text::AllDone(exercise) => {
+                format!("That's it for today 🎉 Everyone did their {}", exercise)
+            }
+            Text::FirstFinisher(name) => format!("\n🏆 First to finish: {}", name),
+            Text::MeStats {
+                name,
+                today,
+                target,
+                total,
+                streak,
+                place,
+                participants,
+            } => format!(
+                "{}: {}/{} today, {} in total\n🔥 Streak: {} days\n🏅 Place: {} of {}",
+                name, today, target, total, streak, place, participants
+            ),
+            Text::MeNotJoined(exercise) => format!(
+                "You're not in yet. Send a number like 20 and I'll start counting your {}",
+                exercise
+            ),
+            Text::Stats {
+                days,
+                total,
+                exercise,
+                average,
+                participation,
+            } => format!(
+                "📊 Stats for {} days\nTotal: {} {}\nAverage per day: {:.1}\nDays with logs: {:.1}%\n",
+                days, total, exercise, average, participation
+            ),
+            Text::MostConsistent { name, days } => {
+                format!("Most consistent: {}, {} days with logs\n", name, days)
+            }
+            Text::CycleUp { from, to } => format!(
+                "Another cycle is over! Raising the target from {} to {}.",
+                from, to
+            ),
+            Text::CycleDown { from, to } => format!(
+                "Another cycle is over! Lowering the target from {} to {}.",
+                from, to
+            ),
+            Text::AtMinimum => " That's the minimum, it stays there.".to_string(),
+            Text::LocaleSet => "Language: English".to_string(),
+            Text::RestDaySet(Some(weekday)) => format!(
+                "Rest day: {}. It doesn't count toward the duration and doesn't break streaks",
+                en_weekday(weekday)
+            ),
+            Text::RestDaySet(None) => "No more rest days".to_string(),
+            Text::RestDay => "🛌 Rest day: no reps needed, streaks are safe\n\n".to_string(),
+            Text::TimeLeft { hours, minutes } => {
+                format!("\n⏳ {}h {}m left until the next day", hours, minutes)
+            }
+            Text::AdminOnly => "Only admins can use this command".to_string(),
+            Text::UnknownCommand => "Unknown command, try /help".to_string(),
+            Text::CantSeeMessages => {
+                "I can't see regular messages in this chat, only commands and replies to me. \
+                 Log reps with /add 20, make me an admin, \
+                 or turn off privacy mode with @BotFather (/setprivacy)"
+                    .to_string()
+            }
+            Text::NoteSaved(day) => format!("📝 Note for day {} saved", day),
+            Text::Joined(name) => format!("👋 {} is in!", name),
+            Text::AlreadyJoined(name) => format!("{}, you're already in", name),
+            Text::Left(name) => format!("{} is out. /join to come back", name),
+            Text::AlreadyLeft(name) => format!("{}, you're not in anyway", name),
+            Text::Undone { name, count } => format!("↩️ {}: {} reps undone", name, count),
+            Text::NothingToUndo => {
+                "Nothing to undo today. To fix a number, send it with a minus, like -10".to_string()
+            }
+            Text::Restarted => "🔄 Starting over from day one".to_string(),
+            Text::NotJoinedYet(name) => format!("{} hasn't joined the workout yet", name),
+            Text::GiftsDisabled => "Reps can't be given away in this chat".to_string(),
+            Text::GiftToSelf => "You can't give reps to yourself".to_string(),
+            Text::NothingToGive => "Nothing to give".to_string(),
+            Text::GiftTooLarge(available) => format!(
+                "You only have {} reps today, you can't give more",
+                available
+            ),
+            Text::Gift { from, to, count } => format!("🎁 {} gives {} {} reps", from, to, count),
+            Text::GiftsSet(true) => "Reps can be given away again".to_string(),
+            Text::GiftsSet(false) => "Giving reps away is off".to_string(),
+            Text::StrictInputSet(true) => {
+                "Only replies to the pinned daily message count".to_string()
+            }
+            Text::StrictInputSet(false) => "Every message with reps counts".to_string(),
+            Text::NotificationsSet(true) => {
+                "🔔 Cycle and workout results will come with a notification".to_string()
+            }
+            Text::NotificationsSet(false) => "🔕 All messages come without a notification".to_string(),
+            Text::UserTargetSet { name, target } => match target {
+                Some(target) => format!("{}: personal target {}", name, target),
+                None => format!("{}: back to the common target", name),
+            },
+            Text::NewUsage => "Say what to count: /new squats".to_string(),
+            Text::ExerciseLength(max) => format!(
+                "The exercise name must be 1 to {} characters long",
+                max
+            ),
+            Text::ExerciseSet(exercise) => format!("Now counting {}", exercise),
+            Text::CycleLengthRange(max) => format!("The cycle length must be 1 to {} days", max),
+            Text::CycleLengthSet(days) => format!("Cycle length: {} days", days),
+            Text::NextCycleLengthSet(days) => {
+                format!("From the next cycle on, cycles will last {} days", days)
+            }
+            Text::CycleIncreaseSet(increase) => format!(
+                "At the end of each cycle the target changes by {:+} reps",
+                increase
+            ),
+            Text::StartRepeatsZero => "The starting target must be above zero".to_string(),
+            Text::StartRepeatsLate => {
+                "The starting target can only be changed on the first day".to_string()
+            }
+            Text::StartRepeatsScheduled => {
+                "The schedule sets the target, turn it off first: /schedule off".to_string()
+            }
+            Text::StartRepeatsSet {
+                repeats,
+                increase,
+                cycle_length,
+            } => format!(
+                "Starting with {} reps, then the target changes by {:+} every {} days",
+                repeats, increase, cycle_length
+            ),
+            Text::GoalZero => "The goal must be above zero".to_string(),
+            Text::GoalSet { target, duration } => {
+                format!("🎯 Total goal: {} reps in {} days", target, duration)
+            }
+            Text::DailyTargets => "Back to daily targets".to_string(),
+            Text::CycleTargets => "The target follows the cycles again".to_string(),
+            Text::ScheduleSet { targets, finish } => format!(
+                "Targets by day: {}; {}",
+                targets,
+                if finish {
+                    "then the workout ends"
+                } else {
+                    "then the last one holds"
+                }
+            ),
+            Text::DurationRange(max) => format!("The duration must be 1 to {} days", max),
+            Text::DurationSet(days) => format!("Workout duration: {} days", days),
+            Text::ResetTimeSet { hour, timezone } => {
+                format!("The new day starts at {:02}:00 ({})", hour, timezone)
+            }
+            Text::ReminderSet { hour, timezone } => match hour {
+                Some(hour) => format!("I'll remind those behind at {:02}:00 ({})", hour, timezone),
+                None => "Reminders are off".to_string(),
+            },
+            Text::NoDeleteRights => {
+                "I need the right to delete messages to keep the chat clean".to_string()
+            }
+            Text::CleanChatSet(true) => {
+                "🧹 I'll delete messages that don't look like reps or commands".to_string()
+            }
+            Text::CleanChatSet(false) => "No longer deleting messages".to_string(),
+            Text::Description {
+                chat_id,
+                challenge_id,
+                exercise,
+                day,
+                duration,
+                users,
+            } => format!(
+                "{} #{} {}: day {} of {}, {} participants",
+                chat_id, challenge_id, exercise, day, duration, users
+            ),
+            Text::NoChallenges => "There are no workouts right now".to_string(),
+            Text::ChallengesHeader(count) => format!("Workouts ({}):\n", count),
+            Text::ConfirmStop => {
+                "Finish the workout now? The results will count up to today".to_string()
+            }
+            Text::ConfirmReset => "Start over from day one? All reps will be deleted".to_string(),
+            Text::ConfirmButton => "Confirm".to_string(),
+            Text::CancelButton => "Cancel".to_string(),
+            Text::ConfirmationGone => "This request is no longer valid".to_string(),
+            Text::ConfirmationExpired => "The time to confirm is up".to_string(),
+            Text::NotYourConfirmation => {
+                "Only whoever sent the command or an admin can answer".to_string()
+            }
+            Text::Confirmed => "Confirmed".to_string(),
+            Text::Cancelled => "Cancelled".to_string(),
+            Text::Nudge => "Nobody has trained yet 🙂 Log reps by sending a number, like 20".to_string(),
+            Text::PauseSet(true) => "⏸ The workout is paused: days don't advance until /resume".to_string(),
+            Text::PauseSet(false) => "▶️ Back to it!".to_string(),
+            Text::PauseUnchanged(true) => "The workout is already paused".to_string(),
+            Text::PauseUnchanged(false) => "The workout is already running".to_string(),
+            Text::PauseExtendsSet(true) => "Paused days extend the workout".to_string(),
+            Text::PauseExtendsSet(false) => "Paused days come off the duration".to_string(),
+            Text::UserRemoved(name) => {
+                format!("{} is removed from the workout along with all their reps", name)
+            }
+            Text::NotInWorkout(name) => format!("{} isn't in the workout", name),
+            Text::NotStarted => "The workout hasn't started yet".to_string(),
+            Text::DayRange(max) => format!("The day must be 1 to {}", max),
+            Text::DaySet { day, duration } => format!("It's now day {} of {}", day, duration),
+        }
+    }
+}
+
+fn ru_weekday(weekday: Weekday) -> &'static str {
+    [
+        "понедельник",
+        "вторник",
+        "среда",
+        "четверг",
+        "пятница",
+        "суббота",
+        "воскресенье",
+    ][weekday.num_days_from_monday() as usize]
+}
+
+fn en_weekday(weekday: Weekday) -> &'static str {
+    [
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ][weekday.num_days_from_monday() as usize]
+}
@@ -0,0 +1,155 @@
+//! Counters for monitoring. Built with the `metrics` feature they are kept and
+//! served in the Prometheus text format on `METRICS_ADDR`; without it,
+//! recording them does nothing and there's no server.
+
+#[cfg(feature = "metrics")]
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Counter {
+    UpdatesProcessed,
+    RepsLogged,
+    ApiErrors,
+}
+
+#[cfg(feature = "metrics")]
+static UPDATES_PROCESSED: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static REPS_LOGGED: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static API_ERRORS: AtomicU64 = AtomicU64::new(0);
+#[cfg(feature = "metrics")]
+static ACTIVE_CONTEXTS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "metrics")]
+pub const DEFAULT_METRICS_ADDR: &str = "0.0.0.0:9090";
+
+#[cfg(feature = "metrics")]
+impl Counter {
+    const ALL: [Counter; 3] = [
+        Counter::UpdatesProcessed,
+        Counter::RepsLogged,
+        Counter::ApiErrors,
+    ];
+
+    fn value(self) -> &'static AtomicU64 {
+        match self {
+            Counter::UpdatesProcessed => &UPDATES_PROCESSED,
+            Counter::RepsLogged => &REPS_LOGGED,
+            Counter::ApiErrors => &API_ERRORS,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Counter::UpdatesProcessed => "workout_updates_processed_total",
+            Counter::RepsLogged => "workout_reps_logged_total",
+            Counter::ApiErrors => "workout_api_errors_total",
+        }
+    }
+}
+
+pub fn increment(counter: Counter, by: u64) {
+    #[cfg(feature = "metrics")]
+    counter.value().fetch_add(by, Ordering::Relaxed);
+    #[cfg(not(feature = "metrics"))]
+    let _ = (counter, by);
+}
+
+/// Records how many challenges have a running handler.
+pub fn set_active_contexts(count: usize) {
+    #[cfg(feature = "metrics")]
+    ACTIVE_CONTEXTS.store(count as u64, Ordering::Relaxed);
+    #[cfg(not(feature = "metrics"))]
+    let _ = count;
+}
+
+#[cfg(feature = "metrics")]
+fn render() -> String {
+    let mut text = String::new();
+
+    for counter in Counter::ALL {
+        text += &format!(
+            "# TYPE {name} counter\n{name} {}\n",
+            counter.value().load(Ordering::Relaxed),
+            name = counter.name()
+        );
+    }
+
+    text += &format!(
+        "# TYPE workout_active_contexts gauge\nworkout_active_contexts {}\n",
+        ACTIVE_CONTEXTS.load(Ordering::Relaxed)
+    );
+
+    text
+}
+
+/// Answers `GET /metrics` on `METRICS_ADDR`.
+#[cfg(feature = "metrics")]
+pub async fn serve_metrics() {
+    use log::{debug, error, info, warn};
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    let addr = std::env::var("METRICS_ADDR").unwrap_or_else(|_| DEFAULT_METRICS_ADDR.to_string());
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to listen on {}: {}", addr, err);
+            return;
+        }
+    };
+
+    info!("Serving metrics on {}/metrics", addr);
+
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+
+        tokio::spawn(async move {
+            let response = match crate::webhook::read_request(&mut stream).await {
+                Ok(request) if request.method == "GET" && request.path == "/metrics" => {
+                    let body = render();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                        body.len(),
+                        body
+                    )
+                }
+                Ok(_) => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string(),
+                Err(err) => {
+                    debug!("Metrics connection failed: {}", err);
+                    return;
+                }
+            };
+
+            if let Err(err) = stream.write_all(response.as_bytes()).await {
+                debug!("Metrics connection failed: {}", err);
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_are_rendered_for_prometheus() {
+        increment(Counter::RepsLogged, 25);
+        set_active_contexts(3);
+
+        let text = render();
+
+        assert!(text.contains("# TYPE workout_reps_logged_total counter\n"));
+        assert!(text.contains("workout_active_contexts 3\n"));
+        assert!(text.contains("workout_api_errors_total "));
+    }
+}
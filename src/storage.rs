@@ -0,0 +1,70 @@
+pub mod json;
+pub mod sqlite;
+
+pub use json::JsonStore;
+pub use sqlite::SqliteStore;
+
+use crate::client::BotClient;
+use crate::context::ContextData;
+use crate::workout::ChallengeKey;
+use std::env;
+use std::sync::Arc;
+use std::{fmt, io};
+
+/// Where chat contexts are kept between restarts.
+pub trait Store: Send + Sync {
+    fn save_context(&self, context: &ContextData) -> Result<(), StorageError>;
+
+    fn delete_context(&self, key: ChallengeKey) -> Result<(), StorageError>;
+
+    fn load_all(&self, api: Arc<dyn BotClient>) -> Vec<ContextData>;
+
+    /// The `offset` to poll Telegram with, so that updates handled before a
+    /// restart aren't fetched again.
+    fn load_update_offset(&self) -> Option<u32>;
+
+    fn save_update_offset(&self, offset: u32) -> Result<(), StorageError>;
+}
+
+#[derive(Debug)]
+pub enum StorageError {
+    Sqlite(rusqlite::Error),
+    Io(io::Error),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sqlite(err) => write!(f, "sqlite: {}", err),
+            Self::Io(err) => write!(f, "io: {}", err),
+            Self::Json(err) => write!(f, "json: {}", err),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self::Sqlite(err)
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        Self::Json(err)
+    }
+}
+
+/// Opens the backend selected by `STORAGE` (`sqlite` by default, or `json`).
+pub fn open_store() -> Result<Arc<dyn Store>, StorageError> {
+    match env::var("STORAGE").as_deref() {
+        Ok("json") => Ok(Arc::new(JsonStore::open(&JsonStore::path())?)),
+        _ => Ok(Arc::new(SqliteStore::open(&SqliteStore::path())?)),
+    }
+}
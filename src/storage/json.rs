@@ -0,0 +1,153 @@
+use super::{StorageError, Store};
+use crate::client::BotClient;
+use crate::context::ContextData;
+use crate::workout::{ChallengeKey, WorkoutState};
+use log::{error, warn};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub const DEFAULT_DATA_DIR: &str = "data";
+
+/// Lives next to the snapshots; without the `.json` extension it isn't
+/// mistaken for one.
+const UPDATE_OFFSET_FILE: &str = "update_offset";
+
+/// Keeps one `<chat_id>.json` snapshot per chat in a directory.
+pub struct JsonStore {
+    dir: PathBuf,
+}
+
+impl JsonStore {
+    pub fn path() -> String {
+        env::var("DATA_DIR").unwrap_or_else(|_| DEFAULT_DATA_DIR.to_string())
+    }
+
+    pub fn open(dir: &str) -> Result<Self, StorageError> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: PathBuf::from(dir),
+        })
+    }
+
+    /// The first challenge of a chat keeps the plain `<chat_id>.json` name it
+    /// had before chats could run several.
+    fn file_path(&self, (chat_id, challenge_id): ChallengeKey) -> PathBuf {
+        match challenge_id {
+            0 => self.dir.join(format!("{}.json", chat_id)),
+            _ => self.dir.join(format!("{}-{}.json", chat_id, challenge_id)),
+        }
+    }
+
+    /// Writes the snapshot to a temporary file first so a crash mid-write
+    /// never leaves a truncated snapshot behind.
+    pub fn flush(&self, context: &ContextData) -> Result<(), StorageError> {
+        let path = self.file_path(context.state.key());
+        let tmp_path = path.with_extension("json.tmp");
+
+        fs::write(&tmp_path, serde_json::to_vec_pretty(&context.state)?)?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Loads every snapshot in the directory. Corrupt files are logged and skipped.
+    pub fn restore(&self, api: Arc<dyn BotClient>) -> Vec<ContextData> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                error!("Failed to read {:?}: {}", self.dir, err);
+                return vec![];
+            }
+        };
+
+        entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .filter_map(|path| match read_snapshot(&path) {
+                Ok(state) => Some(ContextData::from_state(api.clone(), state)),
+                Err(err) => {
+                    warn!("Skipping corrupt snapshot {:?}: {}", path, err);
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+fn read_snapshot(path: &Path) -> Result<WorkoutState, StorageError> {
+    let contents = fs::read(path)?;
+
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+impl Store for JsonStore {
+    fn save_context(&self, context: &ContextData) -> Result<(), StorageError> {
+        self.flush(context)
+    }
+
+    fn delete_context(&self, key: ChallengeKey) -> Result<(), StorageError> {
+        fs::remove_file(self.file_path(key))?;
+
+        Ok(())
+    }
+
+    fn load_all(&self, api: Arc<dyn BotClient>) -> Vec<ContextData> {
+        self.restore(api)
+    }
+
+    fn load_update_offset(&self) -> Option<u32> {
+        let contents = fs::read_to_string(self.dir.join(UPDATE_OFFSET_FILE)).ok()?;
+
+        contents.trim().parse().ok()
+    }
+
+    fn save_update_offset(&self, offset: u32) -> Result<(), StorageError> {
+        let path = self.dir.join(UPDATE_OFFSET_FILE);
+        let tmp_path = path.with_extension("tmp");
+
+        fs::write(&tmp_path, offset.to_string())?;
+        fs::rename(tmp_path, path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockClient;
+    use crate::config::Config;
+    use crate::workout::Participant;
+
+    #[test]
+    fn restore_skips_corrupt_snapshots() {
+        let dir = env::temp_dir().join(format!("workout-json-{}", std::process::id()));
+        let store = JsonStore::open(dir.to_str().unwrap()).unwrap();
+        let api: Arc<dyn BotClient> = Arc::new(MockClient::default());
+
+        let mut context = ContextData::new(api.clone(), 42, &Config::default());
+        context.state.start_next_day();
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+        context.state.add_user_progress(alice, 30);
+        store.flush(&context).unwrap();
+        fs::write(dir.join("13.json"), "{ not json").unwrap();
+
+        let restored = store.restore(api);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(restored.len(), 1);
+        assert_eq!(restored[0].state.chat_id, 42);
+        assert_eq!(restored[0].state.progress, context.state.progress);
+        assert_eq!(restored[0].state.users, context.state.users);
+        assert_eq!(restored[0].state.day_targets, context.state.day_targets);
+    }
+}
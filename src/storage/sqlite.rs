@@ -0,0 +1,439 @@
+use super::{StorageError, Store};
+use crate::client::BotClient;
+use crate::config::Config;
+use crate::context::ContextData;
+use crate::workout::{legacy, ChallengeKey, WorkoutState};
+use chrono_tz::Tz;
+use log::{error, warn};
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+pub const DEFAULT_DATABASE_PATH: &str = "workout.db";
+
+/// Each entry moves the schema one version forward and runs at most once per
+/// database. Never edit a shipped entry, append a new one instead.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS contexts (
+        chat_id INTEGER PRIMARY KEY,
+        started INTEGER NOT NULL,
+        current_day INTEGER NOT NULL,
+        duration INTEGER NOT NULL,
+        repeats INTEGER NOT NULL,
+        cycle_length INTEGER NOT NULL,
+        cycle_increase INTEGER NOT NULL,
+        min_repeats INTEGER NOT NULL,
+        daily_message_id INTEGER,
+        users TEXT NOT NULL,
+        progress TEXT NOT NULL,
+        raw_progress TEXT NOT NULL,
+        day_notes TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        gifts TEXT NOT NULL,
+        clean_chat INTEGER NOT NULL,
+        allow_gifts INTEGER NOT NULL,
+        layout TEXT NOT NULL
+    )",
+    "ALTER TABLE contexts ADD COLUMN exercise TEXT NOT NULL DEFAULT 'отжиманий'",
+    "ALTER TABLE contexts ADD COLUMN reset_hour INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE contexts ADD COLUMN timezone TEXT NOT NULL DEFAULT 'UTC';
+    ALTER TABLE contexts ADD COLUMN next_reset TEXT NOT NULL DEFAULT 'null';",
+    "ALTER TABLE contexts ADD COLUMN strict_input INTEGER NOT NULL DEFAULT 0",
+    "ALTER TABLE contexts ADD COLUMN day_targets TEXT NOT NULL DEFAULT '[]'",
+    "ALTER TABLE contexts ADD COLUMN cycle_start INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE contexts ADD COLUMN next_cycle_length INTEGER;",
+    "ALTER TABLE contexts ADD COLUMN reminder_hour INTEGER",
+    // A chat can hold several challenges, so the key grows a challenge id.
+    "CREATE TABLE contexts_new (
+        chat_id INTEGER NOT NULL,
+        challenge_id INTEGER NOT NULL DEFAULT 0,
+        started INTEGER NOT NULL,
+        current_day INTEGER NOT NULL,
+        duration INTEGER NOT NULL,
+        repeats INTEGER NOT NULL,
+        cycle_length INTEGER NOT NULL,
+        cycle_increase INTEGER NOT NULL,
+        min_repeats INTEGER NOT NULL,
+        daily_message_id INTEGER,
+        users TEXT NOT NULL,
+        progress TEXT NOT NULL,
+        raw_progress TEXT NOT NULL,
+        day_notes TEXT NOT NULL,
+        finished_at TEXT NOT NULL,
+        gifts TEXT NOT NULL,
+        clean_chat INTEGER NOT NULL,
+        allow_gifts INTEGER NOT NULL,
+        layout TEXT NOT NULL,
+        exercise TEXT NOT NULL DEFAULT 'отжиманий',
+        reset_hour INTEGER NOT NULL DEFAULT 0,
+        timezone TEXT NOT NULL DEFAULT 'UTC',
+        next_reset TEXT NOT NULL DEFAULT 'null',
+        strict_input INTEGER NOT NULL DEFAULT 0,
+        day_targets TEXT NOT NULL DEFAULT '[]',
+        cycle_start INTEGER NOT NULL DEFAULT 0,
+        next_cycle_length INTEGER,
+        reminder_hour INTEGER,
+        PRIMARY KEY (chat_id, challenge_id)
+    );
+    INSERT INTO contexts_new (
+        chat_id, started, current_day, duration, repeats, cycle_length, cycle_increase,
+        min_repeats, daily_message_id, users, progress, raw_progress, day_notes, finished_at,
+        gifts, clean_chat, allow_gifts, layout, exercise, reset_hour, timezone, next_reset,
+        strict_input, day_targets, cycle_start, next_cycle_length, reminder_hour
+    )
+    SELECT
+        chat_id, started, current_day, duration, repeats, cycle_length, cycle_increase,
+        min_repeats, daily_message_id, users, progress, raw_progress, day_notes, finished_at,
+        gifts, clean_chat, allow_gifts, layout, exercise, reset_hour, timezone, next_reset,
+        strict_input, day_targets, cycle_start, next_cycle_length, reminder_hour
+    FROM contexts;
+    DROP TABLE contexts;
+    ALTER TABLE contexts_new RENAME TO contexts;",
+    "ALTER TABLE contexts ADD COLUMN schedule TEXT NOT NULL DEFAULT 'null';
+    ALTER TABLE contexts ADD COLUMN schedule_end TEXT NOT NULL DEFAULT '\"Hold\"';",
+    "ALTER TABLE contexts ADD COLUMN targets_per_user TEXT NOT NULL DEFAULT '{}'",
+    "CREATE TABLE bot_state (
+        key TEXT PRIMARY KEY,
+        value INTEGER NOT NULL
+    )",
+    "ALTER TABLE contexts ADD COLUMN silent INTEGER NOT NULL DEFAULT 1",
+    "ALTER TABLE contexts ADD COLUMN locale TEXT NOT NULL DEFAULT '\"Ru\"'",
+    "ALTER TABLE contexts ADD COLUMN mode TEXT NOT NULL DEFAULT '\"Daily\"'",
+    "ALTER TABLE contexts ADD COLUMN paused INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE contexts ADD COLUMN pause_extends INTEGER NOT NULL DEFAULT 1;",
+    "ALTER TABLE contexts ADD COLUMN rest_weekday TEXT NOT NULL DEFAULT 'null';
+    ALTER TABLE contexts ADD COLUMN resting INTEGER NOT NULL DEFAULT 0;",
+    "ALTER TABLE contexts ADD COLUMN cycle_changes TEXT NOT NULL DEFAULT '[]'",
+    "ALTER TABLE contexts ADD COLUMN left_users TEXT NOT NULL DEFAULT '[]'",
+];
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn path() -> String {
+        env::var("DATABASE_PATH").unwrap_or_else(|_| DEFAULT_DATABASE_PATH.to_string())
+    }
+
+    pub fn open(path: &str) -> rusqlite::Result<Self> {
+        let store = Self {
+            connection: Mutex::new(Connection::open(path)?),
+        };
+        store.migrate()?;
+
+        Ok(store)
+    }
+
+    fn migrate(&self) -> rusqlite::Result<()> {
+        let connection = self.connection.lock().unwrap();
+        let version: usize =
+            connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.unchecked_transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", index + 1)?;
+            transaction.commit()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Store for SqliteStore {
+    fn save_context(&self, context: &ContextData) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+
+        connection.execute(
+            "INSERT OR REPLACE INTO contexts (
+                chat_id, started, current_day, duration, repeats, cycle_length,
+                cycle_increase, min_repeats, daily_message_id, users, progress,
+                raw_progress, day_notes, finished_at, gifts, clean_chat, allow_gifts, layout,
+                exercise, reset_hour, timezone, next_reset, strict_input, day_targets,
+                cycle_start, next_cycle_length, reminder_hour, challenge_id, schedule,
+                schedule_end, targets_per_user, silent, locale, mode, paused, pause_extends,
+                rest_weekday, resting, cycle_changes, left_users
+            ) VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+                ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28, ?29, ?30, ?31, ?32, ?33, ?34,
+                ?35, ?36, ?37, ?38, ?39, ?40
+            )",
+            params![
+                context.state.chat_id,
+                context.state.started,
+                context.state.current_day,
+                context.state.duration,
+                context.state.repeats,
+                context.state.cycle_length,
+                context.state.cycle_increase,
+                context.state.min_repeats,
+                context.state.daily_message_id,
+                to_json(&context.state.users)?,
+                to_json(&context.state.progress)?,
+                to_json(&context.state.raw_progress)?,
+                to_json(&context.state.day_notes)?,
+                to_json(&context.state.finished_at)?,
+                to_json(&context.state.gifts)?,
+                context.state.clean_chat,
+                context.state.allow_gifts,
+                to_json(&context.state.layout)?,
+                context.state.exercise,
+                context.state.reset_hour,
+                context.state.timezone.name(),
+                to_json(&context.state.next_reset)?,
+                context.state.strict_input,
+                to_json(&context.state.day_targets)?,
+                context.state.cycle_start,
+                context.state.next_cycle_length,
+                context.state.reminder_hour,
+                context.state.challenge_id,
+                to_json(&context.state.schedule)?,
+                to_json(&context.state.schedule_end)?,
+                to_json(&context.state.targets_per_user)?,
+                context.state.silent,
+                to_json(&context.state.locale)?,
+                to_json(&context.state.mode)?,
+                context.state.paused,
+                context.state.pause_extends,
+                to_json(&context.state.rest_weekday)?,
+                context.state.resting,
+                to_json(&context.state.cycle_changes)?,
+                to_json(&context.state.left)?,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    fn delete_context(&self, (chat_id, challenge_id): ChallengeKey) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "DELETE FROM contexts WHERE chat_id = ?1 AND challenge_id = ?2",
+            params![chat_id, challenge_id],
+        )?;
+
+        Ok(())
+    }
+
+    /// Restores every saved chat. Rows that fail to decode are logged and skipped.
+    fn load_all(&self, api: Arc<dyn BotClient>) -> Vec<ContextData> {
+        let connection = self.connection.lock().unwrap();
+
+        let mut statement = match connection.prepare("SELECT * FROM contexts") {
+            Ok(statement) => statement,
+            Err(err) => {
+                error!("Failed to load contexts: {:?}", err);
+                return vec![];
+            }
+        };
+
+        let rows = statement.query_map([], |row| read_context(row, api.clone()));
+
+        let rows = match rows {
+            Ok(rows) => rows,
+            Err(err) => {
+                error!("Failed to load contexts: {:?}", err);
+                return vec![];
+            }
+        };
+
+        rows.filter_map(|row| match row {
+            Ok(context) => Some(context),
+            Err(err) => {
+                warn!("Skipping context that failed to load: {:?}", err);
+                None
+            }
+        })
+        .collect()
+    }
+
+    fn load_update_offset(&self) -> Option<u32> {
+        let connection = self.connection.lock().unwrap();
+
+        connection
+            .query_row(
+                "SELECT value FROM bot_state WHERE key = 'update_offset'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()
+            .unwrap_or_else(|err| {
+                error!("Failed to load the update offset: {:?}", err);
+                None
+            })
+    }
+
+    fn save_update_offset(&self, offset: u32) -> Result<(), StorageError> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT OR REPLACE INTO bot_state (key, value) VALUES ('update_offset', ?1)",
+            params![offset],
+        )?;
+
+        Ok(())
+    }
+}
+
+fn read_context(row: &Row, api: Arc<dyn BotClient>) -> rusqlite::Result<ContextData> {
+    let mut state = WorkoutState::new(row.get("chat_id")?, &Config::default());
+
+    state.started = row.get("started")?;
+    state.current_day = row.get("current_day")?;
+    state.duration = row.get("duration")?;
+    state.repeats = row.get("repeats")?;
+    state.cycle_length = row.get("cycle_length")?;
+    state.cycle_increase = row.get("cycle_increase")?;
+    state.min_repeats = row.get("min_repeats")?;
+    state.daily_message_id = row.get("daily_message_id")?;
+    state.users = from_json_with(row, "users", legacy::users)?;
+    state.progress = from_json_with(row, "progress", legacy::progress)?;
+    state.raw_progress = from_json_with(row, "raw_progress", legacy::progress)?;
+    state.day_notes = from_json(row, "day_notes")?;
+    state.finished_at = from_json_with(row, "finished_at", legacy::finished_at)?;
+    state.gifts = from_json_with(row, "gifts", legacy::gifts)?;
+    state.clean_chat = row.get("clean_chat")?;
+    state.allow_gifts = row.get("allow_gifts")?;
+    state.layout = from_json(row, "layout")?;
+    state.exercise = row.get("exercise")?;
+    state.reset_hour = row.get("reset_hour")?;
+    state.timezone = parse_timezone(row, "timezone")?;
+    state.next_reset = from_json(row, "next_reset")?;
+    state.strict_input = row.get("strict_input")?;
+    state.day_targets = from_json(row, "day_targets")?;
+    state.cycle_start = row.get("cycle_start")?;
+    state.next_cycle_length = row.get("next_cycle_length")?;
+    state.reminder_hour = row.get("reminder_hour")?;
+    state.challenge_id = row.get("challenge_id")?;
+    state.schedule = from_json(row, "schedule")?;
+    state.schedule_end = from_json(row, "schedule_end")?;
+    state.targets_per_user = from_json(row, "targets_per_user")?;
+    state.silent = row.get("silent")?;
+    state.locale = from_json(row, "locale")?;
+    state.mode = from_json(row, "mode")?;
+    state.paused = row.get("paused")?;
+    state.pause_extends = row.get("pause_extends")?;
+    state.rest_weekday = from_json(row, "rest_weekday")?;
+    state.resting = row.get("resting")?;
+    state.cycle_changes = from_json(row, "cycle_changes")?;
+    state.left = from_json(row, "left_users")?;
+
+    Ok(ContextData::from_state(api, state))
+}
+
+fn to_json<T: Serialize>(value: &T) -> rusqlite::Result<String> {
+    serde_json::to_string(value).map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
+}
+
+fn from_json<T: DeserializeOwned>(row: &Row, column: &str) -> rusqlite::Result<T> {
+    let json: String = row.get(column)?;
+
+    serde_json::from_str(&json).map_err(|err| {
+        let index = row.as_ref().column_index(column).unwrap_or_default();
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.into())
+    })
+}
+
+/// Like `from_json`, for the columns that may still be keyed on usernames.
+fn from_json_with<T>(
+    row: &Row,
+    column: &str,
+    parse: fn(Value) -> serde_json::Result<T>,
+) -> rusqlite::Result<T> {
+    parse(from_json(row, column)?).map_err(|err| {
+        let index = row.as_ref().column_index(column).unwrap_or_default();
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.into())
+    })
+}
+
+fn parse_timezone(row: &Row, column: &str) -> rusqlite::Result<Tz> {
+    let name: String = row.get(column)?;
+
+    name.parse().map_err(|err: String| {
+        let index = row.as_ref().column_index(column).unwrap_or_default();
+        rusqlite::Error::FromSqlConversionFailure(index, Type::Text, err.into())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockClient;
+    use crate::messages::Locale;
+    use crate::workout::{Participant, WorkoutMode};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn saved_context_survives_reopen() {
+        let path = env::temp_dir().join(format!("workout-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let api: Arc<dyn BotClient> = Arc::new(MockClient::default());
+
+        let mut context = ContextData::new(api.clone(), 42, &Config::default());
+        context.state.start_next_day();
+        context.state.init_next_day();
+        context.state.daily_message_id = Some(7);
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+        context.state.add_weighted_user_progress(alice, 20, 1.5);
+        context
+            .state
+            .mark_user_finished(1, Utc.ymd(2022, 3, 1).and_hms(9, 0, 0));
+        context.state.timezone = chrono_tz::Asia::Yekaterinburg;
+        context.state.reminder_hour = Some(15);
+        context.state.silent = false;
+        context.state.locale = Locale::En;
+        context.state.mode = WorkoutMode::TotalGoal { target: 5000 };
+        context.state.targets_per_user.insert(1, 50);
+        context.schedule_next_reset();
+
+        SqliteStore::open(path)
+            .unwrap()
+            .save_context(&context)
+            .unwrap();
+
+        // Opening again must not re-run migrations or drop data.
+        let restored = SqliteStore::open(path).unwrap().load_all(api);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(restored.len(), 1);
+        let restored = &restored[0];
+        assert_eq!(restored.state.chat_id, 42);
+        assert!(restored.state.started);
+        assert_eq!(restored.state.current_day, 1);
+        assert_eq!(restored.state.daily_message_id, Some(7));
+        assert_eq!(restored.state.users, context.state.users);
+        assert_eq!(restored.state.progress, context.state.progress);
+        assert_eq!(restored.state.raw_progress, context.state.raw_progress);
+        assert_eq!(restored.state.finished_at, context.state.finished_at);
+        assert_eq!(restored.state.timezone, context.state.timezone);
+        assert_eq!(restored.state.next_reset, context.state.next_reset);
+        assert_eq!(restored.state.day_targets, vec![100, 100]);
+        assert_eq!(restored.state.reminder_hour, Some(15));
+        assert!(!restored.state.silent);
+        assert_eq!(restored.state.locale, Locale::En);
+        assert_eq!(restored.state.mode, context.state.mode);
+        assert_eq!(
+            restored.state.targets_per_user,
+            context.state.targets_per_user
+        );
+    }
+
+    #[test]
+    fn update_offset_is_kept() {
+        let store = SqliteStore::open(":memory:").unwrap();
+
+        assert_eq!(store.load_update_offset(), None);
+
+        store.save_update_offset(10).unwrap();
+        store.save_update_offset(11).unwrap();
+
+        assert_eq!(store.load_update_offset(), Some(11));
+    }
+}
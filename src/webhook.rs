@@ -0,0 +1,312 @@
+use crate::client::BotClient;
+use crate::context::Contexts;
+use crate::process_update;
+use frankenstein::{SetWebhookParamsBuilder, Update};
+use hyper::body::HttpBody;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use log::{debug, error, info, warn};
+use std::convert::Infallible;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+
+pub const DEFAULT_WEBHOOK_ADDR: &str = "0.0.0.0:8080";
+pub const DEFAULT_WEBHOOK_PATH: &str = "/webhook";
+
+/// The header Telegram sends the secret registered with the webhook in.
+const SECRET_HEADER: &str = "x-telegram-bot-api-secret-token";
+
+/// Telegram updates are small; anything larger isn't one.
+const MAX_BODY_LENGTH: usize = 1024 * 1024;
+
+/// The smallest read buffer hyper accepts, which is plenty for the headers
+/// Telegram sends.
+const MAX_HEADER_LENGTH: usize = 8192;
+
+/// How long a client gets to send the headers, and then the body.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Receives updates pushed by Telegram instead of polling for them.
+///
+/// The server speaks plain HTTP and is meant to sit behind a proxy that
+/// terminates TLS. `WEBHOOK_ADDR` is where it listens, `WEBHOOK_PATH` the path
+/// Telegram posts to and `WEBHOOK_URL`, when set, is registered with Telegram
+/// on start. `WEBHOOK_SECRET` is required: it is registered along with the
+/// URL, and requests that don't carry it are refused.
+pub async fn serve_webhook(
+    api: Arc<dyn BotClient>,
+    contexts: Arc<Mutex<Contexts>>,
+    allowed_updates: Vec<String>,
+) {
+    let addr = env::var("WEBHOOK_ADDR").unwrap_or_else(|_| DEFAULT_WEBHOOK_ADDR.to_string());
+    let path = env::var("WEBHOOK_PATH").unwrap_or_else(|_| DEFAULT_WEBHOOK_PATH.to_string());
+
+    let secret = match env::var("WEBHOOK_SECRET") {
+        Ok(secret) if !secret.is_empty() => secret,
+        _ => {
+            error!("WEBHOOK_SECRET not set, refusing to take updates from anyone");
+            return;
+        }
+    };
+
+    match env::var("WEBHOOK_URL") {
+        Ok(url) => register_webhook(&api, url, allowed_updates, &secret).await,
+        Err(_) => warn!("WEBHOOK_URL not set, expecting the webhook to be registered already"),
+    }
+
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Failed to listen on {}: {}", addr, err);
+            return;
+        }
+    };
+
+    info!("Waiting for updates on {}{}", addr, path);
+
+    let webhook = Webhook {
+        api,
+        contexts,
+        path,
+        secret,
+        read_timeout: READ_TIMEOUT,
+    };
+
+    serve(listener, Arc::new(webhook)).await;
+}
+
+async fn register_webhook(
+    api: &Arc<dyn BotClient>,
+    url: String,
+    allowed_updates: Vec<String>,
+    secret: &str,
+) {
+    let params = SetWebhookParamsBuilder::default()
+        .url(url)
+        .allowed_updates(allowed_updates)
+        // One at a time, so that a chat's messages arrive in order.
+        .max_connections(1_u32)
+        .build()
+        .unwrap();
+
+    if let Err(err) = api.set_webhook(&params, secret).await {
+        error!("Failed to register the webhook: {:?}", err);
+    }
+}
+
+struct Webhook {
+    api: Arc<dyn BotClient>,
+    contexts: Arc<Mutex<Contexts>>,
+    path: String,
+    secret: String,
+    read_timeout: Duration,
+}
+
+/// Serves one request per connection. Connections that don't send their
+/// headers in time, or send too many of them, are dropped.
+async fn serve(listener: TcpListener, webhook: Arc<Webhook>) {
+    let mut http = Http::new();
+    http.http1_only(true)
+        .http1_keep_alive(false)
+        .max_buf_size(MAX_HEADER_LENGTH)
+        .http1_header_read_timeout(webhook.read_timeout);
+
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(err) => {
+                warn!("Failed to accept a connection: {}", err);
+                continue;
+            }
+        };
+
+        let http = http.clone();
+        let webhook = Arc::clone(&webhook);
+
+        tokio::spawn(async move {
+            // hyper only starts timing the headers once the first byte is in.
+            match timeout(webhook.read_timeout, stream.peek(&mut [0])).await {
+                Ok(Ok(read)) if read > 0 => {}
+                _ => return,
+            }
+
+            let service = {
+                let webhook = Arc::clone(&webhook);
+                service_fn(move |request| {
+                    let webhook = Arc::clone(&webhook);
+                    async move { Ok::<_, Infallible>(webhook.handle(request).await) }
+                })
+            };
+
+            if let Err(err) = http.serve_connection(stream, service).await {
+                debug!("Webhook connection failed: {}", err);
+            }
+        });
+    }
+}
+
+impl Webhook {
+    /// The update is processed before answering, so that Telegram doesn't
+    /// send the next one until this one went through.
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        if request.method() != Method::POST || request.uri().path() != self.path {
+            return status(StatusCode::NOT_FOUND);
+        }
+
+        let secret = request.headers().get(SECRET_HEADER);
+        if secret.map(|secret| secret.as_bytes()) != Some(self.secret.as_bytes()) {
+            warn!("Refused an update without the webhook secret");
+            return status(StatusCode::UNAUTHORIZED);
+        }
+
+        let body = match timeout(self.read_timeout, read_body(request.into_body())).await {
+            Ok(Ok(body)) => body,
+            Ok(Err(code)) => return status(code),
+            Err(_) => return status(StatusCode::REQUEST_TIMEOUT),
+        };
+
+        match serde_json::from_slice::<Update>(&body) {
+            Ok(update) => {
+                process_update(update, &self.contexts, &self.api).await;
+                status(StatusCode::OK)
+            }
+            Err(err) => {
+                warn!("Failed to decode an update: {}", err);
+                status(StatusCode::BAD_REQUEST)
+            }
+        }
+    }
+}
+
+async fn read_body(mut body: Body) -> Result<Vec<u8>, StatusCode> {
+    let mut bytes = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        if bytes.len() + chunk.len() > MAX_BODY_LENGTH {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+fn status(code: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = code;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockClient;
+    use crate::config::Config;
+    use crate::storage::{SqliteStore, Store};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    const SECRET: &str = "s3cret";
+
+    fn webhook() -> Arc<Webhook> {
+        let api: Arc<dyn BotClient> = Arc::new(MockClient::default());
+        let store: Arc<dyn Store> = Arc::new(SqliteStore::open(":memory:").unwrap());
+        let contexts = Contexts::new(api.clone(), Config::default(), store);
+
+        Arc::new(Webhook {
+            api,
+            contexts: Arc::new(Mutex::new(contexts)),
+            path: DEFAULT_WEBHOOK_PATH.to_string(),
+            secret: SECRET.to_string(),
+            read_timeout: Duration::from_millis(200),
+        })
+    }
+
+    fn post(secret: Option<&str>, body: impl Into<Body>) -> Request<Body> {
+        let mut request = Request::post(DEFAULT_WEBHOOK_PATH);
+        if let Some(secret) = secret {
+            request = request.header(SECRET_HEADER, secret);
+        }
+        request.body(body.into()).unwrap()
+    }
+
+    /// Serves `webhook` on a free port and sends `raw` to it, returning
+    /// whatever comes back before the server hangs up.
+    async fn exchange(raw: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, webhook()));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(raw).await.unwrap();
+        let mut response = Vec::new();
+        let read = timeout(Duration::from_secs(5), stream.read_to_end(&mut response)).await;
+        server.abort();
+
+        assert!(read.is_ok(), "the connection was kept open");
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    #[tokio::test]
+    async fn updates_without_the_secret_are_refused() {
+        let webhook = webhook();
+        let update = r#"{"update_id":1}"#;
+
+        let missing = webhook.handle(post(None, update)).await;
+        let wrong = webhook.handle(post(Some("guess"), update)).await;
+
+        assert_eq!(missing.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn only_the_webhook_path_is_served() {
+        let request = Request::post("/other")
+            .header(SECRET_HEADER, SECRET)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = webhook().handle(request).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn oversized_bodies_are_refused() {
+        let body = vec![b' '; MAX_BODY_LENGTH + 1];
+
+        let response = webhook().handle(post(Some(SECRET), body)).await;
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn oversized_headers_are_refused() {
+        let raw = format!(
+            "POST /webhook HTTP/1.1\r\nX-Padding: {}\r\n\r\n",
+            "a".repeat(MAX_HEADER_LENGTH)
+        );
+
+        let response = exchange(raw.as_bytes()).await;
+
+        assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn silent_connections_are_dropped() {
+        assert_eq!(exchange(b"").await, "");
+    }
+
+    #[tokio::test]
+    async fn stalled_headers_are_dropped() {
+        let response = exchange(b"POST /webhook HTTP/1.1\r\nHost: bot\r\n").await;
+
+        assert!(!response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+}
@@ -0,0 +1,2850 @@
+pub mod legacy;
+
+use crate::config::Config;
+use crate::messages::{Locale, Text};
+use chrono::{DateTime, Datelike, Duration as ChronoDuration, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use frankenstein::User;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Layout {
+    /// One line per user with their count and the target.
+    Compact,
+    /// Adds a progress bar and the change since yesterday.
+    Detailed,
+}
+
+/// How the challenge is scored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum WorkoutMode {
+    /// Everyone has a target every day.
+    #[default]
+    Daily,
+    /// The group works towards one total at any pace; the challenge ends
+    /// once it's reached or `duration` runs out.
+    TotalGoal { target: usize },
+}
+
+/// What happens once a target schedule has no entry for the next day.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum ScheduleEnd {
+    /// Keep the last target until `duration` runs out.
+    #[default]
+    Hold,
+    /// End the challenge after the last scheduled day.
+    Finish,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Gift {
+    pub day: usize,
+    pub from: i64,
+    pub to: i64,
+    pub count: usize,
+}
+
+/// A new cycle length taking over on `day`, with the cycle it ended, so that
+/// going back over the change can restore it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CycleChange {
+    pub day: usize,
+    pub previous_start: usize,
+    pub previous_length: usize,
+}
+
+/// Reps as they were actually applied by a single log, after clamping.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+    pub user_id: i64,
+    pub raw_count: isize,
+    pub weighted_count: isize,
+}
+
+/// A chat member taking part in the challenge. Progress is keyed by `id`;
+/// `display_name` is only used for messages and may change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participant {
+    pub id: i64,
+    pub display_name: String,
+}
+
+impl Participant {
+    /// Uses the username when there is one, otherwise the first name with the
+    /// id so that people sharing a first name can be told apart.
+    pub fn from_user(user: &User) -> Self {
+        let display_name = match &user.username {
+            Some(username) => username.clone(),
+            None => format!("{} ({})", user.first_name, user.id),
+        };
+
+        Self {
+            id: user.id as i64,
+            display_name,
+        }
+    }
+}
+
+/// Everything about a chat's challenge that doesn't need Telegram: who takes
+/// part, their progress and the targets. Sending the results is up to
+/// `ContextData`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkoutState {
+    pub chat_id: i64,
+    /// Tells apart challenges running side by side in one chat. The one
+    /// started with `/start` is 0.
+    #[serde(default)]
+    pub challenge_id: u32,
+    pub daily_message_id: Option<i32>,
+    /// Zero-based index into `progress`; users see it as `current_day + 1`.
+    pub current_day: usize,
+    /// Set once the first daily message has been posted.
+    pub started: bool,
+    pub cycle_length: usize,
+    /// Day on which the current cycle began.
+    #[serde(default)]
+    pub cycle_start: usize,
+    /// Length set mid-cycle; it takes over at the next cycle boundary.
+    #[serde(default)]
+    pub next_cycle_length: Option<usize>,
+    /// Every time `next_cycle_length` took over, oldest first.
+    #[serde(default)]
+    pub cycle_changes: Vec<CycleChange>,
+    pub cycle_increase: isize,
+    pub min_repeats: usize,
+    /// Targets for each day, starting with the first. Replaces the cycle
+    /// math while set.
+    #[serde(default)]
+    pub schedule: Option<Vec<usize>>,
+    #[serde(default)]
+    pub schedule_end: ScheduleEnd,
+    pub duration: usize,
+    pub repeats: usize,
+    /// Reps multiplied by their difficulty; this is what counts toward the target.
+    #[serde(deserialize_with = "legacy::progress")]
+    pub progress: Vec<HashMap<i64, usize>>,
+    /// Reps as they were actually done, without difficulty multipliers.
+    #[serde(deserialize_with = "legacy::progress")]
+    pub raw_progress: Vec<HashMap<i64, usize>>,
+    pub day_notes: Vec<Option<String>>,
+    /// The target of each day, recorded when the day starts, so that later
+    /// cycle changes don't rewrite whether an earlier day was met.
+    #[serde(default)]
+    pub day_targets: Vec<usize>,
+    /// Personal targets that replace the common one, for mixed-ability
+    /// groups. They stay fixed while the common target follows the cycles.
+    #[serde(default)]
+    pub targets_per_user: HashMap<i64, usize>,
+    #[serde(deserialize_with = "legacy::finished_at")]
+    pub finished_at: HashMap<usize, HashMap<i64, DateTime<Utc>>>,
+    #[serde(deserialize_with = "legacy::users")]
+    pub users: Vec<Participant>,
+    /// Users who left. Their earlier days stay, and so does the name those
+    /// days are shown under.
+    #[serde(default)]
+    pub left: Vec<Participant>,
+    /// Delete messages that are neither rep logs nor commands.
+    pub clean_chat: bool,
+    /// Whether users may move reps to each other with `/give`.
+    pub allow_gifts: bool,
+    /// Only count reps sent as a reply to the daily message. If that message
+    /// is deleted, nothing counts until the next one is posted.
+    #[serde(default)]
+    pub strict_input: bool,
+    /// Send everything without a notification. When off, the final and
+    /// end-of-cycle messages notify; the daily message never does.
+    #[serde(default = "default_silent")]
+    pub silent: bool,
+    /// The language of the challenge's messages.
+    #[serde(default)]
+    pub locale: Locale,
+    #[serde(default)]
+    pub mode: WorkoutMode,
+    /// Holds the days still, for holidays and sick days, so they break
+    /// nobody's streak.
+    #[serde(default)]
+    pub paused: bool,
+    /// Whether paused days push the end back. When off, each one comes off
+    /// the duration and the challenge ends on the same date.
+    #[serde(default = "default_pause_extends")]
+    pub pause_extends: bool,
+    /// A weekday without reps. Like a paused day it doesn't advance, so it
+    /// counts toward neither the duration nor anyone's streak.
+    #[serde(default)]
+    pub rest_weekday: Option<Weekday>,
+    /// Whether today is the rest day, as of the last reset.
+    #[serde(default)]
+    pub resting: bool,
+    #[serde(deserialize_with = "legacy::gifts")]
+    pub gifts: Vec<Gift>,
+    /// Today's most recent rep log, for `/undo`. Not kept across restarts.
+    #[serde(skip)]
+    pub last_entry: Option<Entry>,
+    /// Today's rep logs by message id, for edits. Not kept across restarts.
+    #[serde(skip)]
+    pub logged_messages: HashMap<i32, Entry>,
+    /// The last day on which we reminded an empty chat how to log reps.
+    #[serde(skip)]
+    pub nudged_on: Option<usize>,
+    /// Whether today's "everyone is done" message went out already.
+    #[serde(skip)]
+    pub all_done_announced: bool,
+    pub layout: Layout,
+    /// What is being counted, in the genitive plural ("отжиманий").
+    #[serde(default = "default_exercise")]
+    pub exercise: String,
+    /// Local hour at which a new day starts.
+    #[serde(default)]
+    pub reset_hour: u32,
+    #[serde(default = "default_timezone")]
+    pub timezone: Tz,
+    /// When the next daily message is due.
+    #[serde(default)]
+    pub next_reset: Option<DateTime<Utc>>,
+    /// Local hour at which users still below the target are reminded.
+    #[serde(default)]
+    pub reminder_hour: Option<u32>,
+}
+
+/// A chat id and the challenge id within that chat.
+pub type ChallengeKey = (i64, u32);
+
+/// Keeps `/history` well below Telegram's message length limit.
+pub const MAX_HISTORY_DAYS: usize = 60;
+
+/// Days shown in the daily message's activity line.
+pub const SPARKLINE_DAYS: usize = 7;
+
+fn default_exercise() -> String {
+    Text::DefaultExercise.format(Locale::default())
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+fn default_silent() -> bool {
+    true
+}
+
+fn default_pause_extends() -> bool {
+    true
+}
+
+impl WorkoutState {
+    pub fn new(chat_id: i64, config: &Config) -> Self {
+        Self {
+            chat_id,
+            challenge_id: 0,
+            daily_message_id: None,
+            cycle_increase: config.cycle_increase,
+            min_repeats: config.min_repeats,
+            schedule: None,
+            schedule_end: ScheduleEnd::Hold,
+            cycle_length: config.cycle_length,
+            cycle_start: 0,
+            next_cycle_length: None,
+            cycle_changes: vec![],
+            current_day: 0,
+            started: false,
+            progress: vec![HashMap::new()],
+            raw_progress: vec![HashMap::new()],
+            day_notes: vec![None],
+            day_targets: vec![config.repeats],
+            targets_per_user: HashMap::new(),
+            finished_at: HashMap::new(),
+            duration: config.duration,
+            repeats: config.repeats,
+            users: vec![],
+            left: vec![],
+            clean_chat: false,
+            allow_gifts: true,
+            strict_input: false,
+            silent: true,
+            locale: Locale::default(),
+            mode: WorkoutMode::Daily,
+            paused: false,
+            pause_extends: true,
+            rest_weekday: None,
+            resting: false,
+            gifts: vec![],
+            last_entry: None,
+            logged_messages: HashMap::new(),
+            nudged_on: None,
+            all_done_announced: false,
+            layout: Layout::Compact,
+            exercise: default_exercise(),
+            reset_hour: 0,
+            timezone: default_timezone(),
+            next_reset: None,
+            reminder_hour: None,
+        }
+    }
+
+    pub fn key(&self) -> ChallengeKey {
+        (self.chat_id, self.challenge_id)
+    }
+
+    pub fn is_user_done(&self, user_id: i64) -> bool {
+        self.met_target(user_id, self.current_day)
+    }
+
+    /// Always false towards a total goal, where there are no daily targets to
+    /// meet, so done marks, streaks and celebrations stay out of the way.
+    fn met_target(&self, user_id: i64, day: usize) -> bool {
+        self.mode == WorkoutMode::Daily
+            && self.count_on(user_id, day) >= self.user_target(user_id, day)
+    }
+
+    /// Counted reps of the whole group over all days.
+    pub fn group_total(&self) -> usize {
+        saturating_sum(
+            self.progress
+                .iter()
+                .flat_map(|day_progress| day_progress.values()),
+        )
+    }
+
+    pub fn is_goal_reached(&self) -> bool {
+        match self.mode {
+            WorkoutMode::TotalGoal { target } => self.group_total() >= target,
+            WorkoutMode::Daily => false,
+        }
+    }
+
+    /// On how many days of the challenge the user met that day's target.
+    pub fn days_met(&self, user_id: i64) -> usize {
+        (0..self.days_done())
+            .filter(|day| self.met_target(user_id, *day))
+            .count()
+    }
+
+    pub fn is_all_users_done(&self) -> bool {
+        for user in &self.users {
+            if !self.is_user_done(user.id) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// True the first time a day sees everyone done, so that later logs from
+    /// finished users don't repeat the celebration.
+    pub fn announce_all_done(&mut self) -> bool {
+        if self.all_done_announced || !self.is_all_users_done() {
+            return false;
+        }
+
+        self.all_done_announced = true;
+
+        true
+    }
+
+    pub fn find_user_by_name(&self, display_name: &str) -> Option<&Participant> {
+        self.users
+            .iter()
+            .find(|user| user.display_name == display_name)
+    }
+
+    pub fn display_name(&self, user_id: i64) -> String {
+        match self
+            .users
+            .iter()
+            .chain(&self.left)
+            .find(|user| user.id == user_id)
+        {
+            Some(user) => user.display_name.clone(),
+            None => user_id.to_string(),
+        }
+    }
+
+    pub fn add_user_progress(&mut self, participant: Participant, count: i64) {
+        self.add_weighted_user_progress(participant, count, 1.0);
+    }
+
+    /// Adds (or, for a negative `count`, takes away) today's reps. The day
+    /// total never goes below zero.
+    pub fn add_weighted_user_progress(
+        &mut self,
+        participant: Participant,
+        count: i64,
+        weight: f64,
+    ) {
+        self.ensure_day_slots();
+        let current_day = self.current_day;
+        let weighted_count = (count as f64 * weight).round() as isize;
+        let count = to_delta(count);
+        let user_id = participant.id;
+
+        self.join(participant);
+
+        let raw_count = add_clamped(&mut self.raw_progress[current_day], user_id, count);
+        let weighted_count = add_clamped(&mut self.progress[current_day], user_id, weighted_count);
+
+        self.last_entry = Some(Entry {
+            user_id,
+            raw_count,
+            weighted_count,
+        });
+    }
+
+    /// Adds the participant to the challenge, or refreshes their display name
+    /// if they are already in. Returns whether they were new.
+    pub fn join(&mut self, participant: Participant) -> bool {
+        self.claim_legacy_user(&participant);
+        self.left.retain(|user| user.id != participant.id);
+
+        match self.users.iter_mut().find(|user| user.id == participant.id) {
+            Some(user) => {
+                user.display_name = participant.display_name;
+                false
+            }
+            None => {
+                self.users.push(participant);
+                true
+            }
+        }
+    }
+
+    /// Moves everything saved under a username from an old save over to the
+    /// user now logging under that name.
+    fn claim_legacy_user(&mut self, participant: &Participant) {
+        let legacy_id = match self.users.iter().find(|user| {
+            legacy::is_legacy_id(user.id) && user.display_name == participant.display_name
+        }) {
+            Some(user) => user.id,
+            None => return,
+        };
+
+        if self.users.iter().any(|user| user.id == participant.id) {
+            return;
+        }
+
+        let id = participant.id;
+        let rekey = |values: &mut HashMap<i64, usize>| {
+            if let Some(value) = values.remove(&legacy_id) {
+                values.insert(id, value);
+            }
+        };
+
+        self.progress.iter_mut().for_each(rekey);
+        self.raw_progress.iter_mut().for_each(rekey);
+        rekey(&mut self.targets_per_user);
+
+        for finished in self.finished_at.values_mut() {
+            if let Some(time) = finished.remove(&legacy_id) {
+                finished.insert(id, time);
+            }
+        }
+
+        for gift in &mut self.gifts {
+            if gift.from == legacy_id {
+                gift.from = id;
+            }
+            if gift.to == legacy_id {
+                gift.to = id;
+            }
+        }
+
+        for user in &mut self.users {
+            if user.id == legacy_id {
+                user.id = id;
+            }
+        }
+    }
+
+    /// Takes the user out of the listings along with today's reps. Earlier
+    /// days stay as they were. Returns whether they were taking part.
+    pub fn leave(&mut self, user_id: i64) -> bool {
+        let index = match self.users.iter().position(|user| user.id == user_id) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.left.push(self.users.remove(index));
+
+        self.ensure_day_slots();
+        self.progress[self.current_day].remove(&user_id);
+        self.raw_progress[self.current_day].remove(&user_id);
+
+        if self
+            .last_entry
+            .is_some_and(|entry| entry.user_id == user_id)
+        {
+            self.last_entry = None;
+        }
+
+        true
+    }
+
+    /// Takes the user out of the challenge for good: unlike `leave`, every
+    /// day's reps go too. Returns whether they were taking part.
+    pub fn remove_participant(&mut self, user_id: i64) -> bool {
+        if !self.leave(user_id) {
+            return false;
+        }
+
+        for day_progress in self.progress.iter_mut().chain(&mut self.raw_progress) {
+            day_progress.remove(&user_id);
+        }
+
+        for finished in self.finished_at.values_mut() {
+            finished.remove(&user_id);
+        }
+
+        self.left.retain(|user| user.id != user_id);
+        self.targets_per_user.remove(&user_id);
+        self.gifts
+            .retain(|gift| gift.from != user_id && gift.to != user_id);
+        self.logged_messages
+            .retain(|_, logged| logged.user_id != user_id);
+
+        true
+    }
+
+    /// Takes back the most recent rep log of today, if there is one.
+    pub fn undo_last_entry(&mut self) -> Option<Entry> {
+        let entry = self.last_entry.take()?;
+        self.logged_messages.retain(|_, logged| *logged != entry);
+        self.ensure_day_slots();
+        let current_day = self.current_day;
+
+        add_clamped(
+            &mut self.raw_progress[current_day],
+            entry.user_id,
+            entry.raw_count.saturating_neg(),
+        );
+        add_clamped(
+            &mut self.progress[current_day],
+            entry.user_id,
+            entry.weighted_count.saturating_neg(),
+        );
+
+        if !self.is_user_done(entry.user_id) {
+            if let Some(finishers) = self.finished_at.get_mut(&current_day) {
+                finishers.remove(&entry.user_id);
+            }
+        }
+
+        Some(entry)
+    }
+
+    /// Ties the latest log to the message it came in, for later edits.
+    pub fn remember_message(&mut self, message_id: i32) {
+        if let Some(entry) = self.last_entry {
+            self.logged_messages.insert(message_id, entry);
+        }
+    }
+
+    /// Changes what a log of today counted to `count` reps, by the difference
+    /// between the two, clamped like any other log. Returns the difference
+    /// actually applied, or `None` if the message wasn't one of today's logs.
+    pub fn edit_logged_message(
+        &mut self,
+        message_id: i32,
+        count: i64,
+        weight: f64,
+    ) -> Option<Entry> {
+        self.ensure_day_slots();
+        let current_day = self.current_day;
+        let logged = self.logged_messages.get_mut(&message_id)?;
+        let before = *logged;
+        let weighted_count = (count as f64 * weight).round() as isize;
+
+        let raw_delta = add_clamped(
+            &mut self.raw_progress[current_day],
+            logged.user_id,
+            to_delta(count).saturating_sub(logged.raw_count),
+        );
+        let weighted_delta = add_clamped(
+            &mut self.progress[current_day],
+            logged.user_id,
+            weighted_count.saturating_sub(logged.weighted_count),
+        );
+        logged.raw_count = logged.raw_count.saturating_add(raw_delta);
+        logged.weighted_count = logged.weighted_count.saturating_add(weighted_delta);
+
+        if self.last_entry == Some(before) {
+            self.last_entry = Some(*logged);
+        }
+
+        let delta = Entry {
+            user_id: logged.user_id,
+            raw_count: raw_delta,
+            weighted_count: weighted_delta,
+        };
+
+        if !self.is_user_done(delta.user_id) {
+            if let Some(finishers) = self.finished_at.get_mut(&current_day) {
+                finishers.remove(&delta.user_id);
+            }
+        }
+
+        Some(delta)
+    }
+
+    /// Replaces the target the challenge starts with. Only allowed on the
+    /// first day and without a schedule, which sets the targets itself. The
+    /// first cycle keeps this target; `cycle_increase` applies on top of it
+    /// at the first cycle boundary, as it would to the configured one.
+    pub fn set_start_repeats(&mut self, repeats: usize) -> Result<(), String> {
+        if repeats == 0 {
+            return Err(self.text(Text::StartRepeatsZero));
+        }
+
+        if self.current_day > 0 {
+            return Err(self.text(Text::StartRepeatsLate));
+        }
+
+        if self.schedule.is_some() {
+            return Err(self.text(Text::StartRepeatsScheduled));
+        }
+
+        self.repeats = repeats;
+        self.day_targets = vec![repeats];
+
+        Ok(())
+    }
+
+    /// Moves today's reps from one user to another. Only the counted (weighted)
+    /// progress moves, so the group total stays the same.
+    pub fn give_reps(&mut self, from: i64, to: i64, count: usize) -> Result<(), String> {
+        if from == to {
+            return Err(self.text(Text::GiftToSelf));
+        }
+
+        if count == 0 {
+            return Err(self.text(Text::NothingToGive));
+        }
+
+        if !self.users.iter().any(|user| user.id == to) {
+            return Err(self.text(Text::NotJoinedYet(&self.display_name(to))));
+        }
+
+        self.ensure_day_slots();
+        let day_progress = &mut self.progress[self.current_day];
+        let available = *day_progress.get(&from).unwrap_or(&0);
+
+        if available < count {
+            return Err(self.text(Text::GiftTooLarge(available)));
+        }
+
+        day_progress.insert(from, available - count);
+        let received = day_progress.entry(to).or_insert(0);
+        *received = received.saturating_add(count);
+
+        self.gifts.push(Gift {
+            day: self.current_day,
+            from,
+            to,
+            count,
+        });
+
+        Ok(())
+    }
+
+    /// Remembers when the user first reached today's target.
+    pub fn mark_user_finished(&mut self, user_id: i64, at: DateTime<Utc>) {
+        self.finished_at
+            .entry(self.current_day)
+            .or_default()
+            .entry(user_id)
+            .or_insert(at);
+    }
+
+    pub fn add_day_note(&mut self, text: String) {
+        self.ensure_day_slots();
+        let note = &mut self.day_notes[self.current_day];
+
+        *note = match note.take() {
+            Some(existing) => Some(format!("{}; {}", existing, text)),
+            None => Some(text),
+        };
+    }
+
+    /// Starts the challenge on the first call and moves to the next day after
+    /// that. Returns the previous target when a cycle boundary changed it.
+    pub fn start_next_day(&mut self) -> Option<usize> {
+        if !self.started {
+            self.started = true;
+
+            return None;
+        }
+
+        self.init_next_day()
+    }
+
+    /// Advances to the next day. When a cycle ends and the target changes,
+    /// returns the target of the previous cycle.
+    pub fn init_next_day(&mut self) -> Option<usize> {
+        self.current_day += 1;
+        self.last_entry = None;
+        self.logged_messages.clear();
+        self.all_done_announced = false;
+        self.ensure_day_slots();
+
+        // The daily message shows scheduled targets, they don't need the
+        // end-of-cycle announcement.
+        if let Some(schedule) = &self.schedule {
+            if let Some(repeats) = schedule.get(self.current_day).or(schedule.last()) {
+                self.repeats = *repeats;
+            }
+            self.record_day_target();
+
+            return None;
+        }
+
+        if self.mode != WorkoutMode::Daily {
+            self.record_day_target();
+
+            return None;
+        }
+
+        let previous_repeats = self.repeats;
+
+        if (self.current_day - self.cycle_start).is_multiple_of(self.cycle_length) {
+            if let Some(cycle_length) = self.next_cycle_length.take() {
+                self.cycle_changes.push(CycleChange {
+                    day: self.current_day,
+                    previous_start: self.cycle_start,
+                    previous_length: self.cycle_length,
+                });
+                self.cycle_length = cycle_length;
+                self.cycle_start = self.current_day;
+            }
+
+            let repeats = (self.repeats as isize)
+                .saturating_add(self.cycle_increase)
+                .max(self.min_repeats as isize);
+            self.repeats = repeats as usize;
+        }
+
+        self.record_day_target();
+
+        if self.repeats == previous_repeats {
+            return None;
+        }
+
+        Some(previous_repeats)
+    }
+
+    /// Starts the challenge over from day 0. Settings stay; progress, users
+    /// with their personal targets and the daily message are dropped, and the
+    /// target goes back to day 0's.
+    pub fn reset(&mut self) {
+        let repeats = match self.schedule.as_ref().and_then(|schedule| schedule.first()) {
+            Some(first) => *first,
+            None => self.day_target(0),
+        };
+
+        if let Some(cycle_length) = self.next_cycle_length.take() {
+            self.cycle_length = cycle_length;
+        }
+
+        self.repeats = repeats;
+        self.day_targets = vec![repeats];
+        self.current_day = 0;
+        self.cycle_start = 0;
+        self.cycle_changes.clear();
+        self.started = false;
+        self.daily_message_id = None;
+        self.progress = vec![HashMap::new()];
+        self.raw_progress = vec![HashMap::new()];
+        self.day_notes = vec![None];
+        self.finished_at.clear();
+        self.users.clear();
+        self.left.clear();
+        self.targets_per_user.clear();
+        self.gifts.clear();
+        self.last_entry = None;
+        self.logged_messages.clear();
+        self.nudged_on = None;
+        self.all_done_announced = false;
+    }
+
+    /// Moves the challenge to `day`, counted from 1, to fix it after downtime
+    /// or a mistake. Forward it goes through every day in between, so targets
+    /// and cycles come out as if the days had passed. Back, the later days
+    /// are dropped along with their reps, finishers and gifts, and cycle
+    /// lengths that took over on them are pending again.
+    pub fn set_day(&mut self, day: usize) -> Result<(), String> {
+        if !self.started {
+            return Err(self.text(Text::NotStarted));
+        }
+
+        if day == 0 || day > self.duration {
+            return Err(self.text(Text::DayRange(self.duration)));
+        }
+
+        let day = day - 1;
+
+        while self.current_day < day {
+            self.init_next_day();
+        }
+
+        if self.current_day > day {
+            self.current_day = day;
+            self.ensure_day_slots();
+            self.progress.truncate(day + 1);
+            self.raw_progress.truncate(day + 1);
+            self.day_notes.truncate(day + 1);
+            self.day_targets.truncate(day + 1);
+            self.finished_at
+                .retain(|finished_day, _| *finished_day <= day);
+            self.gifts.retain(|gift| gift.day <= day);
+            self.repeats = self.day_target(day);
+
+            while let Some(change) = self.cycle_changes.last().filter(|change| change.day > day) {
+                self.next_cycle_length = Some(self.cycle_length);
+                self.cycle_length = change.previous_length;
+                self.cycle_start = change.previous_start;
+                self.cycle_changes.pop();
+            }
+
+            self.last_entry = None;
+            self.logged_messages.clear();
+            self.all_done_announced = false;
+        }
+
+        Ok(())
+    }
+
+    /// Sets or clears the target schedule. A running challenge picks it up from
+    /// the next day on; before the start it sets the first day's target too.
+    pub fn set_schedule(&mut self, schedule: Option<Vec<usize>>, end: ScheduleEnd) {
+        self.schedule_end = end;
+
+        if let (false, Some(first)) = (self.started, schedule.as_ref().and_then(|s| s.first())) {
+            self.repeats = *first;
+            self.day_targets = vec![*first];
+        }
+
+        self.schedule = schedule;
+    }
+
+    /// Makes sure the per-day vectors reach `current_day`. They grow together
+    /// with it, but a snapshot edited by hand or saved by an older version may
+    /// fall behind, and indexing past the end would take the handler down.
+    pub fn ensure_day_slots(&mut self) {
+        let days = self.current_day + 1;
+
+        if self.progress.len() < days {
+            self.progress.resize_with(days, HashMap::new);
+        }
+        if self.raw_progress.len() < days {
+            self.raw_progress.resize_with(days, HashMap::new);
+        }
+        if self.day_notes.len() < days {
+            self.day_notes.resize(days, None);
+        }
+    }
+
+    /// The user's counted reps on `day`; zero for days without a record.
+    pub fn count_on(&self, user_id: i64, day: usize) -> usize {
+        self.progress
+            .get(day)
+            .and_then(|day_progress| day_progress.get(&user_id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn raw_count_on(&self, user_id: i64, day: usize) -> usize {
+        self.raw_progress
+            .get(day)
+            .and_then(|day_progress| day_progress.get(&user_id))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn record_day_target(&mut self) {
+        self.day_targets.resize(self.current_day, self.repeats);
+        self.day_targets.push(self.repeats);
+    }
+
+    /// The user's own target if they have one, otherwise the day's.
+    pub fn user_target(&self, user_id: i64, day: usize) -> usize {
+        self.targets_per_user
+            .get(&user_id)
+            .copied()
+            .unwrap_or_else(|| self.day_target(day))
+    }
+
+    /// The target that applied on `day`. Days from before targets were
+    /// recorded fall back to the current one.
+    pub fn day_target(&self, day: usize) -> usize {
+        self.day_targets.get(day).copied().unwrap_or(self.repeats)
+    }
+
+    /// Days in a row, up to today, on which the user met that day's target.
+    /// Today only breaks the streak once it's over, so a user who hasn't
+    /// logged yet this morning keeps yesterday's streak.
+    pub fn user_streak(&self, user_id: i64) -> usize {
+        let met = |day: usize| self.met_target(user_id, day);
+
+        let last_day = if met(self.current_day) {
+            self.current_day
+        } else {
+            match self.current_day.checked_sub(1) {
+                Some(yesterday) => yesterday,
+                None => return 0,
+            }
+        };
+
+        (0..=last_day).rev().take_while(|day| met(*day)).count()
+    }
+
+    pub fn is_workout_over(&self) -> bool {
+        let schedule_over = self.schedule_end == ScheduleEnd::Finish
+            && self
+                .schedule
+                .as_ref()
+                .is_some_and(|schedule| self.current_day + 1 >= schedule.len());
+
+        self.started
+            && (self.current_day + 1 >= self.duration || schedule_over || self.is_goal_reached())
+    }
+
+    /// The first reset strictly after `after`, at `reset_hour` local time.
+    pub fn next_reset_after(&self, after: DateTime<Utc>) -> DateTime<Utc> {
+        self.next_local_hour_after(after, self.reset_hour)
+    }
+
+    /// When the next reminder is due, if reminders are on.
+    pub fn next_reminder_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.reminder_hour
+            .map(|hour| self.next_local_hour_after(after, hour))
+    }
+
+    /// The first time strictly after `after` that the chat's clock shows
+    /// `hour`:00. When that hour is skipped by a DST change it moves an hour
+    /// later.
+    fn next_local_hour_after(&self, after: DateTime<Utc>, hour: u32) -> DateTime<Utc> {
+        let mut date = after.with_timezone(&self.timezone).date().naive_local();
+
+        loop {
+            let at = date.and_hms(hour, 0, 0);
+            let at = self
+                .timezone
+                .from_local_datetime(&at)
+                .earliest()
+                .or_else(|| {
+                    self.timezone
+                        .from_local_datetime(&(at + ChronoDuration::hours(1)))
+                        .earliest()
+                });
+
+            if let Some(at) = at {
+                let at = at.with_timezone(&Utc);
+
+                if at > after {
+                    return at;
+                }
+            }
+
+            date = date.succ();
+        }
+    }
+
+    /// Whether to remind the chat how to log reps: a day has passed and
+    /// nobody took part. Returns true at most once per day.
+    pub fn should_nudge(&mut self) -> bool {
+        if self.current_day == 0
+            || !self.users.is_empty()
+            || self.nudged_on == Some(self.current_day)
+        {
+            return false;
+        }
+
+        self.nudged_on = Some(self.current_day);
+
+        true
+    }
+
+    /// Days the challenge has run, counting today.
+    pub fn days_done(&self) -> usize {
+        if self.started {
+            (self.current_day + 1).min(self.duration)
+        } else {
+            0
+        }
+    }
+
+    pub fn display_day(day: usize) -> usize {
+        day + 1
+    }
+
+    /// Whether `at` falls on the rest weekday in the chat's timezone.
+    pub fn is_rest_day(&self, at: DateTime<Utc>) -> bool {
+        self.rest_weekday == Some(at.with_timezone(&self.timezone).weekday())
+    }
+
+    /// A reset while paused. The day stays where it is; unless paused days
+    /// extend the challenge, it comes off the duration instead.
+    pub fn skip_paused_day(&mut self) {
+        if self.started && !self.pause_extends {
+            self.duration = self.duration.saturating_sub(1).max(self.current_day + 1);
+        }
+    }
+
+    /// The group's totals of the last week up to today, as one block per
+    /// day scaled to the busiest of them. Only an empty day gets the lowest
+    /// block.
+    pub fn sparkline(&self) -> String {
+        const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        let first = (self.current_day + 1).saturating_sub(SPARKLINE_DAYS);
+        let totals: Vec<usize> = (first..=self.current_day)
+            .map(|day| {
+                self.progress
+                    .get(day)
+                    .map_or(0, |day| saturating_sum(day.values()))
+            })
+            .collect();
+        let max = totals.iter().copied().max().unwrap_or(0);
+
+        totals
+            .iter()
+            .map(|total| match max {
+                0 => BLOCKS[0],
+                max => BLOCKS[(*total as u128 * 7).div_ceil(max as u128) as usize],
+            })
+            .collect()
+    }
+
+    pub fn generate_daily_message(&self) -> String {
+        let mut text = match self.mode {
+            WorkoutMode::TotalGoal { target } => self.generate_goal_message(target),
+            WorkoutMode::Daily => self.generate_targets_message(),
+        };
+
+        // A single day makes no trend.
+        if self.current_day > 0 {
+            text += &format!("📈 {}\n", self.sparkline());
+        }
+
+        // On top, so the pinned message shows it at a glance.
+        if self.paused {
+            return self.text(Text::Paused) + &text;
+        }
+        if self.resting {
+            return self.text(Text::RestDay) + &text;
+        }
+
+        text
+    }
+
+    /// Everyone's reps against today's target.
+    fn generate_targets_message(&self) -> String {
+        let mut text = "".to_string();
+        let rank_marks = self.rank_marks();
+
+        for user in &self.users {
+            let rank_mark = rank_marks.get(&user.id).copied().unwrap_or("");
+
+            text += &match self.layout {
+                Layout::Compact => self.format_compact_line(user, rank_mark),
+                Layout::Detailed => self.format_detailed_line(user, rank_mark),
+            };
+        }
+
+        text += &self.text(Text::DailyFooter {
+            day: Self::display_day(self.current_day),
+            duration: self.duration,
+            repeats: self.repeats,
+            exercise: &self.exercise,
+        });
+
+        text
+    }
+
+    /// The daily message towards a total goal: everyone's total with today's
+    /// share, and the group's progress.
+    fn generate_goal_message(&self, target: usize) -> String {
+        let mut text = "".to_string();
+        let rank_marks = self.rank_marks();
+
+        for (user, total) in self.totals_through(self.current_day + 1) {
+            text += &self.text(Text::GoalUserLine {
+                name: &user.display_name,
+                rank_mark: rank_marks.get(&user.id).copied().unwrap_or(""),
+                total,
+                today: self.count_on(user.id, self.current_day),
+            });
+        }
+
+        let total = self.group_total();
+        text += &self.text(Text::GoalFooter {
+            day: Self::display_day(self.current_day),
+            duration: self.duration,
+            total,
+            target,
+            exercise: &self.exercise,
+            bar: &progress_bar(total, target),
+        });
+
+        text
+    }
+
+    /// The daily message with how long today has left, for `/status`. The
+    /// daily message itself goes without it, it would be stale by the next
+    /// edit.
+    pub fn generate_status_message(&self, now: DateTime<Utc>) -> String {
+        let mut text = self.generate_daily_message();
+
+        if let Some(next_reset) = self.next_reset.filter(|at| *at > now) {
+            // Rounded up, so the last minute doesn't read as none left.
+            let minutes = (next_reset - now).num_seconds().saturating_add(59) / 60;
+            text += &self.text(Text::TimeLeft {
+                hours: minutes / 60,
+                minutes: minutes % 60,
+            });
+        }
+
+        text
+    }
+
+    /// ✅ once the user is done today, 🏆 for whoever was done first.
+    fn done_mark(&self, user_id: i64) -> &'static str {
+        if !self.is_user_done(user_id) {
+            ""
+        } else if self.first_finisher(self.current_day).map(|(id, _)| id) == Some(user_id) {
+            " 🏆"
+        } else {
+            " ✅"
+        }
+    }
+
+    /// Who met the target of `day` first, and when.
+    pub fn first_finisher(&self, day: usize) -> Option<(i64, DateTime<Utc>)> {
+        self.finished_at
+            .get(&day)?
+            .iter()
+            .min_by_key(|(_, at)| **at)
+            .map(|(user_id, at)| (*user_id, *at))
+    }
+
+    /// How each user's place in the overall ranking changed since yesterday:
+    /// ⬆️, ⬇️ or ➡️, and 🆕 for users who had no reps before today. Empty
+    /// on the first day.
+    fn rank_marks(&self) -> HashMap<i64, &'static str> {
+        if self.current_day == 0 {
+            return HashMap::new();
+        }
+
+        let place = |totals: Vec<(&Participant, usize)>| -> HashMap<i64, usize> {
+            totals
+                .into_iter()
+                .enumerate()
+                .map(|(place, (user, _))| (user.id, place))
+                .collect()
+        };
+        let mut yesterday = self.totals_through(self.current_day);
+        yesterday.retain(|(_, total)| *total > 0);
+        let yesterday = place(yesterday);
+        let today = place(self.totals_through(self.current_day + 1));
+
+        today
+            .into_iter()
+            .map(|(user_id, place)| {
+                let mark = match yesterday.get(&user_id) {
+                    None => " 🆕",
+                    Some(before) if place < *before => " ⬆️",
+                    Some(before) if place > *before => " ⬇️",
+                    Some(_) => " ➡️",
+                };
+
+                (user_id, mark)
+            })
+            .collect()
+    }
+
+    /// Every user's counted reps over the first `days` days, best first, ties
+    /// by name.
+    fn totals_through(&self, days: usize) -> Vec<(&Participant, usize)> {
+        let mut totals: Vec<(&Participant, usize)> = self
+            .users
+            .iter()
+            .map(|user| {
+                let total = saturating_sum(
+                    self.progress
+                        .iter()
+                        .take(days)
+                        .filter_map(|day_progress| day_progress.get(&user.id)),
+                );
+
+                (user, total)
+            })
+            .collect();
+
+        totals.sort_by(|(a, a_total), (b, b_total)| {
+            b_total
+                .cmp(a_total)
+                .then_with(|| a.display_name.cmp(&b.display_name))
+        });
+
+        totals
+    }
+
+    fn format_compact_line(&self, user: &Participant, rank_mark: &str) -> String {
+        let count = self.count_on(user.id, self.current_day);
+        let raw_count = self.raw_count_on(user.id, self.current_day);
+
+        format!(
+            "{}{}: {}/{}{}{}{}\n",
+            user.display_name,
+            rank_mark,
+            count,
+            self.user_target(user.id, self.current_day),
+            self.raw_count_note(count, raw_count),
+            self.done_mark(user.id),
+            streak_note(self.user_streak(user.id))
+        )
+    }
+
+    fn format_detailed_line(&self, user: &Participant, rank_mark: &str) -> String {
+        let count = self.count_on(user.id, self.current_day);
+        let raw_count = self.raw_count_on(user.id, self.current_day);
+        let target = self.user_target(user.id, self.current_day);
+        let percent = count.saturating_mul(100) / target.max(1);
+
+        let delta = match self.current_day.checked_sub(1) {
+            Some(yesterday) => {
+                let yesterday_count = self.count_on(user.id, yesterday);
+                self.text(Text::DeltaToYesterday(
+                    count as i64 - yesterday_count as i64,
+                ))
+            }
+            None => "".to_string(),
+        };
+
+        format!(
+            "{}{}: {}/{} {} {}%{}{}{}{}\n",
+            user.display_name,
+            rank_mark,
+            count,
+            target,
+            progress_bar(count, target),
+            percent,
+            delta,
+            self.raw_count_note(count, raw_count),
+            self.done_mark(user.id),
+            streak_note(self.user_streak(user.id))
+        )
+    }
+
+    pub fn generate_final_message(&self) -> String {
+        let mut users_progress = HashMap::new();
+        let mut users_raw_progress = HashMap::new();
+        let mut total_progress: usize = 0;
+
+        for day_progress in &self.progress {
+            for (user_id, count) in day_progress.iter() {
+                let total = users_progress.entry(*user_id).or_insert(0_usize);
+                *total = total.saturating_add(*count);
+            }
+        }
+
+        for day_progress in &self.raw_progress {
+            for (user_id, count) in day_progress.iter() {
+                let total = users_raw_progress.entry(*user_id).or_insert(0_usize);
+                *total = total.saturating_add(*count);
+                total_progress = total_progress.saturating_add(*count);
+            }
+        }
+
+        let mut text = "".to_string();
+        text += &self.text(Text::WorkoutOver {
+            days: self.days_done(),
+            total: total_progress,
+            exercise: &self.exercise,
+        });
+
+        let mut users_progress: Vec<(i64, String, usize)> = users_progress
+            .into_iter()
+            .map(|(user_id, count)| (user_id, self.display_name(user_id), count))
+            .collect();
+        users_progress.sort_by(|(_, a, a_count), (_, b, b_count)| {
+            b_count.cmp(a_count).then_with(|| a.cmp(b))
+        });
+
+        for (user_id, name, count) in users_progress {
+            let raw_count = users_raw_progress.get(&user_id).unwrap_or(&0);
+
+            text += &self.text(Text::FinalUserLine {
+                name: &name,
+                count,
+                raw_note: &self.raw_count_note(count, *raw_count),
+                days_met: self.days_met(user_id),
+                days: self.days_done(),
+            });
+        }
+
+        text += &self.generate_day_breakdown();
+        text += &self.generate_best_days();
+
+        if !self.gifts.is_empty() {
+            text += &self.text(Text::GiftsHeader);
+
+            for gift in &self.gifts {
+                text += &self.text(Text::GiftLine {
+                    day: Self::display_day(gift.day),
+                    from: &self.display_name(gift.from),
+                    to: &self.display_name(gift.to),
+                    count: gift.count,
+                });
+            }
+        }
+
+        if self.day_notes.iter().any(Option::is_some) {
+            text += &self.text(Text::NotesHeader);
+
+            for (day, note) in self.day_notes.iter().enumerate() {
+                if let Some(note) = note {
+                    text += &self.text(Text::DayNote {
+                        day: Self::display_day(day),
+                        note,
+                    });
+                }
+            }
+        }
+
+        text
+    }
+
+    /// The group's counted reps of every day so far, with that day's target,
+    /// for the final message.
+    fn generate_day_breakdown(&self) -> String {
+        let days = self.progress.len().min(self.current_day + 1);
+        let mut text = self.text(Text::DaysHeader);
+
+        for day in 0..days {
+            text += &self.text(Text::FinalDayLine {
+                day: Self::display_day(day),
+                total: saturating_sum(self.progress[day].values()),
+                target: (self.mode == WorkoutMode::Daily).then(|| self.day_target(day)),
+            });
+        }
+
+        text
+    }
+
+    /// Everyone's best single day and the group's, for the final message.
+    /// Ties go to the earlier day.
+    fn generate_best_days(&self) -> String {
+        let best_day = |count_on: &dyn Fn(&HashMap<i64, usize>) -> usize| {
+            self.progress
+                .iter()
+                .map(count_on)
+                .enumerate()
+                .filter(|(_, count)| *count > 0)
+                .min_by_key(|(day, count)| (std::cmp::Reverse(*count), *day))
+        };
+
+        let group_best = match best_day(&|day_progress| saturating_sum(day_progress.values())) {
+            Some(best) => best,
+            None => return "".to_string(),
+        };
+
+        let mut users: Vec<&Participant> = self.users.iter().collect();
+        users.sort_by(|a, b| a.display_name.cmp(&b.display_name));
+
+        let mut text = self.text(Text::BestDaysHeader);
+
+        for user in users {
+            let count_on = |day_progress: &HashMap<i64, usize>| {
+                day_progress.get(&user.id).copied().unwrap_or(0)
+            };
+
+            if let Some((day, count)) = best_day(&count_on) {
+                text += &self.text(Text::UserBestDay {
+                    name: &user.display_name,
+                    day: Self::display_day(day),
+                    count,
+                });
+            }
+        }
+
+        text += &self.text(Text::GroupBestDay {
+            day: Self::display_day(group_best.0),
+            total: group_best.1,
+            exercise: &self.exercise,
+        });
+
+        text
+    }
+
+    pub fn generate_winners_message(&self) -> String {
+        let mut text = self.text(Text::WinnersHeader);
+
+        for day in 0..=self.current_day {
+            text += &match self.first_finisher(day) {
+                Some((user_id, at)) => self.text(Text::DayWinner {
+                    day: Self::display_day(day),
+                    name: &self.display_name(user_id),
+                    time: &at
+                        .with_timezone(&self.timezone)
+                        .format("%H:%M %Z")
+                        .to_string(),
+                }),
+                None => self.text(Text::DayWithoutWinner(Self::display_day(day))),
+            };
+        }
+
+        text
+    }
+
+    /// One line per finished day with the group total and whether everyone
+    /// met that day's target. Only the latest `MAX_HISTORY_DAYS` are listed.
+    pub fn generate_history_message(&self) -> String {
+        let days = self.current_day.min(self.progress.len());
+
+        if days == 0 {
+            return self.text(Text::NoFinishedDays);
+        }
+
+        let mut text = self.text(Text::HistoryHeader);
+        let first_day = days.saturating_sub(MAX_HISTORY_DAYS);
+
+        if first_day > 0 {
+            text += &self.text(Text::MoreDays(first_day));
+        }
+
+        for day in first_day..days {
+            let total = saturating_sum(self.progress[day].values());
+            let all_done = !self.users.is_empty()
+                && self.users.iter().all(|user| self.met_target(user.id, day));
+
+            text += &self.text(Text::HistoryLine {
+                day: Self::display_day(day),
+                total,
+                exercise: &self.exercise,
+                mark: if all_done { "✅" } else { "❌" },
+            });
+
+            if let Some(Some(note)) = self.day_notes.get(day) {
+                text += &format!(" ({})", note);
+            }
+
+            text += "\n";
+        }
+
+        text
+    }
+
+    /// Everyone's counted reps over all days so far, best first.
+    pub fn generate_leaderboard(&self) -> String {
+        let totals = self.totals_through(self.progress.len());
+
+        if totals.is_empty() {
+            return self.text(Text::NobodyYet);
+        }
+
+        let mut text = self.text(Text::LeaderboardHeader);
+
+        for (place, (user, total)) in totals.into_iter().enumerate() {
+            text += &format!("{} {}: {}\n", place_mark(place), user.display_name, total);
+        }
+
+        text
+    }
+
+    /// The user's own numbers, for `/me`: today against their target, their
+    /// total, streak and place overall.
+    pub fn generate_me_message(&self, user_id: i64) -> String {
+        let totals = self.totals_through(self.progress.len());
+        let place = match totals.iter().position(|(user, _)| user.id == user_id) {
+            Some(place) => place,
+            None => return self.text(Text::MeNotJoined(&self.exercise)),
+        };
+        let (user, total) = totals[place];
+
+        self.text(Text::MeStats {
+            name: &user.display_name,
+            today: self.count_on(user_id, self.current_day),
+            target: self.user_target(user_id, self.current_day),
+            total,
+            streak: self.user_streak(user_id),
+            place: place + 1,
+            participants: totals.len(),
+        })
+    }
+
+    /// Totals, the daily average and how many days anyone logged, over the
+    /// days so far including today.
+    pub fn generate_stats_message(&self) -> String {
+        let progress = &self.progress[..self.progress.len().min(self.current_day + 1)];
+
+        let mut text = self.text(Text::Stats {
+            days: progress.len(),
+            total: saturating_sum(progress.iter().flat_map(|day| day.values())),
+            exercise: &self.exercise,
+            average: average_per_day(progress),
+            participation: participation_rate(progress) * 100.0,
+        });
+
+        let most_consistent = self
+            .users
+            .iter()
+            .map(|user| (user, days_logged(progress, user.id)))
+            .filter(|(_, days)| *days > 0)
+            .min_by(|(a, a_days), (b, b_days)| {
+                b_days
+                    .cmp(a_days)
+                    .then_with(|| a.display_name.cmp(&b.display_name))
+            });
+
+        if let Some((user, days)) = most_consistent {
+            text += &self.text(Text::MostConsistent {
+                name: &user.display_name,
+                days,
+            });
+        }
+
+        text
+    }
+
+    /// Every count so far as `day,name,count` rows, days numbered as in the
+    /// messages. Counts are the ones that go towards the target.
+    pub fn generate_progress_csv(&self) -> String {
+        let mut csv = "day,name,count\n".to_string();
+
+        for (day, day_progress) in self.progress.iter().enumerate() {
+            let mut counts: Vec<(String, usize)> = day_progress
+                .iter()
+                .map(|(user_id, count)| (self.display_name(*user_id), *count))
+                .collect();
+            counts.sort();
+
+            for (name, count) in counts {
+                csv += &format!(
+                    "{},{},{}\n",
+                    Self::display_day(day),
+                    csv_field(&name),
+                    count
+                );
+            }
+        }
+
+        csv
+    }
+
+    /// Ranks everyone by the reps of one day alone.
+    pub fn generate_daily_top(&self, day: usize) -> String {
+        let day_progress = match self.progress.get(day) {
+            Some(day_progress) => day_progress,
+            None => return self.text(Text::NoSuchDay(self.progress.len())),
+        };
+
+        let mut counts: Vec<(String, usize)> = day_progress
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(user_id, count)| (self.display_name(*user_id), *count))
+            .collect();
+
+        if counts.is_empty() {
+            return self.text(Text::NobodyOnDay(Self::display_day(day)));
+        }
+
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        let mut text = self.text(Text::DailyTopHeader(Self::display_day(day)));
+
+        for (place, (name, count)) in counts.into_iter().enumerate() {
+            text += &format!("{} {}: {}\n", place_mark(place), name, count);
+        }
+
+        text
+    }
+
+    /// Fills in the greeting template from the config, or the built-in
+    /// greeting when the config has none.
+    /// `commands` are listed in the built-in greeting.
+    pub fn generate_greeting_message(&self, template: Option<&str>, commands: &[&str]) -> String {
+        match template {
+            Some(template) => template
+                .replace("{exercise}", &self.exercise)
+                .replace("{repeats}", &self.repeats.to_string())
+                .replace("{duration}", &self.duration.to_string()),
+            None => self.text(Text::Greeting {
+                exercise: &self.exercise,
+                repeats: self.repeats,
+                duration: self.duration,
+                commands: &commands.join(", "),
+            }),
+        }
+    }
+
+    /// Switches the language. The default exercise is renamed along, a
+    /// chosen one is kept.
+    pub fn set_locale(&mut self, locale: Locale) {
+        if self.exercise == self.text(Text::DefaultExercise) {
+            self.exercise = Text::DefaultExercise.format(locale);
+        }
+
+        self.locale = locale;
+    }
+
+    pub fn generate_config_message(&self) -> String {
+        let mut text = self.text(Text::ConfigHeader);
+
+        text += &self.text(Text::ConfigExercise(&self.exercise));
+        text += &self.text(Text::ConfigDuration(self.duration));
+        text += &self.text(Text::ConfigToday(Self::display_day(self.current_day)));
+        text += &self.text(match self.mode {
+            WorkoutMode::TotalGoal { target } => Text::ConfigGoal(target),
+            WorkoutMode::Daily => Text::ConfigRepeats(self.repeats),
+        });
+        text += &self.text(match self.next_cycle_length {
+            Some(next) => Text::ConfigNextCycleLength {
+                current: self.cycle_length,
+                next,
+            },
+            None => Text::ConfigCycleLength(self.cycle_length),
+        });
+        if let Some(schedule) = &self.schedule {
+            let targets: Vec<String> = schedule.iter().map(usize::to_string).collect();
+            text += &self.text(Text::ConfigSchedule {
+                targets: &targets.join(", "),
+                finish: self.schedule_end == ScheduleEnd::Finish,
+            });
+        }
+        text += &self.text(Text::ConfigCycleIncrease(self.cycle_increase));
+        text += &self.text(Text::ConfigMinRepeats(self.min_repeats));
+        text += &self.text(Text::ConfigReset {
+            hour: self.reset_hour,
+            timezone: self.timezone.name(),
+        });
+        if let Some(hour) = self.reminder_hour {
+            text += &self.text(Text::ConfigReminder(hour));
+        }
+        if !self.silent {
+            text += &self.text(Text::ConfigNotify);
+        }
+        if self.paused {
+            text += &self.text(Text::ConfigPaused);
+        }
+        text += &self.text(Text::ConfigPauseExtends(self.pause_extends));
+        if let Some(weekday) = self.rest_weekday {
+            text += &self.text(Text::ConfigRestDay(weekday));
+        }
+        text += &self.text(Text::ConfigUsers(self.users.len()));
+
+        text
+    }
+
+    /// Lists users who haven't reached today's target yet, or `None` when
+    /// there is nobody to remind.
+    pub fn generate_reminder_message(&self) -> Option<String> {
+        if self.mode != WorkoutMode::Daily {
+            return None;
+        }
+
+        let lagging: Vec<String> = self
+            .users
+            .iter()
+            .filter(|user| !self.is_user_done(user.id))
+            .map(|user| {
+                let count = self.count_on(user.id, self.current_day);
+                let target = self.user_target(user.id, self.current_day);
+
+                self.text(Text::ReminderEntry {
+                    name: &user.display_name,
+                    count,
+                    target,
+                })
+            })
+            .collect();
+
+        if lagging.is_empty() {
+            return None;
+        }
+
+        Some(self.text(Text::Reminder(&lagging.join(", "))))
+    }
+
+    pub fn generate_user_done_message(&self, user_id: i64) -> String {
+        self.text(Text::UserDone {
+            name: &self.display_name(user_id),
+            exercise: &self.exercise,
+        })
+    }
+
+    pub fn generate_all_done_message(&self) -> String {
+        let mut text = self.text(Text::AllDone(&self.exercise));
+
+        if let Some((user_id, _)) = self.first_finisher(self.current_day) {
+            text += &self.text(Text::FirstFinisher(&self.display_name(user_id)));
+        }
+
+        text
+    }
+
+    pub fn generate_end_of_cycle_message(&self, previous_repeats: usize) -> String {
+        if self.repeats > previous_repeats {
+            return self.text(Text::CycleUp {
+                from: previous_repeats,
+                to: self.repeats,
+            });
+        }
+
+        let mut text = self.text(Text::CycleDown {
+            from: previous_repeats,
+            to: self.repeats,
+        });
+
+        if self.repeats == self.min_repeats {
+            text += &self.text(Text::AtMinimum);
+        }
+
+        text
+    }
+
+    /// Renders `text` in the chat's language.
+    pub fn text(&self, text: Text) -> String {
+        text.format(self.locale)
+    }
+
+    fn raw_count_note(&self, count: usize, raw_count: usize) -> String {
+        if count == raw_count {
+            "".to_string()
+        } else {
+            self.text(Text::RawCountNote(raw_count))
+        }
+    }
+}
+
+/// Adds `delta` to the user's total without going below zero and returns how
+/// much the total actually changed.
+fn add_clamped(day_progress: &mut HashMap<i64, usize>, user_id: i64, delta: isize) -> isize {
+    let total = day_progress.entry(user_id).or_insert(0);
+    let before = *total;
+    *total = total.saturating_add_signed(delta);
+
+    // At most `delta` away from it, so it fits.
+    if *total >= before {
+        0_isize.saturating_add_unsigned(*total - before)
+    } else {
+        0_isize.saturating_sub_unsigned(before - *total)
+    }
+}
+
+/// A logged count as a change to a day's total, saturating where `isize`
+/// is narrower than `i64`.
+fn to_delta(count: i64) -> isize {
+    isize::try_from(count).unwrap_or(if count < 0 { isize::MIN } else { isize::MAX })
+}
+
+/// The group's mean reps per day; zero without days.
+fn average_per_day(progress: &[HashMap<i64, usize>]) -> f64 {
+    if progress.is_empty() {
+        return 0.0;
+    }
+
+    let total: f64 = progress
+        .iter()
+        .flat_map(|day| day.values())
+        .map(|count| *count as f64)
+        .sum();
+
+    total / progress.len() as f64
+}
+
+/// The share of days on which anyone logged reps, from 0 to 1.
+fn participation_rate(progress: &[HashMap<i64, usize>]) -> f64 {
+    if progress.is_empty() {
+        return 0.0;
+    }
+
+    let active = progress
+        .iter()
+        .filter(|day| day.values().any(|count| *count > 0))
+        .count();
+
+    active as f64 / progress.len() as f64
+}
+
+fn days_logged(progress: &[HashMap<i64, usize>], user_id: i64) -> usize {
+    progress
+        .iter()
+        .filter(|day| day.get(&user_id).is_some_and(|count| *count > 0))
+        .count()
+}
+
+/// Adds up counts, stopping at `usize::MAX` rather than overflowing.
+fn saturating_sum<'a>(counts: impl IntoIterator<Item = &'a usize>) -> usize {
+    counts
+        .into_iter()
+        .fold(0, |total, count| total.saturating_add(*count))
+}
+
+fn streak_note(streak: usize) -> String {
+    if streak == 0 {
+        "".to_string()
+    } else {
+        format!(" 🔥{}", streak)
+    }
+}
+
+/// Quotes a CSV field if it has anything that would break the row.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A medal for the first three places, the number for the rest.
+fn place_mark(place: usize) -> String {
+    match place {
+        0 => "🥇".to_string(),
+        1 => "🥈".to_string(),
+        2 => "🥉".to_string(),
+        _ => format!("{}.", place + 1),
+    }
+}
+
+fn progress_bar(count: usize, target: usize) -> String {
+    const WIDTH: usize = 10;
+
+    let filled = (count.saturating_mul(WIDTH) / target.max(1)).min(WIDTH);
+
+    "▓".repeat(filled) + &"░".repeat(WIDTH - filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frankenstein::UserBuilder;
+
+    fn state(config: Config) -> WorkoutState {
+        WorkoutState::new(1, &config)
+    }
+
+    fn participant(id: i64, name: &str) -> Participant {
+        Participant {
+            id,
+            display_name: name.to_string(),
+        }
+    }
+
+    #[test]
+    fn progress_accumulates_per_user_and_day() {
+        let mut state = state(Config::default());
+
+        state.add_user_progress(participant(1, "alice"), 30);
+        state.add_user_progress(participant(1, "alice"), 20);
+        state.add_user_progress(participant(2, "bob"), 5);
+
+        assert_eq!(state.progress[0][&1], 50);
+        assert_eq!(state.progress[0][&2], 5);
+        assert_eq!(state.users.len(), 2);
+
+        state.init_next_day();
+        state.add_user_progress(participant(2, "bob"), 7);
+
+        assert_eq!(state.progress[1].get(&1), None);
+        assert_eq!(state.progress[1][&2], 7);
+    }
+
+    #[test]
+    fn weighted_reps_count_toward_the_target() {
+        let mut state = state(Config::default());
+
+        state.add_weighted_user_progress(participant(1, "alice"), 40, 2.5);
+
+        assert_eq!(state.progress[0][&1], 100);
+        assert_eq!(state.raw_progress[0][&1], 40);
+        assert!(state.is_user_done(1));
+    }
+
+    #[test]
+    fn everyone_has_to_reach_the_target() {
+        let mut state = state(Config::default());
+
+        assert!(state.is_all_users_done());
+
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.add_user_progress(participant(2, "bob"), 99);
+
+        assert!(state.is_user_done(1));
+        assert!(!state.is_user_done(2));
+        assert!(!state.is_user_done(3));
+        assert!(!state.is_all_users_done());
+
+        state.add_user_progress(participant(2, "bob"), 1);
+        assert!(state.is_all_users_done());
+
+        state.init_next_day();
+        assert!(!state.is_all_users_done());
+    }
+
+    #[test]
+    fn target_grows_at_every_cycle_boundary() {
+        let mut state = state(Config {
+            duration: 10,
+            repeats: 10,
+            cycle_length: 3,
+            cycle_increase: 5,
+            min_repeats: 1,
+            edit_debounce_secs: 2,
+            ..Config::default()
+        });
+
+        let changes: Vec<Option<usize>> = (0..9).map(|_| state.init_next_day()).collect();
+
+        assert_eq!(
+            changes,
+            vec![
+                None,
+                None,
+                Some(10),
+                None,
+                None,
+                Some(15),
+                None,
+                None,
+                Some(20)
+            ]
+        );
+        assert_eq!(
+            state.day_targets,
+            vec![10, 10, 10, 15, 15, 15, 20, 20, 20, 25]
+        );
+    }
+
+    #[test]
+    fn cycle_of_one_day_changes_the_target_daily() {
+        let mut state = state(Config {
+            duration: 10,
+            repeats: 10,
+            cycle_length: 1,
+            cycle_increase: 10,
+            min_repeats: 1,
+            edit_debounce_secs: 2,
+            ..Config::default()
+        });
+
+        assert_eq!(state.init_next_day(), Some(10));
+        assert_eq!(state.init_next_day(), Some(20));
+        assert_eq!(state.repeats, 30);
+    }
+
+    #[test]
+    fn workout_is_over_on_the_last_day() {
+        let mut state = state(Config {
+            duration: 3,
+            ..Config::default()
+        });
+
+        assert!(!state.is_workout_over());
+
+        state.start_next_day();
+        assert!(!state.is_workout_over());
+
+        state.start_next_day();
+        assert!(!state.is_workout_over());
+
+        state.start_next_day();
+        assert_eq!(state.current_day, 2);
+        assert!(state.is_workout_over());
+    }
+
+    #[test]
+    fn descending_program_stops_at_floor() {
+        let mut state = state(Config {
+            duration: 10,
+            repeats: 30,
+            cycle_length: 2,
+            cycle_increase: -10,
+            min_repeats: 15,
+            edit_debounce_secs: 2,
+            ..Config::default()
+        });
+
+        let changes: Vec<Option<usize>> = (0..8).map(|_| state.init_next_day()).collect();
+
+        assert_eq!(
+            changes,
+            vec![None, Some(30), None, Some(20), None, None, None, None]
+        );
+        assert_eq!(state.repeats, 15);
+        assert_eq!(
+            state.generate_end_of_cycle_message(20),
+            "Очередной цикл завершён! Уменьшаем повторения с 20 до 15. Это минимум, дальше без изменений."
+        );
+        assert!(!state.is_workout_over());
+    }
+
+    #[test]
+    fn first_day_is_displayed_as_day_one() {
+        let mut state = state(Config::default());
+
+        assert_eq!(state.start_next_day(), None);
+        assert_eq!(state.current_day, 0);
+        assert_eq!(
+            state.generate_daily_message(),
+            "День 1 из 35. 100 повторений (отжиманий)\n"
+        );
+
+        state.start_next_day();
+        assert_eq!(state.current_day, 1);
+        assert!(state.generate_daily_message().starts_with("День 2 из 35."));
+    }
+
+    #[test]
+    fn leaderboard_breaks_ties_by_name() {
+        let mut state = state(Config::default());
+        assert_eq!(state.generate_leaderboard(), "Пока никто не участвует");
+
+        state.add_user_progress(participant(1, "carol"), 10);
+        state.add_user_progress(participant(2, "bob"), 30);
+        state.init_next_day();
+        state.add_user_progress(participant(3, "alice"), 30);
+        state.add_user_progress(participant(4, "dave"), 5);
+
+        assert_eq!(
+            state.generate_leaderboard(),
+            "Общий зачёт:\n🥇 alice: 30\n🥈 bob: 30\n🥉 carol: 10\n4. dave: 5\n"
+        );
+    }
+
+    #[test]
+    fn final_message_highlights_the_best_days() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 30);
+        state.add_user_progress(participant(2, "bob"), 50);
+        state.init_next_day();
+        state.add_user_progress(participant(1, "alice"), 60);
+        state.add_user_progress(participant(2, "bob"), 50);
+        state.init_next_day();
+        state.add_user_progress(participant(1, "alice"), 10);
+
+        let text = state.generate_final_message();
+
+        assert!(text.contains(
+            "\nПо дням:\nДень 1: 80, норма 100\nДень 2: 110, норма 100\nДень 3: 10, норма 100\n"
+        ));
+        assert!(text.contains(
+            "\nЛучшие дни:\nalice: день 2, 60\nbob: день 1, 50\nВся группа: день 2, 110 отжиманий\n"
+        ));
+    }
+
+    #[test]
+    fn status_shows_the_time_left_today() {
+        let mut state = state(Config::default());
+        let reset = Utc.ymd(2022, 3, 2).and_hms(0, 0, 0);
+        state.next_reset = Some(reset);
+
+        let status = state.generate_status_message(reset - ChronoDuration::minutes(330));
+        assert!(status.ends_with("\n⏳ До нового дня осталось 5ч 30м"));
+
+        let status = state.generate_status_message(reset - ChronoDuration::seconds(20));
+        assert!(status.ends_with("осталось 0ч 1м"));
+
+        assert_eq!(
+            state.generate_status_message(reset),
+            state.generate_daily_message()
+        );
+    }
+
+    #[test]
+    fn removed_participants_lose_every_day() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 10);
+        state.add_user_progress(participant(2, "spam"), 5000);
+        state.init_next_day();
+        state.add_user_progress(participant(2, "spam"), 5000);
+
+        assert!(state.remove_participant(2));
+        assert!(!state.remove_participant(2));
+
+        assert_eq!(state.users, vec![participant(1, "alice")]);
+        assert!(state.progress.iter().all(|day| !day.contains_key(&2)));
+        assert!(state.raw_progress.iter().all(|day| !day.contains_key(&2)));
+    }
+
+    #[test]
+    fn edited_logs_are_counted_by_the_difference() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 10);
+        state.remember_message(100);
+
+        let delta = state.edit_logged_message(100, 15, 1.0).unwrap();
+        assert_eq!(delta.raw_count, 5);
+        assert_eq!(state.progress[0].get(&1), Some(&15));
+
+        // Set to 5 with only 5 left after a correction: clamped at zero.
+        state.add_user_progress(participant(1, "alice"), -10);
+        let delta = state.edit_logged_message(100, 5, 1.0).unwrap();
+        assert_eq!(delta.raw_count, -5);
+        assert_eq!(state.progress[0].get(&1), Some(&0));
+
+        assert_eq!(state.edit_logged_message(101, 20, 1.0), None);
+        state.init_next_day();
+        assert_eq!(state.edit_logged_message(100, 20, 1.0), None);
+    }
+
+    #[test]
+    fn progress_is_exported_as_csv() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "bob"), 20);
+        state.add_user_progress(participant(2, "Иван, \"старший\""), 10);
+        state.init_next_day();
+        state.add_user_progress(participant(1, "bob"), 30);
+
+        assert_eq!(
+            state.generate_progress_csv(),
+            "day,name,count\n1,bob,20\n1,\"Иван, \"\"старший\"\"\",10\n2,bob,30\n"
+        );
+    }
+
+    #[test]
+    fn daily_top_counts_one_day() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "carol"), 50);
+        state.init_next_day();
+        state.add_user_progress(participant(1, "carol"), 10);
+        state.add_user_progress(participant(2, "bob"), 20);
+
+        assert_eq!(
+            state.generate_daily_top(0),
+            "Лучшие за день 1:\n🥇 carol: 50\n"
+        );
+        assert_eq!(
+            state.generate_daily_top(1),
+            "Лучшие за день 2:\n🥇 bob: 20\n🥈 carol: 10\n"
+        );
+        assert_eq!(
+            state.generate_daily_top(2),
+            "Такого дня нет, пока прошло дней: 2"
+        );
+
+        state.init_next_day();
+        assert_eq!(
+            state.generate_daily_top(2),
+            "За день 3 пока никто не занимался"
+        );
+    }
+
+    #[test]
+    fn streak_uses_the_target_of_each_day() {
+        let mut state = state(Config {
+            duration: 10,
+            repeats: 10,
+            cycle_length: 2,
+            cycle_increase: 10,
+            min_repeats: 1,
+            edit_debounce_secs: 2,
+            ..Config::default()
+        });
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+
+        state.add_user_progress(alice.clone(), 10);
+        state.init_next_day();
+        state.add_user_progress(alice.clone(), 10);
+        state.init_next_day();
+        assert_eq!(state.repeats, 20);
+
+        // Not done yet today, the streak from the first two days holds.
+        assert_eq!(state.user_streak(1), 2);
+
+        state.add_user_progress(alice.clone(), 20);
+        assert_eq!(state.user_streak(1), 3);
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice ➡️: 20/20 ✅ 🔥3\n"));
+
+        state.init_next_day();
+        state.init_next_day();
+        assert_eq!(state.user_streak(1), 0);
+    }
+
+    #[test]
+    fn undo_reverts_only_the_last_entry() {
+        let mut state = state(Config::default());
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+
+        assert_eq!(state.undo_last_entry(), None);
+
+        state.add_user_progress(alice.clone(), 10);
+        state.add_weighted_user_progress(alice, 100, 1.5);
+        state.undo_last_entry();
+
+        assert_eq!(state.progress[0][&1], 10);
+        assert_eq!(state.raw_progress[0][&1], 10);
+        assert_eq!(state.undo_last_entry(), None);
+    }
+
+    #[test]
+    fn empty_chat_is_nudged_once_per_day() {
+        let mut state = state(Config::default());
+
+        state.start_next_day();
+        assert!(!state.should_nudge());
+
+        state.start_next_day();
+        assert!(state.should_nudge());
+        assert!(!state.should_nudge());
+    }
+
+    #[test]
+    fn new_cycle_length_waits_for_the_cycle_boundary() {
+        let mut state = state(Config {
+            duration: 20,
+            repeats: 10,
+            cycle_length: 3,
+            cycle_increase: 10,
+            min_repeats: 1,
+            edit_debounce_secs: 2,
+            ..Config::default()
+        });
+
+        state.init_next_day();
+        state.next_cycle_length = Some(2);
+
+        let changes: Vec<Option<usize>> = (0..6).map(|_| state.init_next_day()).collect();
+
+        // Day 3 ends the original cycle, then the new length counts from there.
+        assert_eq!(
+            changes,
+            vec![None, Some(10), None, Some(20), None, Some(30)]
+        );
+        assert_eq!(state.cycle_start, 3);
+    }
+
+    #[test]
+    fn corrections_do_not_go_below_zero() {
+        let mut state = state(Config::default());
+        let alice = Participant {
+            id: 1,
+            display_name: "alice".to_string(),
+        };
+
+        state.add_user_progress(alice.clone(), 10);
+        state.add_user_progress(alice, -15);
+
+        assert_eq!(state.progress[0][&1], 0);
+        assert_eq!(state.raw_progress[0][&1], 0);
+    }
+
+    #[test]
+    fn reset_follows_local_time() {
+        let mut state = state(Config::default());
+        state.reset_hour = 6;
+        state.timezone = chrono_tz::Europe::Moscow;
+
+        // 06:00 in Moscow is 03:00 UTC.
+        assert_eq!(
+            state.next_reset_after(Utc.ymd(2022, 3, 1).and_hms(2, 0, 0)),
+            Utc.ymd(2022, 3, 1).and_hms(3, 0, 0)
+        );
+        assert_eq!(
+            state.next_reset_after(Utc.ymd(2022, 3, 1).and_hms(3, 0, 0)),
+            Utc.ymd(2022, 3, 2).and_hms(3, 0, 0)
+        );
+    }
+
+    #[test]
+    fn reminder_lists_only_users_below_the_target() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.add_user_progress(participant(2, "bob"), 40);
+
+        assert_eq!(
+            state.generate_reminder_message().as_deref(),
+            Some("⏰ Ещё не выполнили норму: bob (40 из 100)")
+        );
+
+        state.add_user_progress(participant(2, "bob"), 60);
+
+        assert_eq!(state.generate_reminder_message(), None);
+
+        state.reminder_hour = Some(15);
+        assert_eq!(
+            state.next_reminder_after(Utc.ymd(2022, 3, 1).and_hms(16, 0, 0)),
+            Some(Utc.ymd(2022, 3, 2).and_hms(15, 0, 0))
+        );
+    }
+
+    #[test]
+    fn restored_state_with_missing_days_does_not_panic() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 30);
+        state.started = true;
+        state.current_day = 3;
+
+        assert!(!state.is_user_done(1));
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice ➡️: 0/100\nДень 4 из 35."));
+
+        state.add_user_progress(participant(1, "alice"), 20);
+        state.add_day_note("бег".to_string());
+
+        assert_eq!(state.progress.len(), 4);
+        assert_eq!(state.progress[3][&1], 20);
+        assert_eq!(state.day_notes[3].as_deref(), Some("бег"));
+    }
+
+    #[test]
+    fn left_users_drop_out_of_the_daily_message() {
+        let mut state = state(Config::default());
+
+        assert!(state.join(participant(1, "alice")));
+        assert!(!state.join(participant(1, "alice")));
+        state.add_user_progress(participant(2, "bob"), 40);
+
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice: 0/100\nbob: 40/100\n"));
+
+        assert!(state.leave(2));
+        assert!(!state.leave(2));
+        assert_eq!(state.progress[0].get(&2), None);
+        assert_eq!(state.undo_last_entry(), None);
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice: 0/100\nДень 1"));
+    }
+
+    #[test]
+    fn left_users_keep_their_name_in_the_results() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(2, "bob"), 40);
+        state.init_next_day();
+        state.leave(2);
+
+        let final_message = state.generate_final_message();
+        assert!(final_message.contains("\nbob: 40 "), "{}", final_message);
+        assert_eq!(state.display_name(2), "bob");
+
+        state.join(participant(2, "bob"));
+        assert!(state.left.is_empty());
+    }
+
+    #[test]
+    fn final_results_are_ranked_by_total() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "carol"), 40);
+        state.add_user_progress(participant(2, "alice"), 100);
+        state.add_user_progress(participant(3, "bob"), 40);
+        state.init_next_day();
+
+        let final_message = state.generate_final_message();
+        let names: Vec<&str> = final_message
+            .lines()
+            .filter_map(|line| line.split_once(": ").map(|(name, _)| name))
+            .filter(|name| ["alice", "bob", "carol"].contains(name))
+            .take(3)
+            .collect();
+
+        assert_eq!(names, ["alice", "bob", "carol"]);
+    }
+
+    #[test]
+    fn schedule_replaces_the_cycle_math() {
+        let mut state = state(Config::default());
+        state.set_schedule(Some(vec![20, 25, 40]), ScheduleEnd::Hold);
+        state.start_next_day();
+
+        assert_eq!(state.repeats, 20);
+
+        let targets: Vec<usize> = (0..3)
+            .map(|_| {
+                state.init_next_day();
+                state.repeats
+            })
+            .collect();
+
+        assert_eq!(targets, vec![25, 40, 40]);
+        assert!(!state.is_workout_over());
+        assert_eq!(state.day_target(0), 20);
+    }
+
+    #[test]
+    fn schedule_can_end_the_challenge() {
+        let mut state = state(Config::default());
+        state.set_schedule(Some(vec![20, 25]), ScheduleEnd::Finish);
+        state.start_next_day();
+
+        assert!(!state.is_workout_over());
+
+        state.init_next_day();
+
+        assert!(state.is_workout_over());
+    }
+
+    #[test]
+    fn reset_starts_over_with_the_same_settings() {
+        let config = Config {
+            repeats: 10,
+            cycle_length: 1,
+            cycle_increase: 5,
+            duration: 30,
+            ..Config::default()
+        };
+        let mut state = state(config);
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 10);
+        state.start_next_day();
+        state.start_next_day();
+        state.daily_message_id = Some(3);
+        state.targets_per_user.insert(1, 50);
+
+        state.reset();
+
+        assert!(!state.started);
+        assert_eq!(state.current_day, 0);
+        assert_eq!(state.repeats, 10);
+        assert_eq!(state.daily_message_id, None);
+        assert!(state.users.is_empty());
+        assert!(state.targets_per_user.is_empty());
+        assert_eq!(state.progress, vec![HashMap::new()]);
+        assert_eq!(state.duration, 30);
+        assert_eq!(state.cycle_increase, 5);
+
+        state.start_next_day();
+        state.start_next_day();
+        assert_eq!(state.repeats, 15);
+    }
+
+    #[test]
+    fn messages_follow_the_locale() {
+        let mut state = state(Config::default());
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 40);
+
+        assert_eq!(
+            state.generate_daily_message(),
+            "alice: 40/100\nДень 1 из 35. 100 повторений (отжиманий)\n"
+        );
+
+        state.locale = Locale::En;
+        state.exercise = "push-ups".to_string();
+
+        assert_eq!(
+            state.generate_daily_message(),
+            "alice: 40/100\nDay 1 of 35. 100 reps (push-ups)\n"
+        );
+        assert_eq!(
+            state.generate_reminder_message(),
+            Some("⏰ Still below the target: alice (40 of 100)".to_string())
+        );
+    }
+
+    #[test]
+    fn start_repeats_are_only_set_on_the_first_day() {
+        let mut state = state(Config {
+            repeats: 100,
+            cycle_length: 2,
+            cycle_increase: 5,
+            ..Config::default()
+        });
+        state.start_next_day();
+
+        assert!(state.set_start_repeats(0).is_err());
+        assert_eq!(state.set_start_repeats(30), Ok(()));
+        assert_eq!(state.day_target(0), 30);
+
+        state.start_next_day();
+        assert_eq!(state.repeats, 30);
+        assert!(state.set_start_repeats(40).is_err());
+
+        state.start_next_day();
+        assert_eq!(state.repeats, 35);
+    }
+
+    #[test]
+    fn rank_changes_are_marked() {
+        let mut state = state(Config::default());
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 50);
+        state.add_user_progress(participant(2, "bob"), 30);
+
+        assert_eq!(
+            state.generate_daily_message(),
+            "alice: 50/100\nbob: 30/100\nДень 1 из 35. 100 повторений (отжиманий)\n"
+        );
+
+        state.start_next_day();
+        state.add_user_progress(participant(2, "bob"), 40);
+        state.add_user_progress(participant(3, "carol"), 10);
+
+        assert_eq!(
+            state.generate_daily_message(),
+            "alice ⬇️: 0/100\nbob ⬆️: 40/100\ncarol 🆕: 10/100\nДень 2 из 35. 100 повторений (отжиманий)\n📈 █▆\n"
+        );
+    }
+
+    #[test]
+    fn total_goal_replaces_daily_targets() {
+        let mut state = state(Config {
+            repeats: 10,
+            cycle_length: 1,
+            cycle_increase: 5,
+            ..Config::default()
+        });
+        state.mode = WorkoutMode::TotalGoal { target: 100 };
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 30);
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 20);
+        state.add_user_progress(participant(2, "bob"), 10);
+
+        assert_eq!(state.repeats, 10);
+        assert!(!state.is_user_done(1));
+        assert_eq!(state.generate_reminder_message(), None);
+        assert_eq!(
+            state.generate_daily_message(),
+            "alice ➡️: 50 (сегодня 20)\nbob 🆕: 10 (сегодня 10)\n\
+             День 2 из 35. Цель: 60 из 100 отжиманий ▓▓▓▓▓▓░░░░\n📈 ██\n"
+        );
+        assert!(!state.is_workout_over());
+
+        state.add_user_progress(participant(2, "bob"), 40);
+        assert!(state.is_workout_over());
+    }
+
+    #[test]
+    fn greeting_is_filled_in() {
+        let state = state(Config::default());
+
+        assert_eq!(
+            state.generate_greeting_message(Some("{repeats} {exercise} за {duration} дней"), &[]),
+            "100 отжиманий за 35 дней"
+        );
+    }
+
+    #[test]
+    fn english_chats_get_the_english_greeting() {
+        let mut state = state(Config::default());
+        state.set_locale(Locale::En);
+
+        assert_eq!(state.exercise, "push-ups");
+        let greeting = state.generate_greeting_message(None, &["/help", "/status"]);
+        assert!(greeting.starts_with("👋 Hi! I'm counting push-ups in this chat."));
+        assert!(greeting.ends_with("Commands: /help, /status."));
+
+        state.exercise = "приседаний".to_string();
+        state.set_locale(Locale::Ru);
+        assert_eq!(state.exercise, "приседаний");
+    }
+
+    #[test]
+    fn history_lists_finished_days() {
+        let mut state = state(Config::default());
+
+        assert_eq!(
+            state.generate_history_message(),
+            "Пока нет завершённых дней"
+        );
+
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.add_user_progress(participant(2, "bob"), 50);
+        state.add_day_note("жара".to_string());
+        state.init_next_day();
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.add_user_progress(participant(2, "bob"), 100);
+        state.init_next_day();
+
+        assert_eq!(
+            state.generate_history_message(),
+            "История:\n\
+             День 1: 150 отжиманий ❌ (жара)\n\
+             День 2: 200 отжиманий ✅\n"
+        );
+
+        for _ in 0..MAX_HISTORY_DAYS {
+            state.init_next_day();
+        }
+
+        let history = state.generate_history_message();
+        assert!(history.starts_with("История:\n...и ещё 2 дней\nДень 3: 0"));
+    }
+
+    #[test]
+    fn personal_targets_replace_the_common_one() {
+        let mut state = state(Config::default());
+        state.targets_per_user.insert(2, 50);
+        state.add_user_progress(participant(1, "alice"), 60);
+        state.add_user_progress(participant(2, "bob"), 30);
+
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice: 60/100\nbob: 30/50\n"));
+
+        state.add_user_progress(participant(2, "bob"), 20);
+
+        assert!(state
+            .generate_daily_message()
+            .starts_with("alice: 60/100\nbob: 50/50 ✅ 🔥1\n"));
+        assert!(state.is_user_done(2));
+        assert!(!state.is_all_users_done());
+
+        state.add_user_progress(participant(1, "alice"), 40);
+
+        assert!(state.is_all_users_done());
+    }
+
+    #[test]
+    fn username_keyed_saves_are_merged_into_the_user() {
+        let saved = serde_json::json!({
+            "progress": [{ "alice": 30, "bob": 10 }],
+            "raw_progress": [{ "alice": 30, "bob": 10 }],
+            "finished_at": {},
+            "users": ["alice", "bob"],
+            "gifts": [{ "day": 0, "from": "bob", "to": "alice", "count": 5 }],
+        });
+        let mut state = state(Config::default());
+        state.progress = legacy::progress(&saved["progress"]).unwrap();
+        state.raw_progress = legacy::progress(&saved["raw_progress"]).unwrap();
+        state.finished_at = legacy::finished_at(&saved["finished_at"]).unwrap();
+        state.users = legacy::users(&saved["users"]).unwrap();
+        state.gifts = legacy::gifts(&saved["gifts"]).unwrap();
+
+        state.add_user_progress(participant(7, "alice"), 20);
+
+        assert_eq!(state.users.len(), 2);
+        assert_eq!(state.progress[0].get(&7), Some(&50));
+        assert_eq!(state.gifts[0].to, 7);
+        assert_eq!(state.totals_through(1)[0], (&participant(7, "alice"), 50));
+
+        // Renamed later, still the same person.
+        state.add_user_progress(participant(7, "alice_new"), 5);
+        assert_eq!(state.users.len(), 2);
+        assert_eq!(state.progress[0].get(&7), Some(&55));
+    }
+
+    #[test]
+    fn users_without_username_are_told_apart_by_id() {
+        let mut state = state(Config::default());
+        let user = |id: u64| {
+            UserBuilder::default()
+                .id(id)
+                .is_bot(false)
+                .first_name("Иван")
+                .build()
+                .unwrap()
+        };
+
+        state.add_user_progress(Participant::from_user(&user(10)), 30);
+        state.add_user_progress(Participant::from_user(&user(20)), 40);
+        state.add_user_progress(Participant::from_user(&user(10)), 5);
+
+        assert_eq!(state.users.len(), 2);
+        assert_eq!(
+            state.generate_daily_message(),
+            "Иван (10): 35/100\nИван (20): 40/100\nДень 1 из 35. 100 повторений (отжиманий)\n"
+        );
+    }
+
+    #[test]
+    fn paused_days_extend_or_shorten_the_challenge() {
+        let mut state = state(Config::default());
+        state.start_next_day();
+        state.paused = true;
+
+        state.skip_paused_day();
+        assert_eq!((state.current_day, state.duration), (0, 35));
+        assert!(state.generate_daily_message().starts_with("⏸ На паузе"));
+
+        state.pause_extends = false;
+        state.skip_paused_day();
+        assert_eq!((state.current_day, state.duration), (0, 34));
+
+        state.duration = 1;
+        state.skip_paused_day();
+        assert_eq!(state.duration, 1);
+    }
+
+    #[test]
+    fn sparkline_scales_the_last_week_to_the_busiest_day() {
+        let mut state = state(Config::default());
+        assert_eq!(state.sparkline(), "▁");
+
+        state.add_user_progress(participant(1, "alice"), 10);
+        assert_eq!(state.sparkline(), "█");
+
+        for count in [0, 70, 35, 1, 0, 20, 70, 5] {
+            state.init_next_day();
+            if count > 0 {
+                state.add_user_progress(participant(1, "alice"), count);
+            }
+        }
+
+        // The first two days have scrolled out.
+        assert_eq!(state.sparkline(), "█▅▂▁▃█▂");
+        assert!(state.generate_daily_message().ends_with("📈 █▅▂▁▃█▂\n"));
+    }
+
+    #[test]
+    fn winners_are_shown_in_the_chat_clock() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.timezone = Tz::Europe__Moscow;
+        state.finished_at.insert(
+            0,
+            HashMap::from([(1, Utc.ymd(2022, 3, 5).and_hms(5, 30, 0))]),
+        );
+
+        assert_eq!(
+            state.generate_winners_message(),
+            "Первыми норму выполнили:\nДень 1: alice (08:30 MSK)\n"
+        );
+    }
+
+    #[test]
+    fn rest_day_follows_the_chat_clock() {
+        let mut state = state(Config::default());
+        state.rest_weekday = Some(Weekday::Sun);
+        // Saturday in UTC, already Sunday in Moscow.
+        let at = Utc.ymd(2022, 3, 5).and_hms(22, 0, 0);
+
+        assert!(!state.is_rest_day(at));
+        state.timezone = Tz::Europe__Moscow;
+        assert!(state.is_rest_day(at));
+
+        state.resting = true;
+        assert!(state.generate_daily_message().starts_with("🛌 День отдыха"));
+    }
+
+    #[test]
+    fn me_shows_only_the_callers_numbers() {
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(1, "alice"), 100);
+        state.init_next_day();
+        state.add_user_progress(participant(1, "alice"), 30);
+        state.add_user_progress(participant(2, "bob"), 150);
+
+        assert_eq!(
+            state.generate_me_message(1),
+            "alice: сегодня 30/100, всего 130\n🔥 Серия: 1 дн.\n🏅 Место: 2 из 2"
+        );
+        assert!(state
+            .generate_me_message(3)
+            .starts_with("Тебя пока нет в списке"));
+    }
+
+    #[test]
+    fn huge_counts_saturate_instead_of_overflowing() {
+        let mut state = state(Config::default());
+        for _ in 0..3 {
+            state.add_user_progress(participant(1, "alice"), i64::MAX);
+        }
+        assert_eq!(state.count_on(1, 0), usize::MAX);
+
+        // The third log only added what was left, and only that comes off.
+        assert_eq!(state.undo_last_entry().unwrap().weighted_count, 1);
+        assert_eq!(state.count_on(1, 0), usize::MAX - 1);
+
+        state.add_user_progress(participant(2, "bob"), i64::MAX);
+        assert!(state.give_reps(2, 1, 10).is_ok());
+        assert_eq!(state.count_on(1, 0), usize::MAX);
+        assert_eq!(state.group_total(), usize::MAX);
+        assert!(state.generate_daily_message().contains("alice"));
+        assert!(state
+            .generate_final_message()
+            .contains(&usize::MAX.to_string()));
+    }
+
+    #[test]
+    fn day_can_be_set_either_way() {
+        let mut state = state(Config {
+            repeats: 10,
+            cycle_length: 2,
+            cycle_increase: 5,
+            ..Config::default()
+        });
+        assert!(state.set_day(3).is_err());
+        state.start_next_day();
+        state.add_user_progress(participant(1, "alice"), 10);
+
+        state.set_day(4).unwrap();
+        assert_eq!(state.current_day, 3);
+        assert_eq!(state.progress.len(), 4);
+        assert_eq!(state.day_target(3), 15);
+        state.add_user_progress(participant(1, "alice"), 15);
+
+        state.set_day(2).unwrap();
+        assert_eq!(state.current_day, 1);
+        assert_eq!(state.progress.len(), 2);
+        assert_eq!(state.day_targets, vec![10, 10]);
+        assert_eq!(state.group_total(), 10);
+
+        state.set_day(3).unwrap();
+        assert_eq!(state.day_target(2), 15);
+        assert_eq!(state.count_on(1, 2), 0);
+
+        assert!(state.set_day(0).is_err());
+        assert!(state.set_day(state.duration + 1).is_err());
+
+        state.locale = Locale::En;
+        assert_eq!(
+            state.set_day(0),
+            Err(format!("The day must be 1 to {}", state.duration))
+        );
+    }
+
+    #[test]
+    fn stats_aggregate_the_days_so_far() {
+        let day = |counts: &[(i64, usize)]| counts.iter().copied().collect::<HashMap<_, _>>();
+        let progress = vec![day(&[(1, 10), (2, 20)]), day(&[]), day(&[(1, 5), (2, 0)])];
+
+        assert_eq!(average_per_day(&progress), 35.0 / 3.0);
+        assert_eq!(participation_rate(&progress), 2.0 / 3.0);
+        assert_eq!(days_logged(&progress, 1), 2);
+        assert_eq!(days_logged(&progress, 2), 1);
+        assert_eq!(average_per_day(&[]), 0.0);
+
+        let mut state = state(Config::default());
+        state.add_user_progress(participant(2, "bob"), 20);
+        state.add_user_progress(participant(1, "alice"), 10);
+        state.init_next_day();
+        state.init_next_day();
+        state.add_user_progress(participant(1, "alice"), 5);
+
+        assert_eq!(
+            state.generate_stats_message(),
+            "📊 Статистика за 3 дн.\nВсего: 35 отжиманий\nВ среднем за день: 11.7\n\
+             Дни с занятиями: 66.7%\nСамый постоянный: alice, 2 дн. с занятиями\n"
+        );
+    }
+
+    #[test]
+    fn going_back_over_a_cycle_change_restores_the_cycle() {
+        let mut state = state(Config {
+            duration: 30,
+            repeats: 10,
+            cycle_length: 7,
+            cycle_increase: 5,
+            ..Config::default()
+        });
+        state.start_next_day();
+        state.set_day(3).unwrap();
+        state.next_cycle_length = Some(5);
+        state.set_day(10).unwrap();
+        assert_eq!((state.cycle_start, state.cycle_length), (7, 5));
+
+        state.set_day(4).unwrap();
+        assert_eq!((state.cycle_start, state.cycle_length), (0, 7));
+        assert_eq!(state.next_cycle_length, Some(5));
+        assert!(state.cycle_changes.is_empty());
+
+        // The original boundary still raises the target, and the new length
+        // takes over there as before.
+        state.set_day(13).unwrap();
+        assert_eq!(state.day_target(6), 10);
+        assert_eq!(state.day_target(7), 15);
+        assert_eq!(state.day_target(11), 15);
+        assert_eq!(state.day_target(12), 20);
+        assert_eq!(state.cycle_start, 7);
+    }
+}
@@ -0,0 +1,112 @@
+//! Reading challenges saved before participants were keyed on their user id,
+//! when every map was keyed on the username.
+//!
+//! A username from such a save becomes a participant with a negative id made
+//! from the name, the same in every field. Telegram user ids are positive, so
+//! these can't clash, and `WorkoutState::join` hands the reps over to the real
+//! user the first time someone with that name logs again.
+
+use super::{Gift, Participant};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Users {
+    Participants(Vec<Participant>),
+    Names(Vec<String>),
+}
+
+type FinishedAt = HashMap<usize, HashMap<i64, DateTime<Utc>>>;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredGift {
+    Gift(Gift),
+    Names {
+        day: usize,
+        from: String,
+        to: String,
+        count: usize,
+    },
+}
+
+/// The stand-in id of a username from an old save. FNV-1a, so it's the same
+/// on every start.
+pub fn legacy_id(name: &str) -> i64 {
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    -((hash >> 2) as i64) - 1
+}
+
+pub fn is_legacy_id(id: i64) -> bool {
+    id < 0
+}
+
+/// Map keys are strings in JSON either way: a user id, or a username, which
+/// can't be all digits.
+fn rekey<T>(values: HashMap<String, T>) -> HashMap<i64, T> {
+    values
+        .into_iter()
+        .map(|(key, value)| {
+            let id = key.parse().unwrap_or_else(|_| legacy_id(&key));
+
+            (id, value)
+        })
+        .collect()
+}
+
+pub fn users<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Participant>, D::Error> {
+    Ok(match Users::deserialize(deserializer)? {
+        Users::Participants(users) => users,
+        Users::Names(names) => names
+            .into_iter()
+            .map(|name| Participant {
+                id: legacy_id(&name),
+                display_name: name,
+            })
+            .collect(),
+    })
+}
+
+pub fn progress<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<HashMap<i64, usize>>, D::Error> {
+    let days = Vec::<HashMap<String, usize>>::deserialize(deserializer)?;
+
+    Ok(days.into_iter().map(rekey).collect())
+}
+
+pub fn finished_at<'de, D: Deserializer<'de>>(deserializer: D) -> Result<FinishedAt, D::Error> {
+    let days = HashMap::<usize, HashMap<String, DateTime<Utc>>>::deserialize(deserializer)?;
+
+    Ok(days
+        .into_iter()
+        .map(|(day, finished)| (day, rekey(finished)))
+        .collect())
+}
+
+pub fn gifts<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Gift>, D::Error> {
+    let gifts = Vec::<StoredGift>::deserialize(deserializer)?;
+
+    Ok(gifts
+        .into_iter()
+        .map(|gift| match gift {
+            StoredGift::Gift(gift) => gift,
+            StoredGift::Names {
+                day,
+                from,
+                to,
+                count,
+            } => Gift {
+                day,
+                from: legacy_id(&from),
+                to: legacy_id(&to),
+                count,
+            },
+        })
+        .collect())
+}